@@ -1,155 +1,189 @@
-use std::collections::HashMap;
+use std::iter::once;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-use core_foundation::base::{CFType, TCFType};
-use core_foundation::dictionary::CFDictionaryRef;
-use core_foundation::number::CFNumber;
-use core_foundation::string::CFString;
-use core_graphics::display::{
-    kCGNullWindowID, kCGWindowListExcludeDesktopElements, kCGWindowListOptionOnScreenOnly,
-    CGWindowListCopyWindowInfo,
-};
 use tauri::{AppHandle, Emitter, EventTarget, Manager, WebviewWindow};
 use tauri_plugin_log::log;
 
-#[derive(Debug, Clone, serde::Serialize)]
+use crate::anchor_tracker::{AnchorTracker, TrackerCommand};
+use crate::app_binding::is_hidden_by_binding;
+use crate::save_load::set_note_anchor;
+use crate::window_source::{platform_window_source, ExternalWindow, WindowSource};
+use crate::windows::note_id_from_label;
+
+/// Which point of the target window a note's offset is measured from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorMode {
+    /// The note's top-left corner keeps its distance to the target's top-left.
+    #[default]
+    TopLeft,
+    /// The note's top-right corner keeps its distance to the target's top-right.
+    TopRight,
+    /// The note's bottom-left corner keeps its distance to the target's bottom-left.
+    BottomLeft,
+    /// The note's bottom-right corner keeps its distance to the target's bottom-right.
+    BottomRight,
+    /// The offsets are fractions of the target size, so the note scales its
+    /// position with the target.
+    Proportional,
+}
+
+/// How to place a note relative to its target, as chosen in the anchor picker.
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+pub struct AnchorPlacement {
+    /// `None` picks the target corner closest to the note.
+    #[serde(default)]
+    pub mode: Option<AnchorMode>,
+    #[serde(default)]
+    pub clamp: bool,
+}
+
+/// Where a note sits relative to the window it is anchored to. Stored on the
+/// note record so anchors survive restarts; the window id is only meaningful
+/// while the target application keeps running, so re-attaching falls back to
+/// the app name and window title.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct AnchorInfo {
+    #[serde(default)]
     pub target_window_id: u32,
+    /// Interpreted according to `mode`: logical pixels from the chosen corner,
+    /// or fractions of the target size for `Proportional`.
     pub offset_x: f64,
     pub offset_y: f64,
+    #[serde(default)]
+    pub mode: AnchorMode,
+    /// Keeps the note inside the target bounds when the target shrinks.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub clamp: bool,
     pub target_app_name: String,
+    #[serde(default)]
+    pub target_window_title: String,
+    /// Set for "frontmost window of app X" anchors: when re-attaching, any
+    /// window of the app will do, regardless of its title.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub any_window_of_app: bool,
+    /// Set when the note was anchored together with other notes. The offsets
+    /// above then place the group's bounding box rather than the note itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<AnchorGroup>,
 }
 
-#[derive(Debug, Default)]
-pub struct AnchorState {
-    pub anchors: Mutex<HashMap<String, AnchorInfo>>,
-    pub polling_active: Mutex<bool>,
-}
-
-#[derive(Debug, Clone)]
-struct ExternalWindow {
-    id: u32,
-    x: f64,
-    y: f64,
-    width: f64,
-    height: f64,
-    owner_name: String,
+/// A note's place within an anchor group, which moves as one block so the
+/// notes keep their layout relative to each other.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AnchorGroup {
+    pub id: String,
+    /// Offset of the note from the top-left of the group's bounding box.
+    pub offset_x: f64,
+    pub offset_y: f64,
+    /// Size of the bounding box when the group was anchored.
+    pub width: f64,
+    pub height: f64,
 }
 
-fn get_external_windows(own_pid: u32) -> Vec<ExternalWindow> {
-    let mut windows = Vec::new();
-
-    unsafe {
-        let window_list = CGWindowListCopyWindowInfo(
-            kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
-            kCGNullWindowID,
-        );
-
-        if window_list.is_null() {
-            return windows;
-        }
-
-        let cf_array = core_foundation::array::CFArray::<CFType>::wrap_under_get_rule(
-            window_list as core_foundation::array::CFArrayRef,
-        );
-        let count = cf_array.len();
-
-        for i in 0..count {
-            let item = match cf_array.get(i) {
-                Some(item) => item,
-                None => continue,
-            };
-            let dict_ref = item.as_CFTypeRef() as CFDictionaryRef;
-
-            let layer = get_dict_number(dict_ref, "kCGWindowLayer");
-            if layer != Some(0) {
-                continue;
+impl AnchorInfo {
+    /// Where the note's top-left corner goes for the given target bounds.
+    pub fn note_position(&self, target: &ExternalWindow, note_w: f64, note_h: f64) -> (f64, f64) {
+        match &self.group {
+            Some(group) => {
+                let (x, y) = self.box_position(target, group.width, group.height);
+                (x + group.offset_x, y + group.offset_y)
             }
+            None => self.box_position(target, note_w, note_h),
+        }
+    }
 
-            let pid = get_dict_number(dict_ref, "kCGWindowOwnerPID");
-            if pid == Some(own_pid as i64) {
-                continue;
-            }
-
-            let window_id = match get_dict_number(dict_ref, "kCGWindowNumber") {
-                Some(id) => id as u32,
-                None => continue,
-            };
-
-            let owner_name = get_dict_string(dict_ref, "kCGWindowOwnerName").unwrap_or_default();
-
-            let bounds = match get_dict_bounds(dict_ref) {
-                Some(b) => b,
-                None => continue,
-            };
+    fn box_position(&self, target: &ExternalWindow, note_w: f64, note_h: f64) -> (f64, f64) {
+        let (x, y) = match self.mode {
+            AnchorMode::TopLeft => (target.x + self.offset_x, target.y + self.offset_y),
+            AnchorMode::TopRight => (
+                target.x + target.width + self.offset_x - note_w,
+                target.y + self.offset_y,
+            ),
+            AnchorMode::BottomLeft => (
+                target.x + self.offset_x,
+                target.y + target.height + self.offset_y - note_h,
+            ),
+            AnchorMode::BottomRight => (
+                target.x + target.width + self.offset_x - note_w,
+                target.y + target.height + self.offset_y - note_h,
+            ),
+            AnchorMode::Proportional => (
+                target.x + self.offset_x * target.width,
+                target.y + self.offset_y * target.height,
+            ),
+        };
+
+        if !self.clamp {
+            return (x, y);
+        }
 
-            if bounds.2 < 50.0 || bounds.3 < 50.0 {
-                continue;
-            }
+        // If the note is larger than the target, pin it to the top-left edge.
+        let x = x.min(target.x + target.width - note_w).max(target.x);
+        let y = y.min(target.y + target.height - note_h).max(target.y);
+        (x, y)
+    }
+}
 
-            windows.push(ExternalWindow {
-                id: window_id,
-                x: bounds.0,
-                y: bounds.1,
-                width: bounds.2,
-                height: bounds.3,
-                owner_name,
-            });
-        }
+/// The target corner closest to the note's center.
+fn nearest_corner(
+    sticky_x: f64,
+    sticky_y: f64,
+    sticky_w: f64,
+    sticky_h: f64,
+    target: &ExternalWindow,
+) -> AnchorMode {
+    let right = sticky_x + sticky_w / 2.0 > target.x + target.width / 2.0;
+    let bottom = sticky_y + sticky_h / 2.0 > target.y + target.height / 2.0;
+
+    match (right, bottom) {
+        (false, false) => AnchorMode::TopLeft,
+        (true, false) => AnchorMode::TopRight,
+        (false, true) => AnchorMode::BottomLeft,
+        (true, true) => AnchorMode::BottomRight,
     }
+}
 
-    windows
+/// A window the user can anchor a note to, as listed by the anchor picker.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct AnchorCandidate {
+    #[serde(flatten)]
+    pub window: ExternalWindow,
+    /// Distance between the centers of the note and the window, in logical pixels.
+    pub distance: f64,
 }
 
-unsafe fn get_dict_number(dict: CFDictionaryRef, key: &str) -> Option<i64> {
-    let cf_key = CFString::new(key);
-    let mut value: *const core::ffi::c_void = std::ptr::null();
-    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
-        dict,
-        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
-        &mut value,
-    ) != 0
-    {
-        let cf_num = CFNumber::wrap_under_get_rule(value as core_foundation::number::CFNumberRef);
-        cf_num.to_i64()
-    } else {
-        None
-    }
+// Polling cadence used when the window source cannot report changes: fast
+// while targets are moving, backing off exponentially while they sit still.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(150);
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(1500);
+// Sources with change notifications are still re-read this often in case an
+// event was missed.
+const NOTIFIED_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct AnchorState {
+    pub tracker: Mutex<AnchorTracker>,
+    tracking_active: Mutex<bool>,
+    wake: Mutex<Option<Sender<()>>>,
+    pub source: Box<dyn WindowSource>,
 }
 
-unsafe fn get_dict_string(dict: CFDictionaryRef, key: &str) -> Option<String> {
-    let cf_key = CFString::new(key);
-    let mut value: *const core::ffi::c_void = std::ptr::null();
-    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
-        dict,
-        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
-        &mut value,
-    ) != 0
-    {
-        let cf_str = CFString::wrap_under_get_rule(value as core_foundation::string::CFStringRef);
-        Some(cf_str.to_string())
-    } else {
-        None
+impl AnchorState {
+    pub fn new(source: Box<dyn WindowSource>) -> Self {
+        Self {
+            tracker: Mutex::new(AnchorTracker::default()),
+            tracking_active: Mutex::new(false),
+            wake: Mutex::new(None),
+            source,
+        }
     }
 }
 
-unsafe fn get_dict_bounds(dict: CFDictionaryRef) -> Option<(f64, f64, f64, f64)> {
-    let cf_key = CFString::new("kCGWindowBounds");
-    let mut value: *const core::ffi::c_void = std::ptr::null();
-    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
-        dict,
-        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
-        &mut value,
-    ) != 0
-    {
-        let bounds_dict = value as CFDictionaryRef;
-        let x = get_dict_number(bounds_dict, "X").unwrap_or(0) as f64;
-        let y = get_dict_number(bounds_dict, "Y").unwrap_or(0) as f64;
-        let w = get_dict_number(bounds_dict, "Width").unwrap_or(0) as f64;
-        let h = get_dict_number(bounds_dict, "Height").unwrap_or(0) as f64;
-        Some((x, y, w, h))
-    } else {
-        None
+impl Default for AnchorState {
+    fn default() -> Self {
+        Self::new(platform_window_source())
     }
 }
 
@@ -157,6 +191,18 @@ fn get_own_pid() -> u32 {
     std::process::id()
 }
 
+fn center_distance(
+    sticky_x: f64,
+    sticky_y: f64,
+    sticky_w: f64,
+    sticky_h: f64,
+    window: &ExternalWindow,
+) -> f64 {
+    let dx = (sticky_x + sticky_w / 2.0) - (window.x + window.width / 2.0);
+    let dy = (sticky_y + sticky_h / 2.0) - (window.y + window.height / 2.0);
+    (dx.powi(2) + dy.powi(2)).sqrt()
+}
+
 fn find_nearest_window(
     sticky_x: f64,
     sticky_y: f64,
@@ -164,17 +210,9 @@ fn find_nearest_window(
     sticky_h: f64,
     external_windows: &[ExternalWindow],
 ) -> Option<&ExternalWindow> {
-    let sticky_cx = sticky_x + sticky_w / 2.0;
-    let sticky_cy = sticky_y + sticky_h / 2.0;
-
     external_windows.iter().min_by(|a, b| {
-        let a_cx = a.x + a.width / 2.0;
-        let a_cy = a.y + a.height / 2.0;
-        let dist_a = (sticky_cx - a_cx).powi(2) + (sticky_cy - a_cy).powi(2);
-
-        let b_cx = b.x + b.width / 2.0;
-        let b_cy = b.y + b.height / 2.0;
-        let dist_b = (sticky_cx - b_cx).powi(2) + (sticky_cy - b_cy).powi(2);
+        let dist_a = center_distance(sticky_x, sticky_y, sticky_w, sticky_h, a);
+        let dist_b = center_distance(sticky_x, sticky_y, sticky_w, sticky_h, b);
 
         dist_a
             .partial_cmp(&dist_b)
@@ -182,73 +220,318 @@ fn find_nearest_window(
     })
 }
 
-fn find_window_by_id(id: u32, windows: &[ExternalWindow]) -> Option<&ExternalWindow> {
-    windows.iter().find(|w| w.id == id)
+/// All windows a note could be anchored to, nearest first.
+fn rank_candidates(
+    sticky_x: f64,
+    sticky_y: f64,
+    sticky_w: f64,
+    sticky_h: f64,
+    external_windows: Vec<ExternalWindow>,
+) -> Vec<AnchorCandidate> {
+    let mut candidates: Vec<AnchorCandidate> = external_windows
+        .into_iter()
+        .map(|window| AnchorCandidate {
+            distance: center_distance(sticky_x, sticky_y, sticky_w, sticky_h, &window),
+            window,
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| {
+        a.distance
+            .partial_cmp(&b.distance)
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    candidates
 }
 
-pub fn anchor_to_nearest(app: &AppHandle, window: &WebviewWindow) -> Result<String, anyhow::Error> {
-    let own_pid = get_own_pid();
-    let external = get_external_windows(own_pid);
+/// External windows are listed front-to-back, so the first match is frontmost.
+fn find_frontmost_window_of_app<'a>(
+    app_name: &str,
+    external_windows: &'a [ExternalWindow],
+) -> Option<&'a ExternalWindow> {
+    external_windows
+        .iter()
+        .find(|window| window.owner_name.eq_ignore_ascii_case(app_name))
+}
 
-    if external.is_empty() {
-        anyhow::bail!("No external windows found to anchor to");
+fn anchor_to_window(
+    (sticky_x, sticky_y, sticky_w, sticky_h): (f64, f64, f64, f64),
+    target: &ExternalWindow,
+    placement: AnchorPlacement,
+) -> AnchorInfo {
+    let mode = placement
+        .mode
+        .unwrap_or_else(|| nearest_corner(sticky_x, sticky_y, sticky_w, sticky_h, target));
+    let right = sticky_x + sticky_w;
+    let bottom = sticky_y + sticky_h;
+
+    let (offset_x, offset_y) = match mode {
+        AnchorMode::TopLeft => (sticky_x - target.x, sticky_y - target.y),
+        AnchorMode::TopRight => (right - (target.x + target.width), sticky_y - target.y),
+        AnchorMode::BottomLeft => (sticky_x - target.x, bottom - (target.y + target.height)),
+        AnchorMode::BottomRight => (
+            right - (target.x + target.width),
+            bottom - (target.y + target.height),
+        ),
+        AnchorMode::Proportional => (
+            (sticky_x - target.x) / target.width,
+            (sticky_y - target.y) / target.height,
+        ),
+    };
+
+    AnchorInfo {
+        target_window_id: target.id,
+        offset_x,
+        offset_y,
+        mode,
+        clamp: placement.clamp,
+        target_app_name: target.owner_name.clone(),
+        target_window_title: target.title.clone(),
+        any_window_of_app: false,
+        group: None,
+    }
+}
+
+/// Anchor infos for notes anchored together. A single note is placed on its
+/// own; several notes share one placement of their bounding box.
+fn anchor_members(
+    bounds: &[(f64, f64, f64, f64)],
+    target: &ExternalWindow,
+    placement: AnchorPlacement,
+    group_id: &str,
+) -> Vec<AnchorInfo> {
+    if let [single] = bounds {
+        return vec![anchor_to_window(*single, target, placement)];
     }
 
+    let left = bounds.iter().map(|b| b.0).fold(f64::INFINITY, f64::min);
+    let top = bounds.iter().map(|b| b.1).fold(f64::INFINITY, f64::min);
+    let right = bounds
+        .iter()
+        .map(|b| b.0 + b.2)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let bottom = bounds
+        .iter()
+        .map(|b| b.1 + b.3)
+        .fold(f64::NEG_INFINITY, f64::max);
+    let group_bounds = (left, top, right - left, bottom - top);
+    let shared = anchor_to_window(group_bounds, target, placement);
+
+    bounds
+        .iter()
+        .map(|(x, y, _, _)| AnchorInfo {
+            group: Some(AnchorGroup {
+                id: group_id.to_string(),
+                offset_x: x - left,
+                offset_y: y - top,
+                width: group_bounds.2,
+                height: group_bounds.3,
+            }),
+            ..shared.clone()
+        })
+        .collect()
+}
+
+fn note_bounds(window: &WebviewWindow) -> Result<(f64, f64, f64, f64), anyhow::Error> {
     let scale_factor = window.scale_factor()?;
     let pos = window.outer_position()?.to_logical::<f64>(scale_factor);
     let size = window.outer_size()?.to_logical::<f64>(scale_factor);
+    Ok((pos.x, pos.y, size.width, size.height))
+}
 
-    let nearest = find_nearest_window(pos.x, pos.y, size.width, size.height, &external)
-        .ok_or_else(|| anyhow::anyhow!("No nearest window found"))?;
-
-    let offset_x = pos.x - nearest.x;
-    let offset_y = pos.y - nearest.y;
-
-    let info = AnchorInfo {
-        target_window_id: nearest.id,
-        offset_x,
-        offset_y,
-        target_app_name: nearest.owner_name.clone(),
+fn persist_anchor(app: &AppHandle, label: &str, anchor_info: Option<&AnchorInfo>) {
+    let Some(note_id) = note_id_from_label(label) else {
+        return;
     };
 
+    if let Err(error) = set_note_anchor(app, &note_id, anchor_info.cloned()) {
+        log::warn!("Failed to save anchor for {}: {:#}", label, error);
+    }
+}
+
+fn attach(app: &AppHandle, window: &WebviewWindow, info: AnchorInfo) -> String {
+    let state = app.state::<AnchorState>();
     let label = window.label().to_string();
+    let target_id = info.target_window_id;
     let target_name = info.target_app_name.clone();
 
-    let state = app.state::<AnchorState>();
-    state.anchors.lock().unwrap().insert(label.clone(), info);
+    persist_anchor(app, &label, Some(&info));
+    state.tracker.lock().unwrap().attach(&label, info);
 
     log::info!(
         "Anchored {} to window {} ({})",
         label,
-        nearest.id,
+        target_id,
         target_name
     );
 
-    start_polling_if_needed(app);
+    start_tracking_if_needed(app);
+
+    target_name
+}
+
+/// Anchors `window` and the other `members` to `target`. The other members
+/// are told through `anchor_set`; the caller gets the target app name.
+fn attach_members(
+    app: &AppHandle,
+    window: &WebviewWindow,
+    members: &[WebviewWindow],
+    infos: Vec<AnchorInfo>,
+) -> String {
+    let mut target_name = String::new();
+
+    for (member, info) in once(window).chain(members).zip(infos) {
+        target_name = attach(app, member, info);
+        if member.label() != window.label() {
+            let _ = app.emit_to(
+                EventTarget::webview_window(member.label()),
+                "anchor_set",
+                target_name.clone(),
+            );
+        }
+    }
+
+    target_name
+}
+
+fn member_bounds(
+    window: &WebviewWindow,
+    members: &[WebviewWindow],
+) -> Result<Vec<(f64, f64, f64, f64)>, anyhow::Error> {
+    once(window).chain(members).map(note_bounds).collect()
+}
 
-    Ok(target_name)
+pub fn anchor_to_nearest(app: &AppHandle, window: &WebviewWindow) -> Result<String, anyhow::Error> {
+    let state = app.state::<AnchorState>();
+    let external = state.source.external_windows(get_own_pid());
+
+    if external.is_empty() {
+        anyhow::bail!("No external windows found to anchor to");
+    }
+
+    let bounds = note_bounds(window)?;
+    let (x, y, width, height) = bounds;
+    let nearest = find_nearest_window(x, y, width, height, &external)
+        .ok_or_else(|| anyhow::anyhow!("No nearest window found"))?;
+
+    let info = anchor_to_window(bounds, nearest, AnchorPlacement::default());
+    Ok(attach(app, window, info))
 }
 
+/// Lists the windows the note can be anchored to, for the anchor picker.
+pub fn anchor_candidates(
+    app: &AppHandle,
+    window: &WebviewWindow,
+) -> Result<Vec<AnchorCandidate>, anyhow::Error> {
+    let state = app.state::<AnchorState>();
+    let external = state.source.external_windows(get_own_pid());
+    let (x, y, width, height) = note_bounds(window)?;
+
+    Ok(rank_candidates(x, y, width, height, external))
+}
+
+/// Anchors to a window picked from `anchor_candidates`. Notes in `members`
+/// are anchored along with `window` as one group.
+pub fn anchor_to_window_id(
+    app: &AppHandle,
+    window: &WebviewWindow,
+    members: &[WebviewWindow],
+    target_window_id: u32,
+    placement: AnchorPlacement,
+) -> Result<String, anyhow::Error> {
+    let state = app.state::<AnchorState>();
+    let external = state.source.external_windows(get_own_pid());
+    let target = external
+        .iter()
+        .find(|candidate| candidate.id == target_window_id)
+        .ok_or_else(|| anyhow::anyhow!("Window {} is no longer available", target_window_id))?;
+
+    let bounds = member_bounds(window, members)?;
+    let infos = anchor_members(&bounds, target, placement, window.label());
+    Ok(attach_members(app, window, members, infos))
+}
+
+/// Anchors to the frontmost window of the named app, along with `members`.
+pub fn anchor_to_app(
+    app: &AppHandle,
+    window: &WebviewWindow,
+    members: &[WebviewWindow],
+    app_name: &str,
+    placement: AnchorPlacement,
+) -> Result<String, anyhow::Error> {
+    let state = app.state::<AnchorState>();
+    let external = state.source.external_windows(get_own_pid());
+    let target = find_frontmost_window_of_app(app_name, &external)
+        .ok_or_else(|| anyhow::anyhow!("No window of {} found", app_name))?;
+
+    let bounds = member_bounds(window, members)?;
+    let infos = anchor_members(&bounds, target, placement, window.label())
+        .into_iter()
+        .map(|info| AnchorInfo {
+            any_window_of_app: true,
+            ..info
+        })
+        .collect();
+    Ok(attach_members(app, window, members, infos))
+}
+
+/// Releases the note's anchor, and those of the rest of its group.
 pub fn unanchor(app: &AppHandle, window: &WebviewWindow) -> Result<(), anyhow::Error> {
-    let label = window.label().to_string();
     let state = app.state::<AnchorState>();
-    state.anchors.lock().unwrap().remove(&label);
+    let released: Vec<(String, bool)> = {
+        let mut tracker = state.tracker.lock().unwrap();
+        tracker
+            .group_labels(window.label())
+            .into_iter()
+            .map(|label| {
+                let was_hidden = tracker.is_hidden(&label);
+                tracker.remove(&label);
+                (label, was_hidden)
+            })
+            .collect()
+    };
+
+    for (label, was_hidden) in released {
+        if was_hidden && !is_hidden_by_binding(app, &label) {
+            if let Some(member) = app.get_webview_window(&label) {
+                member.show()?;
+            }
+        }
+        persist_anchor(app, &label, None);
+        let _ = app.emit_to(
+            EventTarget::webview_window(label.clone()),
+            "anchor_lost",
+            (),
+        );
+
+        log::info!("Unanchored {}", label);
+    }
+
+    Ok(())
+}
+
+/// Re-registers an anchor loaded from the note store. It stays pending until
+/// the tracking loop finds a matching target window.
+pub fn restore_anchor(app: &AppHandle, label: &str, anchor_info: AnchorInfo) {
+    let state = app.state::<AnchorState>();
+    let target_name = anchor_info.target_app_name.clone();
+    state.tracker.lock().unwrap().restore(label, anchor_info);
     let _ = app.emit_to(
-        EventTarget::webview_window(label.clone()),
-        "anchor_lost",
-        (),
+        EventTarget::webview_window(label.to_string()),
+        "anchor_pending",
+        target_name.clone(),
     );
 
-    log::info!("Unanchored {}", label);
-    Ok(())
+    log::info!("Restored pending anchor {} ({})", label, target_name);
+    start_tracking_if_needed(app);
 }
 
 pub fn is_anchored(app: &AppHandle, window: &WebviewWindow) -> bool {
     let state = app.state::<AnchorState>();
     state
-        .anchors
+        .tracker
         .lock()
-        .map(|anchors| anchors.contains_key(window.label()))
+        .map(|tracker| tracker.contains(window.label()))
         .unwrap_or(false)
 }
 
@@ -270,78 +553,359 @@ pub fn toggle_anchor_to_nearest(
     Ok(())
 }
 
-fn start_polling_if_needed(app: &AppHandle) {
+fn next_poll_interval(current: Duration, targets_moved: bool) -> Duration {
+    if targets_moved {
+        MIN_POLL_INTERVAL
+    } else {
+        (current * 2).min(MAX_POLL_INTERVAL)
+    }
+}
+
+/// Whether window change notifications still arrive. A watch that stopped is
+/// started again on `wake`; if that fails, the caller goes back to polling.
+fn still_notified(source: &dyn WindowSource, wake: Option<&Sender<()>>) -> bool {
+    if source.is_watching_changes() {
+        return true;
+    }
+    let renewed = wake.is_some_and(|wake| source.watch_changes(wake.clone()));
+    if renewed {
+        log::info!("Window change notifications restarted");
+    } else {
+        log::warn!("Window change notifications stopped, polling instead");
+    }
+    renewed
+}
+
+fn start_tracking_if_needed(app: &AppHandle) {
     let state = app.state::<AnchorState>();
-    let mut polling = state.polling_active.lock().unwrap();
-    if *polling {
+    let mut active = state.tracking_active.lock().unwrap();
+    if *active {
+        // Let the running loop pick up the new anchor right away.
+        if let Some(wake) = state.wake.lock().unwrap().as_ref() {
+            let _ = wake.send(());
+        }
         return;
     }
-    *polling = true;
-    drop(polling);
+    *active = true;
+    drop(active);
+
+    let (sender, changes) = mpsc::channel();
+    let mut notified = state.source.watch_changes(sender.clone());
+    *state.wake.lock().unwrap() = Some(sender);
 
     let app_handle = app.clone();
 
     std::thread::spawn(move || {
-        log::info!("Anchor polling loop started");
+        log::info!(
+            "Anchor tracking started ({})",
+            if notified {
+                "change notifications"
+            } else {
+                "polling"
+            }
+        );
         let own_pid = get_own_pid();
+        let mut poll_interval = MIN_POLL_INTERVAL;
+        let mut awaiting_grace_period = false;
 
         loop {
-            std::thread::sleep(std::time::Duration::from_millis(150));
+            let timeout = if notified && !awaiting_grace_period {
+                NOTIFIED_REFRESH_INTERVAL
+            } else {
+                poll_interval
+            };
+            if let Ok(()) | Err(RecvTimeoutError::Disconnected) = changes.recv_timeout(timeout) {
+                // Coalesce bursts of notifications (e.g. a window being dragged).
+                while changes.try_recv().is_ok() {}
+                poll_interval = MIN_POLL_INTERVAL;
+            }
 
             let state = app_handle.state::<AnchorState>();
-            let anchors = state.anchors.lock().unwrap().clone();
-
-            if anchors.is_empty() {
-                let mut polling = state.polling_active.lock().unwrap();
-                *polling = false;
-                log::info!("Anchor polling loop stopped (no anchors)");
-                break;
+            if notified {
+                let wake = state.wake.lock().unwrap().clone();
+                notified = still_notified(state.source.as_ref(), wake.as_ref());
+                if !notified {
+                    poll_interval = MIN_POLL_INTERVAL;
+                }
             }
+            let external = state.source.external_windows(own_pid);
+            let open_windows = app_handle.webview_windows();
+
+            let commands = {
+                let mut tracker = state.tracker.lock().unwrap();
+                tracker.retain_labels(|label| open_windows.contains_key(label));
+
+                if tracker.is_empty() {
+                    *state.tracking_active.lock().unwrap() = false;
+                    *state.wake.lock().unwrap() = None;
+                    log::info!("Anchor tracking stopped (no anchors)");
+                    break;
+                }
 
-            let external = get_external_windows(own_pid);
+                let commands = tracker.process(
+                    &external,
+                    Instant::now(),
+                    |id| state.source.window_exists(id),
+                    |label| {
+                        let window = open_windows.get(label)?;
+                        let scale_factor = window.scale_factor().ok()?;
+                        let size = window.outer_size().ok()?.to_logical::<f64>(scale_factor);
+                        Some((size.width, size.height))
+                    },
+                );
+                awaiting_grace_period = tracker.awaiting_grace_period();
+                commands
+            };
 
-            let mut to_remove: Vec<String> = Vec::new();
+            let targets_moved = commands
+                .iter()
+                .any(|command| matches!(command, TrackerCommand::MoveNote { .. }));
+            // Keep looking closely while a missing target may come back or get hidden.
+            poll_interval =
+                next_poll_interval(poll_interval, targets_moved || awaiting_grace_period);
 
-            for (label, anchor_info) in &anchors {
-                if let Some(target) = find_window_by_id(anchor_info.target_window_id, &external) {
-                    let new_x = target.x + anchor_info.offset_x;
-                    let new_y = target.y + anchor_info.offset_y;
+            for command in commands {
+                match command {
+                    TrackerCommand::MoveNote { label, x, y } => {
+                        let Some(window) = open_windows.get(&label) else {
+                            continue;
+                        };
 
-                    if let Some(window) = app_handle.webview_windows().get(label) {
                         let scale_factor = window.scale_factor().unwrap_or(1.0);
                         let current_pos = window
                             .outer_position()
                             .map(|p| p.to_logical::<f64>(scale_factor));
 
                         if let Ok(current) = current_pos {
-                            let dx = (current.x - new_x).abs();
-                            let dy = (current.y - new_y).abs();
+                            let dx = (current.x - x).abs();
+                            let dy = (current.y - y).abs();
                             if dx > 1.0 || dy > 1.0 {
-                                let physical_pos = tauri::LogicalPosition::new(new_x, new_y);
-                                let _ = window.set_position(physical_pos);
+                                let _ = window.set_position(tauri::LogicalPosition::new(x, y));
                             }
                         }
-                    } else {
-                        to_remove.push(label.clone());
                     }
-                } else {
-                    to_remove.push(label.clone());
-                    let _ = app_handle.emit_to(
-                        tauri::EventTarget::webview_window(label.clone()),
-                        "anchor_lost",
-                        (),
-                    );
-                    log::info!("Target window closed for anchor {}", label);
-                }
-            }
-
-            if !to_remove.is_empty() {
-                let state = app_handle.state::<AnchorState>();
-                let mut anchors = state.anchors.lock().unwrap();
-                for label in to_remove {
-                    anchors.remove(&label);
+                    TrackerCommand::HideNote { label } => {
+                        if let Some(window) = open_windows.get(&label) {
+                            let _ = window.hide();
+                        }
+                        log::info!("Target window hidden, hiding {}", label);
+                    }
+                    TrackerCommand::ShowNote { label } => {
+                        // Notes bound to an app that is not frontmost stay hidden.
+                        if is_hidden_by_binding(&app_handle, &label) {
+                            continue;
+                        }
+                        if let Some(window) = open_windows.get(&label) {
+                            let _ = window.show();
+                        }
+                        log::info!("Target window back on screen, showing {}", label);
+                    }
+                    TrackerCommand::Attached { label, anchor } => {
+                        let target_name = anchor.target_app_name.clone();
+                        persist_anchor(&app_handle, &label, Some(&anchor));
+                        let _ = app_handle.emit_to(
+                            EventTarget::webview_window(label.clone()),
+                            "anchor_set",
+                            target_name.clone(),
+                        );
+                        log::info!("Re-attached anchor {} to {}", label, target_name);
+                    }
+                    TrackerCommand::TargetLost { label } => {
+                        persist_anchor(&app_handle, &label, None);
+                        let _ = app_handle.emit_to(
+                            EventTarget::webview_window(label.clone()),
+                            "anchor_lost",
+                            (),
+                        );
+                        log::info!("Target window closed for anchor {}", label);
+                    }
                 }
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::{
+        anchor_members, anchor_to_window, find_frontmost_window_of_app, find_nearest_window,
+        next_poll_interval, rank_candidates, still_notified, AnchorMode, AnchorPlacement,
+        MAX_POLL_INTERVAL, MIN_POLL_INTERVAL,
+    };
+    use crate::window_source::fake::{window, FakeWindowSource};
+    use crate::window_source::WindowSource;
+
+    #[test]
+    fn finds_window_with_closest_center() {
+        let source = FakeWindowSource::new(vec![
+            window(1, "Editor", 0.0, 0.0, 800.0, 600.0),
+            window(2, "Browser", 900.0, 0.0, 800.0, 600.0),
+        ]);
+        let external = source.external_windows(1);
+
+        let nearest = find_nearest_window(1500.0, 100.0, 200.0, 200.0, &external).unwrap();
+
+        assert_eq!(nearest.id, 2);
+    }
+
+    #[test]
+    fn corner_anchors_follow_their_corner_when_target_resizes() {
+        let editor = window(7, "Editor", 100.0, 100.0, 800.0, 600.0);
+        let note = (680.0, 120.0, 200.0, 150.0);
+        let resized = window(7, "Editor", 100.0, 100.0, 600.0, 400.0);
+
+        let info = anchor_to_window(note, &editor, AnchorPlacement::default());
+        assert_eq!(info.mode, AnchorMode::TopRight);
+        assert_eq!(info.note_position(&editor, 200.0, 150.0), (680.0, 120.0));
+        assert_eq!(info.note_position(&resized, 200.0, 150.0), (480.0, 120.0));
+
+        let placement = AnchorPlacement {
+            mode: Some(AnchorMode::BottomLeft),
+            clamp: false,
+        };
+        let info = anchor_to_window((120.0, 530.0, 200.0, 150.0), &editor, placement);
+        assert_eq!(info.note_position(&resized, 200.0, 150.0), (120.0, 330.0));
+    }
+
+    #[test]
+    fn proportional_anchors_scale_and_clamp_inside_target() {
+        let editor = window(7, "Editor", 0.0, 0.0, 800.0, 600.0);
+        let placement = AnchorPlacement {
+            mode: Some(AnchorMode::Proportional),
+            clamp: true,
+        };
+
+        let info = anchor_to_window((400.0, 300.0, 200.0, 150.0), &editor, placement);
+        assert_eq!((info.offset_x, info.offset_y), (0.5, 0.5));
+
+        let larger = window(7, "Editor", 0.0, 0.0, 1600.0, 1200.0);
+        assert_eq!(info.note_position(&larger, 200.0, 150.0), (800.0, 600.0));
+
+        let smaller = window(7, "Editor", 0.0, 0.0, 300.0, 200.0);
+        assert_eq!(info.note_position(&smaller, 200.0, 150.0), (100.0, 50.0));
+    }
+
+    #[test]
+    fn group_members_keep_their_layout() {
+        let editor = window(7, "Editor", 0.0, 0.0, 1000.0, 800.0);
+        let notes = [
+            (700.0, 20.0, 200.0, 150.0),
+            (700.0, 190.0, 200.0, 100.0),
+            (480.0, 20.0, 200.0, 150.0),
+        ];
+        let placement = AnchorPlacement {
+            mode: Some(AnchorMode::TopRight),
+            clamp: false,
+        };
+
+        let infos = anchor_members(&notes, &editor, placement, "sticky_a");
+
+        assert!(infos
+            .iter()
+            .all(|info| info.target_window_id == 7 && info.offset_x == infos[0].offset_x));
+        let narrower = window(7, "Editor", 0.0, 0.0, 800.0, 800.0);
+        let positions: Vec<(f64, f64)> = infos
+            .iter()
+            .map(|info| info.note_position(&narrower, 0.0, 0.0))
+            .collect();
+        assert_eq!(
+            positions,
+            vec![(500.0, 20.0), (500.0, 190.0), (280.0, 20.0)]
+        );
+    }
+
+    #[test]
+    fn ranks_candidates_by_distance() {
+        let external = vec![
+            window(1, "Editor", 0.0, 0.0, 800.0, 600.0),
+            window(2, "Browser", 900.0, 0.0, 800.0, 600.0),
+            window(3, "Terminal", 1400.0, 0.0, 400.0, 300.0),
+        ];
+
+        let candidates = rank_candidates(1500.0, 100.0, 200.0, 200.0, external);
+
+        let ids: Vec<u32> = candidates.iter().map(|c| c.window.id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(candidates[0].distance, 50.0);
+    }
+
+    #[test]
+    fn finds_frontmost_window_of_app() {
+        let external = vec![
+            window(1, "Browser", 0.0, 0.0, 800.0, 600.0),
+            window(2, "Editor", 0.0, 0.0, 800.0, 600.0),
+            window(3, "Editor", 0.0, 0.0, 800.0, 600.0),
+        ];
+
+        let target = find_frontmost_window_of_app("editor", &external);
+
+        assert_eq!(target.map(|w| w.id), Some(2));
+        assert_eq!(find_frontmost_window_of_app("Terminal", &external), None);
+    }
+
+    #[test]
+    fn fake_source_skips_own_and_tiny_windows() {
+        let mut own = window(1, "Sticky", 0.0, 0.0, 300.0, 250.0);
+        own.owner_pid = 42;
+        let source = FakeWindowSource::new(vec![
+            own,
+            window(2, "Menu", 0.0, 0.0, 20.0, 20.0),
+            window(3, "Editor", 0.0, 0.0, 800.0, 600.0),
+        ]);
+
+        let ids: Vec<u32> = source.external_windows(42).iter().map(|w| w.id).collect();
+
+        assert_eq!(ids, vec![3]);
+    }
+
+    #[test]
+    fn fake_source_notifies_watchers_of_changes() {
+        let source = FakeWindowSource::default();
+        let (sender, changes) = mpsc::channel();
+        assert!(source.watch_changes(sender));
+
+        source.set_windows(vec![window(3, "Editor", 0.0, 0.0, 800.0, 600.0)]);
+
+        assert!(changes.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn stopped_change_watch_is_restarted_or_polled() {
+        let source = FakeWindowSource::default();
+        let (sender, changes) = mpsc::channel();
+        assert!(source.watch_changes(sender.clone()));
+        assert!(still_notified(&source, Some(&sender)));
+
+        source.stop_watching();
+        assert!(!still_notified(&source, None));
+        assert!(still_notified(&source, Some(&sender)));
+
+        source.set_windows(vec![window(3, "Editor", 0.0, 0.0, 800.0, 600.0)]);
+        assert!(changes.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn fake_source_keeps_hidden_windows_alive() {
+        let source = FakeWindowSource::new(vec![window(3, "Editor", 0.0, 0.0, 800.0, 600.0)]);
+
+        source.set_hidden(vec![3]);
+
+        assert!(source.external_windows(1).is_empty());
+        assert!(source.window_exists(3));
+        assert!(!source.window_exists(4));
+    }
+
+    #[test]
+    fn poll_interval_backs_off_while_idle() {
+        let mut interval = MIN_POLL_INTERVAL;
+        for _ in 0..10 {
+            interval = next_poll_interval(interval, false);
+        }
+        assert_eq!(interval, MAX_POLL_INTERVAL);
+
+        assert_eq!(next_poll_interval(interval, true), MIN_POLL_INTERVAL);
+    }
+}
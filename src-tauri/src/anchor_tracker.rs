@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::anchor::AnchorInfo;
+use crate::window_source::ExternalWindow;
+
+/// How long a target may be missing from the snapshot before its notes are
+/// hidden, so brief flickers (a workspace switch, a window being re-mapped)
+/// do not make notes blink.
+pub const HIDE_GRACE_PERIOD: Duration = Duration::from_millis(400);
+
+/// What the tracking loop has to do after looking at a window snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackerCommand {
+    /// Move the note window to this logical position.
+    MoveNote { label: String, x: f64, y: f64 },
+    /// A restored anchor found its target window; persist the refreshed info.
+    Attached { label: String, anchor: AnchorInfo },
+    /// The target is minimized, hidden or off-screen; hide the note until it returns.
+    HideNote { label: String },
+    /// The target is back on screen; show the note again.
+    ShowNote { label: String },
+    /// The target window is gone and the anchor was released.
+    TargetLost { label: String },
+}
+
+#[derive(Debug, Clone)]
+struct TrackedAnchor {
+    info: AnchorInfo,
+    last_target_bounds: Option<(f64, f64, f64, f64)>,
+    missing_since: Option<Instant>,
+    hidden: bool,
+}
+
+/// Keeps every anchor of the app and turns successive window snapshots into
+/// note move commands. It has no notion of time or threads: the caller decides
+/// when to take a snapshot and executes the returned commands.
+#[derive(Debug, Default)]
+pub struct AnchorTracker {
+    anchors: HashMap<String, TrackedAnchor>,
+    pending: HashMap<String, AnchorInfo>,
+}
+
+impl AnchorTracker {
+    /// Tracks an anchor whose target window is known to be on screen.
+    pub fn attach(&mut self, label: &str, info: AnchorInfo) {
+        self.pending.remove(label);
+        self.anchors.insert(
+            label.to_string(),
+            TrackedAnchor {
+                info,
+                last_target_bounds: None,
+                missing_since: None,
+                hidden: false,
+            },
+        );
+    }
+
+    /// Tracks a persisted anchor that still has to find its target window.
+    pub fn restore(&mut self, label: &str, info: AnchorInfo) {
+        self.anchors.remove(label);
+        self.pending.insert(label.to_string(), info);
+    }
+
+    pub fn remove(&mut self, label: &str) -> bool {
+        let was_anchored = self.anchors.remove(label).is_some();
+        let was_pending = self.pending.remove(label).is_some();
+        was_anchored || was_pending
+    }
+
+    pub fn contains(&self, label: &str) -> bool {
+        self.anchors.contains_key(label) || self.pending.contains_key(label)
+    }
+
+    /// Labels of every note anchored in the same group as `label`, including
+    /// `label` itself; just `label` for notes anchored on their own.
+    pub fn group_labels(&self, label: &str) -> Vec<String> {
+        let info = self
+            .anchors
+            .get(label)
+            .map(|tracked| &tracked.info)
+            .or_else(|| self.pending.get(label));
+        let Some(group_id) = info.and_then(|info| info.group.as_ref()).map(|g| &g.id) else {
+            return vec![label.to_string()];
+        };
+
+        let in_group = |info: &AnchorInfo| info.group.as_ref().is_some_and(|g| &g.id == group_id);
+        self.anchors
+            .iter()
+            .filter(|(_, tracked)| in_group(&tracked.info))
+            .map(|(label, _)| label.clone())
+            .chain(
+                self.pending
+                    .iter()
+                    .filter(|(_, info)| in_group(info))
+                    .map(|(label, _)| label.clone()),
+            )
+            .collect()
+    }
+
+    /// Whether the note was hidden because its target is not on screen.
+    pub fn is_hidden(&self, label: &str) -> bool {
+        self.anchors
+            .get(label)
+            .is_some_and(|tracked| tracked.hidden)
+    }
+
+    /// Whether a target is missing but still within its grace period, in which
+    /// case the caller should take another snapshot soon.
+    pub fn awaiting_grace_period(&self) -> bool {
+        self.anchors
+            .values()
+            .any(|tracked| tracked.missing_since.is_some() && !tracked.hidden)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchors.is_empty() && self.pending.is_empty()
+    }
+
+    /// Forgets anchors of note windows that no longer exist.
+    pub fn retain_labels(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.anchors.retain(|label, _| keep(label));
+        self.pending.retain(|label, _| keep(label));
+    }
+
+    /// Compares a snapshot with the previous one. Notes are only moved when
+    /// their target moved or was resized (or was just attached), so an idle
+    /// desktop produces no commands at all.
+    ///
+    /// A target missing from the snapshot is only released once
+    /// `window_exists` says it was destroyed; otherwise its notes are hidden
+    /// after `HIDE_GRACE_PERIOD` and shown again when it comes back.
+    /// `note_size` reports the logical size of a note window, needed by
+    /// corner-relative and clamped anchors.
+    pub fn process(
+        &mut self,
+        snapshot: &[ExternalWindow],
+        now: Instant,
+        window_exists: impl Fn(u32) -> bool,
+        note_size: impl Fn(&str) -> Option<(f64, f64)>,
+    ) -> Vec<TrackerCommand> {
+        let mut commands = Vec::new();
+
+        let resolved: Vec<(String, AnchorInfo)> = self
+            .pending
+            .iter()
+            .filter_map(|(label, info)| {
+                let target = match_anchor_target(info, snapshot)?;
+                Some((
+                    label.clone(),
+                    AnchorInfo {
+                        target_window_id: target.id,
+                        target_window_title: target.title.clone(),
+                        ..info.clone()
+                    },
+                ))
+            })
+            .collect();
+
+        for (label, info) in resolved {
+            self.attach(&label, info.clone());
+            commands.push(TrackerCommand::Attached {
+                label,
+                anchor: info,
+            });
+        }
+
+        let mut lost = Vec::new();
+        for (label, tracked) in &mut self.anchors {
+            let Some(target) = snapshot
+                .iter()
+                .find(|window| window.id == tracked.info.target_window_id)
+            else {
+                if !window_exists(tracked.info.target_window_id) {
+                    lost.push((label.clone(), tracked.hidden));
+                    continue;
+                }
+
+                let missing_since = *tracked.missing_since.get_or_insert(now);
+                if !tracked.hidden && now.duration_since(missing_since) >= HIDE_GRACE_PERIOD {
+                    tracked.hidden = true;
+                    commands.push(TrackerCommand::HideNote {
+                        label: label.clone(),
+                    });
+                }
+                continue;
+            };
+
+            tracked.missing_since = None;
+
+            let bounds = (target.x, target.y, target.width, target.height);
+            if tracked.last_target_bounds != Some(bounds) || tracked.hidden {
+                tracked.last_target_bounds = Some(bounds);
+                let (note_w, note_h) = note_size(label).unwrap_or_default();
+                let (x, y) = tracked.info.note_position(target, note_w, note_h);
+                commands.push(TrackerCommand::MoveNote {
+                    label: label.clone(),
+                    x,
+                    y,
+                });
+            }
+
+            if tracked.hidden {
+                tracked.hidden = false;
+                commands.push(TrackerCommand::ShowNote {
+                    label: label.clone(),
+                });
+            }
+        }
+
+        for (label, hidden) in lost {
+            self.anchors.remove(&label);
+            if hidden {
+                commands.push(TrackerCommand::ShowNote {
+                    label: label.clone(),
+                });
+            }
+            commands.push(TrackerCommand::TargetLost { label });
+        }
+
+        commands
+    }
+}
+
+/// Finds the window a persisted anchor belongs to: the same window id if the
+/// target app kept running, otherwise a window of the same app with the same
+/// title, otherwise the app's only window. Anchors made with the "frontmost
+/// window of app" rule take the app's frontmost window instead.
+fn match_anchor_target<'a>(
+    anchor_info: &AnchorInfo,
+    external: &'a [ExternalWindow],
+) -> Option<&'a ExternalWindow> {
+    let candidates: Vec<&ExternalWindow> = external
+        .iter()
+        .filter(|window| window.owner_name == anchor_info.target_app_name)
+        .collect();
+
+    if let Some(window) = candidates
+        .iter()
+        .find(|window| window.id == anchor_info.target_window_id)
+    {
+        return Some(window);
+    }
+
+    if anchor_info.any_window_of_app {
+        return candidates.first().copied();
+    }
+
+    if !anchor_info.target_window_title.is_empty() {
+        if let Some(window) = candidates
+            .iter()
+            .find(|window| window.title == anchor_info.target_window_title)
+        {
+            return Some(window);
+        }
+    }
+
+    match candidates.as_slice() {
+        [only] => Some(only),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{match_anchor_target, AnchorTracker, TrackerCommand, HIDE_GRACE_PERIOD};
+    use crate::anchor::{AnchorInfo, AnchorMode};
+    use crate::window_source::fake::window;
+    use crate::window_source::ExternalWindow;
+
+    fn anchor(target: &ExternalWindow, offset_x: f64, offset_y: f64) -> AnchorInfo {
+        AnchorInfo {
+            target_window_id: target.id,
+            offset_x,
+            offset_y,
+            mode: AnchorMode::TopLeft,
+            clamp: false,
+            target_app_name: target.owner_name.clone(),
+            target_window_title: target.title.clone(),
+            any_window_of_app: false,
+            group: None,
+        }
+    }
+
+    fn no_size(_label: &str) -> Option<(f64, f64)> {
+        None
+    }
+
+    fn destroyed(_id: u32) -> bool {
+        false
+    }
+
+    fn minimized(_id: u32) -> bool {
+        true
+    }
+
+    fn persisted_anchor(app_name: &str, title: &str) -> AnchorInfo {
+        AnchorInfo {
+            target_window_id: 99,
+            offset_x: 10.0,
+            offset_y: 20.0,
+            mode: AnchorMode::TopLeft,
+            clamp: false,
+            target_app_name: app_name.to_string(),
+            target_window_title: title.to_string(),
+            any_window_of_app: false,
+            group: None,
+        }
+    }
+
+    #[test]
+    fn moves_notes_only_when_target_moves() {
+        let editor = window(7, "Editor", 100.0, 100.0, 800.0, 600.0);
+        let mut tracker = AnchorTracker::default();
+        tracker.attach("sticky_a", anchor(&editor, 750.0, 20.0));
+
+        let snapshots = [
+            vec![editor.clone()],
+            vec![editor.clone()],
+            vec![window(7, "Editor", 300.0, 50.0, 800.0, 600.0)],
+        ];
+        let commands: Vec<Vec<TrackerCommand>> = snapshots
+            .iter()
+            .map(|snapshot| tracker.process(snapshot, Instant::now(), destroyed, no_size))
+            .collect();
+
+        assert_eq!(
+            commands,
+            vec![
+                vec![TrackerCommand::MoveNote {
+                    label: "sticky_a".to_string(),
+                    x: 850.0,
+                    y: 120.0,
+                }],
+                vec![],
+                vec![TrackerCommand::MoveNote {
+                    label: "sticky_a".to_string(),
+                    x: 1050.0,
+                    y: 70.0,
+                }],
+            ]
+        );
+    }
+
+    #[test]
+    fn releases_anchor_when_target_disappears() {
+        let editor = window(7, "Editor", 100.0, 100.0, 800.0, 600.0);
+        let mut tracker = AnchorTracker::default();
+        tracker.attach("sticky_a", anchor(&editor, 0.0, 0.0));
+
+        let commands = tracker.process(&[], Instant::now(), destroyed, no_size);
+
+        assert_eq!(
+            commands,
+            vec![TrackerCommand::TargetLost {
+                label: "sticky_a".to_string(),
+            }]
+        );
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn hides_note_while_target_is_minimized_and_restores_it() {
+        let editor = window(7, "Editor", 100.0, 100.0, 800.0, 600.0);
+        let mut tracker = AnchorTracker::default();
+        tracker.attach("sticky_a", anchor(&editor, 20.0, 30.0));
+        let start = Instant::now();
+        tracker.process(&[editor], start, minimized, no_size);
+
+        assert!(tracker.process(&[], start, minimized, no_size).is_empty());
+        assert!(tracker.awaiting_grace_period());
+
+        let after_grace = start + HIDE_GRACE_PERIOD + Duration::from_millis(1);
+        assert_eq!(
+            tracker.process(&[], after_grace, minimized, no_size),
+            vec![TrackerCommand::HideNote {
+                label: "sticky_a".to_string(),
+            }]
+        );
+        assert!(tracker.is_hidden("sticky_a"));
+        assert!(tracker
+            .process(&[], after_grace + HIDE_GRACE_PERIOD, minimized, no_size)
+            .is_empty());
+
+        let restored = window(7, "Editor", 400.0, 200.0, 800.0, 600.0);
+        assert_eq!(
+            tracker.process(&[restored], after_grace, minimized, no_size),
+            vec![
+                TrackerCommand::MoveNote {
+                    label: "sticky_a".to_string(),
+                    x: 420.0,
+                    y: 230.0,
+                },
+                TrackerCommand::ShowNote {
+                    label: "sticky_a".to_string(),
+                },
+            ]
+        );
+        assert!(tracker.contains("sticky_a"));
+    }
+
+    #[test]
+    fn shows_hidden_note_again_when_target_is_destroyed() {
+        let editor = window(7, "Editor", 100.0, 100.0, 800.0, 600.0);
+        let mut tracker = AnchorTracker::default();
+        tracker.attach("sticky_a", anchor(&editor, 0.0, 0.0));
+        let start = Instant::now();
+        tracker.process(&[editor], start, minimized, no_size);
+        tracker.process(&[], start, minimized, no_size);
+        tracker.process(&[], start + HIDE_GRACE_PERIOD, minimized, no_size);
+
+        let commands = tracker.process(&[], start + HIDE_GRACE_PERIOD, destroyed, no_size);
+
+        assert_eq!(
+            commands,
+            vec![
+                TrackerCommand::ShowNote {
+                    label: "sticky_a".to_string(),
+                },
+                TrackerCommand::TargetLost {
+                    label: "sticky_a".to_string(),
+                },
+            ]
+        );
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn pending_anchor_attaches_once_target_appears() {
+        let mut tracker = AnchorTracker::default();
+        tracker.restore("sticky_a", persisted_anchor("Terminal", "deploy"));
+
+        assert!(tracker
+            .process(&[], Instant::now(), destroyed, no_size)
+            .is_empty());
+        assert!(tracker.contains("sticky_a"));
+
+        let mut terminal = window(12, "Terminal", 0.0, 0.0, 800.0, 600.0);
+        terminal.title = "deploy".to_string();
+        let commands = tracker.process(&[terminal], Instant::now(), destroyed, no_size);
+
+        let attached = AnchorInfo {
+            target_window_id: 12,
+            ..persisted_anchor("Terminal", "deploy")
+        };
+        assert_eq!(
+            commands,
+            vec![
+                TrackerCommand::Attached {
+                    label: "sticky_a".to_string(),
+                    anchor: attached,
+                },
+                TrackerCommand::MoveNote {
+                    label: "sticky_a".to_string(),
+                    x: 10.0,
+                    y: 20.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rematches_persisted_anchor_by_app_and_title() {
+        let mut docs = window(3, "Editor", 0.0, 0.0, 800.0, 600.0);
+        docs.title = "notes.md".to_string();
+        let mut code = window(4, "Editor", 0.0, 0.0, 800.0, 600.0);
+        code.title = "main.rs".to_string();
+        let external = vec![window(2, "Browser", 0.0, 0.0, 800.0, 600.0), docs, code];
+
+        let target = match_anchor_target(&persisted_anchor("Editor", "main.rs"), &external);
+        assert_eq!(target.map(|w| w.id), Some(4));
+
+        let target = match_anchor_target(&persisted_anchor("Editor", "other.rs"), &external);
+        assert_eq!(target, None);
+
+        let target = match_anchor_target(&persisted_anchor("Browser", "Some page"), &external);
+        assert_eq!(target.map(|w| w.id), Some(2));
+
+        let rule = AnchorInfo {
+            any_window_of_app: true,
+            ..persisted_anchor("Editor", "other.rs")
+        };
+        assert_eq!(match_anchor_target(&rule, &external).map(|w| w.id), Some(3));
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tauri::{AppHandle, Manager, WebviewWindow};
+use tauri_plugin_log::log;
+
+use crate::anchor::AnchorState;
+use crate::save_load::set_note_app_binding;
+use crate::window_source::ExternalWindow;
+use crate::windows::note_id_from_label;
+
+const FRONTMOST_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Notes bound to an application with "show only with app X", keyed by window
+/// label. Bound notes are hidden while another application is frontmost.
+#[derive(Default)]
+pub struct AppBindingState {
+    bindings: Mutex<HashMap<String, String>>,
+    /// Labels currently hidden because their app is not frontmost.
+    hidden: Mutex<HashSet<String>>,
+    watching: Mutex<bool>,
+}
+
+/// External windows are listed front-to-back, so the owner of the first one is
+/// the frontmost application (the sticky notes themselves are never listed).
+fn frontmost_app(external: &[ExternalWindow]) -> Option<&str> {
+    external.first().map(|window| window.owner_name.as_str())
+}
+
+/// Which bound notes have to be shown (`true`) or hidden (`false`) for the
+/// given frontmost app. Without a frontmost app nothing changes, so notes do
+/// not flicker while the window list is briefly empty.
+fn visibility_changes(
+    bindings: &HashMap<String, String>,
+    hidden: &HashSet<String>,
+    frontmost: Option<&str>,
+) -> Vec<(String, bool)> {
+    let Some(frontmost) = frontmost else {
+        return Vec::new();
+    };
+
+    bindings
+        .iter()
+        .filter_map(|(label, app_name)| {
+            let show = app_name.eq_ignore_ascii_case(frontmost);
+            (show == hidden.contains(label)).then(|| (label.clone(), show))
+        })
+        .collect()
+}
+
+/// Whether the note is hidden because its bound app is not frontmost.
+pub fn is_hidden_by_binding(app: &AppHandle, label: &str) -> bool {
+    let state = app.state::<AppBindingState>();
+    let hidden = state.hidden.lock().unwrap();
+    hidden.contains(label)
+}
+
+/// Names of the applications that currently have windows, frontmost first.
+pub fn running_apps(app: &AppHandle) -> Vec<String> {
+    let anchor_state = app.state::<AnchorState>();
+    let mut apps: Vec<String> = Vec::new();
+
+    for window in anchor_state.source.external_windows(std::process::id()) {
+        if !apps.contains(&window.owner_name) {
+            apps.push(window.owner_name);
+        }
+    }
+
+    apps
+}
+
+/// Registers a binding loaded from the note store.
+pub fn restore_app_binding(app: &AppHandle, label: &str, app_name: String) {
+    let state = app.state::<AppBindingState>();
+    state
+        .bindings
+        .lock()
+        .unwrap()
+        .insert(label.to_string(), app_name);
+    start_watching_if_needed(app);
+}
+
+/// Binds the note to `app_name`, or removes its binding when `None`.
+pub fn set_app_binding(
+    app: &AppHandle,
+    window: &WebviewWindow,
+    app_name: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let label = window.label().to_string();
+    let note_id = note_id_from_label(&label)
+        .ok_or_else(|| anyhow::anyhow!("Not a sticky note window: {}", label))?;
+
+    set_note_app_binding(app, &note_id, app_name.clone())?;
+
+    let state = app.state::<AppBindingState>();
+    match app_name {
+        Some(app_name) => {
+            log::info!("Showing {} only with {}", label, app_name);
+            state.bindings.lock().unwrap().insert(label, app_name);
+            start_watching_if_needed(app);
+        }
+        None => {
+            log::info!("Showing {} with every app", label);
+            state.bindings.lock().unwrap().remove(&label);
+            if state.hidden.lock().unwrap().remove(&label) {
+                window.show()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn start_watching_if_needed(app: &AppHandle) {
+    let state = app.state::<AppBindingState>();
+    let mut watching = state.watching.lock().unwrap();
+    if *watching {
+        return;
+    }
+    *watching = true;
+    drop(watching);
+
+    let (sender, changes) = mpsc::channel();
+    let notified = app
+        .state::<AnchorState>()
+        .source
+        .watch_changes(sender.clone());
+
+    let app_handle = app.clone();
+
+    std::thread::spawn(move || {
+        // Keeps the channel open when the source dropped its sender, so
+        // waiting on it still paces the loop.
+        let _sender = sender;
+        log::info!(
+            "Watching the frontmost app for bound notes ({})",
+            if notified {
+                "change notifications"
+            } else {
+                "polling"
+            }
+        );
+        let own_pid = std::process::id();
+
+        loop {
+            let external = app_handle
+                .state::<AnchorState>()
+                .source
+                .external_windows(own_pid);
+            let open_windows = app_handle.webview_windows();
+            let state = app_handle.state::<AppBindingState>();
+
+            let changes_to_apply = {
+                let mut bindings = state.bindings.lock().unwrap();
+                let mut hidden = state.hidden.lock().unwrap();
+                bindings.retain(|label, _| open_windows.contains_key(label));
+                hidden.retain(|label| bindings.contains_key(label));
+
+                if bindings.is_empty() {
+                    *state.watching.lock().unwrap() = false;
+                    log::info!("Stopped watching the frontmost app (no bound notes)");
+                    break;
+                }
+
+                let changes_to_apply =
+                    visibility_changes(&bindings, &hidden, frontmost_app(&external));
+                for (label, show) in &changes_to_apply {
+                    if *show {
+                        hidden.remove(label);
+                    } else {
+                        hidden.insert(label.clone());
+                    }
+                }
+                changes_to_apply
+            };
+
+            let anchor_state = app_handle.state::<AnchorState>();
+            for (label, show) in changes_to_apply {
+                let Some(window) = open_windows.get(&label) else {
+                    continue;
+                };
+
+                if !show {
+                    let _ = window.hide();
+                    continue;
+                }
+
+                // A note whose anchor target is off-screen stays hidden until
+                // the target returns.
+                let anchor_hidden = anchor_state
+                    .tracker
+                    .lock()
+                    .map(|tracker| tracker.is_hidden(&label))
+                    .unwrap_or(false);
+                if !anchor_hidden {
+                    let _ = window.show();
+                }
+            }
+
+            wait_for_changes(&changes, FRONTMOST_POLL_INTERVAL);
+        }
+    });
+}
+
+/// Waits up to `timeout` for a window change, taking any that queued up
+/// behind it. A closed channel waits the full timeout rather than returning
+/// at once, which would spin the loop.
+fn wait_for_changes(changes: &Receiver<()>, timeout: Duration) {
+    match changes.recv_timeout(timeout) {
+        Ok(()) => while changes.try_recv().is_ok() {},
+        Err(RecvTimeoutError::Disconnected) => std::thread::sleep(timeout),
+        Err(RecvTimeoutError::Timeout) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use super::{frontmost_app, visibility_changes, wait_for_changes};
+    use crate::window_source::fake::window;
+
+    #[test]
+    fn frontmost_app_owns_the_first_window() {
+        let external = vec![
+            window(1, "Terminal", 0.0, 0.0, 800.0, 600.0),
+            window(2, "Browser", 0.0, 0.0, 800.0, 600.0),
+        ];
+
+        assert_eq!(frontmost_app(&external), Some("Terminal"));
+        assert_eq!(frontmost_app(&[]), None);
+    }
+
+    #[test]
+    fn shows_and_hides_bound_notes_for_the_frontmost_app() {
+        let bindings = HashMap::from([
+            ("sticky_deploy".to_string(), "Terminal".to_string()),
+            ("sticky_review".to_string(), "Browser".to_string()),
+        ]);
+        let hidden = HashSet::from(["sticky_deploy".to_string()]);
+
+        let mut changes = visibility_changes(&bindings, &hidden, Some("terminal"));
+        changes.sort();
+
+        assert_eq!(
+            changes,
+            vec![
+                ("sticky_deploy".to_string(), true),
+                ("sticky_review".to_string(), false),
+            ]
+        );
+        assert!(visibility_changes(&bindings, &hidden, None).is_empty());
+    }
+
+    #[test]
+    fn closed_change_channel_still_waits() {
+        let (sender, changes) = mpsc::channel();
+        drop(sender);
+
+        let started = Instant::now();
+        wait_for_changes(&changes, Duration::from_millis(50));
+
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+fn temp_path_for(path: &Path) -> anyhow::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("Invalid file path {:?}", path))?;
+    Ok(path.with_file_name(format!(".{file_name}.tmp")))
+}
+
+/// Replaces `path` with `contents` so that readers (and a crash at any point)
+/// see either the old file or the new one, never a partial write. The data is
+/// written to a temporary file next to `path`, synced, and renamed over it.
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let temp_path = temp_path_for(path)?;
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+
+    if let Err(error) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(error).with_context(|| format!("Failed to write {:?}", path));
+    }
+
+    // Persist the rename itself; not supported for directories on every platform.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::write_atomically;
+
+    #[test]
+    fn replaces_file_without_leaving_temp_files() {
+        let dir = std::env::temp_dir().join(format!("atomic_file_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("note.md");
+
+        write_atomically(&path, "first").unwrap();
+        write_atomically(&path, "second").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tauri_plugin_log::log;
+
+use crate::{atomic_file::write_atomically, note_formats::format_for_path};
+
+/// Folder of the notes directory that holds images and dropped files.
+pub const ATTACHMENTS_FOLDER: &str = "attachments";
+
+/// Image file extensions and their MIME types. The first extension of a
+/// type is the one its files are saved with.
+const IMAGE_TYPES: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("svg", "image/svg+xml"),
+    ("bmp", "image/bmp"),
+    ("avif", "image/avif"),
+];
+
+/// A file dropped on a note, as the editor inserts it.
+#[derive(serde::Serialize, Debug)]
+pub struct DroppedFile {
+    pub name: String,
+    /// Data URL of an image, which the note embeds.
+    pub image: Option<String>,
+    /// Path of any other file, copied to the attachments folder and linked.
+    pub link: Option<String>,
+}
+
+/// Contents as written to the file of a note in `notebook`: pasted images
+/// refer to the attachment files they are stored in, and attachment paths
+/// are relative to the note's folder.
+pub fn file_references(contents: &str, notebook: &str) -> String {
+    let prefix = notes_dir_prefix(notebook);
+    rewrite_references(contents, |reference, image| {
+        if let Some((name, _)) = image.then(|| image_attachment(reference)).flatten() {
+            return Some(format!("{prefix}{ATTACHMENTS_FOLDER}/{name}"));
+        }
+        (!prefix.is_empty() && attachment_name(reference).is_some())
+            .then(|| format!("{prefix}{reference}"))
+    })
+}
+
+/// Contents read from the file of a note in `notebook`, with attachment
+/// paths made relative to the notes folder again.
+pub fn note_references(contents: &str, notebook: &str) -> String {
+    let prefix = notes_dir_prefix(notebook);
+    if prefix.is_empty() {
+        return contents.to_string();
+    }
+    rewrite_references(contents, |reference, _| {
+        let path = reference.strip_prefix(&prefix)?;
+        attachment_name(path).map(|_| path.to_string())
+    })
+}
+
+/// Contents with attachment images read into data URLs, so the editor can
+/// show them. Images whose file is missing keep their path.
+pub fn embed_images(notes_dir: &Path, contents: &str) -> String {
+    rewrite_references(contents, |reference, image| {
+        let name = attachment_name(reference).filter(|_| image)?;
+        let mime = image_type(name)?;
+        let bytes = fs::read(notes_dir.join(ATTACHMENTS_FOLDER).join(name)).ok()?;
+        Some(data_url(mime, &bytes))
+    })
+}
+
+/// Writes the pasted images of `contents` to the attachments folder.
+pub fn store_images(notes_dir: &Path, contents: &str) -> anyhow::Result<()> {
+    let folder = notes_dir.join(ATTACHMENTS_FOLDER);
+    for (source, _) in references(contents).into_iter().filter(|(_, image)| *image) {
+        let Some((name, bytes)) = image_attachment(&source) else {
+            continue;
+        };
+        let path = folder.join(name);
+        if !path.exists() {
+            fs::create_dir_all(&folder).context("Failed to create attachments folder")?;
+            write_atomically(&path, bytes).context("Failed to write image attachment")?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a dropped file to the attachments folder, or reads it in if it
+/// is an image. A different file of the same name gets its content hash
+/// added to the name.
+pub fn attach_file(notes_dir: &Path, source: &Path) -> anyhow::Result<DroppedFile> {
+    let bytes = fs::read(source).with_context(|| format!("Failed to read {:?}", source))?;
+    let name = sanitize_file_name(
+        &source
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    );
+    if let Some(mime) = image_type(&name) {
+        return Ok(DroppedFile {
+            image: Some(data_url(mime, &bytes)),
+            name,
+            link: None,
+        });
+    }
+
+    let folder = notes_dir.join(ATTACHMENTS_FOLDER);
+    fs::create_dir_all(&folder).context("Failed to create attachments folder")?;
+    let mut file_name = name.clone();
+    if fs::read(folder.join(&file_name)).is_ok_and(|existing| existing != bytes) {
+        let path = Path::new(&name);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        file_name = match path.extension() {
+            Some(extension) => format!(
+                "{stem}-{}.{}",
+                content_hash(&bytes),
+                extension.to_string_lossy()
+            ),
+            None => format!("{stem}-{}", content_hash(&bytes)),
+        };
+    }
+    write_atomically(&folder.join(&file_name), &bytes).context("Failed to copy attachment")?;
+
+    Ok(DroppedFile {
+        name,
+        image: None,
+        link: Some(format!("{ATTACHMENTS_FOLDER}/{file_name}")),
+    })
+}
+
+/// Deletes attachments that no note in `contents` and no file in the notes
+/// folder, conflict copies included, refers to.
+pub fn remove_unused_attachments<'a>(
+    notes_dir: &Path,
+    contents: impl IntoIterator<Item = &'a str>,
+) -> anyhow::Result<()> {
+    let folder = notes_dir.join(ATTACHMENTS_FOLDER);
+    let Ok(entries) = fs::read_dir(&folder) else {
+        return Ok(());
+    };
+
+    let mut used = HashSet::new();
+    for contents in contents {
+        for (reference, _) in references(&file_references(contents, "")) {
+            used.extend(attachment_name(&reference).map(str::to_string));
+        }
+    }
+    for path in text_file_paths(notes_dir) {
+        if let Ok(text) = fs::read_to_string(&path) {
+            used.extend(mentioned_attachments(&text));
+        }
+    }
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_file() && !name.starts_with('.') && !used.contains(&name) {
+            log::info!("Removing unused attachment {}", name);
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    Ok(())
+}
+
+/// Image sources and links of a delta, each with whether it is an image.
+fn references(contents: &str) -> Vec<(String, bool)> {
+    let mut found = Vec::new();
+    rewrite_references(contents, |reference, image| {
+        found.push((reference.to_string(), image));
+        None
+    });
+    found
+}
+
+/// Replaces the image sources and links of a delta with what `rewrite`
+/// returns for them, keeping those it returns `None` for.
+fn rewrite_references(
+    contents: &str,
+    mut rewrite: impl FnMut(&str, bool) -> Option<String>,
+) -> String {
+    let Ok(mut delta) = serde_json::from_str::<Value>(contents) else {
+        return contents.to_string();
+    };
+    let Some(ops) = delta.get_mut("ops").and_then(Value::as_array_mut) else {
+        return contents.to_string();
+    };
+
+    let mut changed = false;
+    for op in ops {
+        for (pointer, image) in [("/insert/image", true), ("/attributes/link", false)] {
+            if let Some(Value::String(reference)) = op.pointer_mut(pointer) {
+                if let Some(rewritten) = rewrite(reference, image) {
+                    *reference = rewritten;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    if !changed {
+        return contents.to_string();
+    }
+    serde_json::to_string(&delta).unwrap_or_else(|_| contents.to_string())
+}
+
+/// `../` for every folder the notebook is below the notes folder.
+fn notes_dir_prefix(notebook: &str) -> String {
+    "../".repeat(notebook.split('/').filter(|part| !part.is_empty()).count())
+}
+
+/// Name of the attachment file a path relative to the notes folder points
+/// to.
+fn attachment_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix(ATTACHMENTS_FOLDER)?.strip_prefix('/')?;
+    (!name.is_empty() && !name.contains(['/', '\\']) && name != "..").then_some(name)
+}
+
+/// Names of the attachments a note file's text mentions.
+fn mentioned_attachments(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.match_indices(ATTACHMENTS_FOLDER)
+        .filter_map(|(index, _)| {
+            let rest = text[index + ATTACHMENTS_FOLDER.len()..].strip_prefix('/')?;
+            let end = rest
+                .find(|ch: char| ch.is_whitespace() || "()[]<>\"'".contains(ch))
+                .unwrap_or(rest.len());
+            (end > 0).then(|| rest[..end].to_string())
+        })
+}
+
+/// Note files of every format in `dir` and its subfolders, conflict copies
+/// included.
+fn text_file_paths(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if path.is_dir() {
+            if !name.starts_with('.') && name != ATTACHMENTS_FOLDER {
+                paths.extend(text_file_paths(&path));
+            }
+        } else if format_for_path(&path).is_some() {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// File name and bytes of the attachment a pasted image is stored in, named
+/// by its content so the same image is stored once.
+fn image_attachment(source: &str) -> Option<(String, Vec<u8>)> {
+    let (mime, data) = source.strip_prefix("data:")?.split_once(";base64,")?;
+    let (extension, _) = IMAGE_TYPES
+        .iter()
+        .find(|(_, image_mime)| image_mime.eq_ignore_ascii_case(mime))?;
+    let bytes = STANDARD.decode(data).ok()?;
+    Some((format!("{}.{extension}", content_hash(&bytes)), bytes))
+}
+
+fn image_type(file_name: &str) -> Option<&'static str> {
+    let extension = Path::new(file_name).extension()?.to_str()?;
+    IMAGE_TYPES
+        .iter()
+        .find(|(image_extension, _)| image_extension.eq_ignore_ascii_case(extension))
+        .map(|(_, mime)| *mime)
+}
+
+fn data_url(mime: &str, bytes: &[u8]) -> String {
+    format!("data:{mime};base64,{}", STANDARD.encode(bytes))
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// A file name that needs no escaping in markdown links.
+fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|ch| {
+            if ch.is_alphanumeric() || "-_.".contains(ch) {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let sanitized = sanitized.trim_start_matches('.');
+    if sanitized.is_empty() {
+        "file".to_string()
+    } else {
+        sanitized.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::{json, Value};
+
+    use super::{
+        attach_file, embed_images, file_references, note_references, remove_unused_attachments,
+        store_images,
+    };
+
+    const PIXEL: &str = "data:image/png;base64,iVBORw0KGgo=";
+
+    fn delta(ops: Value) -> String {
+        json!({ "ops": ops }).to_string()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("attachments_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn pasted_images_are_written_as_attachment_paths() {
+        let contents = delta(json!([
+            { "insert": { "image": PIXEL } },
+            { "insert": "report", "attributes": { "link": "attachments/report.pdf" } },
+            { "insert": "\n" },
+        ]));
+
+        let at_root: Value = serde_json::from_str(&file_references(&contents, "")).unwrap();
+        let image = at_root["ops"][0]["insert"]["image"].as_str().unwrap();
+        assert!(image.starts_with("attachments/") && image.ends_with(".png"));
+        assert_eq!(
+            at_root["ops"][1]["attributes"]["link"],
+            "attachments/report.pdf"
+        );
+
+        let nested = file_references(&contents, "work/2025");
+        assert!(nested.contains("\"../../attachments/report.pdf\""));
+        assert_eq!(
+            note_references(&nested, "work/2025"),
+            file_references(&contents, "")
+        );
+    }
+
+    #[test]
+    fn stored_images_are_embedded_again() {
+        let notes_dir = temp_dir("embed");
+        let contents = delta(json!([{ "insert": { "image": PIXEL } }, { "insert": "\n" }]));
+
+        store_images(&notes_dir, &contents).unwrap();
+        let linked = file_references(&contents, "");
+        assert_ne!(linked, contents);
+        assert_eq!(
+            serde_json::from_str::<Value>(&embed_images(&notes_dir, &linked)).unwrap(),
+            serde_json::from_str::<Value>(&contents).unwrap()
+        );
+
+        let _ = fs::remove_dir_all(notes_dir);
+    }
+
+    #[test]
+    fn removes_only_unreferenced_attachments() {
+        let notes_dir = temp_dir("gc");
+        let dropped = notes_dir.join("dropped.pdf");
+        fs::write(&dropped, "pdf").unwrap();
+        let attached = attach_file(&notes_dir, &dropped).unwrap();
+        assert_eq!(attached.link.as_deref(), Some("attachments/dropped.pdf"));
+        let contents = delta(json!([{ "insert": { "image": PIXEL } }, { "insert": "\n" }]));
+        store_images(&notes_dir, &contents).unwrap();
+        fs::write(
+            notes_dir.join("note.conflict-1.md"),
+            "[file](attachments/dropped.pdf)",
+        )
+        .unwrap();
+
+        remove_unused_attachments(&notes_dir, [contents.as_str()]).unwrap();
+        assert_eq!(
+            fs::read_dir(notes_dir.join("attachments")).unwrap().count(),
+            2
+        );
+
+        fs::remove_file(notes_dir.join("note.conflict-1.md")).unwrap();
+        remove_unused_attachments(&notes_dir, []).unwrap();
+        assert_eq!(
+            fs::read_dir(notes_dir.join("attachments")).unwrap().count(),
+            0
+        );
+
+        let _ = fs::remove_dir_all(notes_dir);
+    }
+}
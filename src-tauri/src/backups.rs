@@ -0,0 +1,473 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::NaiveDateTime;
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager};
+use tauri_plugin_log::log;
+
+use crate::{
+    atomic_file::write_atomically,
+    save_load::{backup_policy, create_backup},
+};
+
+/// How often the scheduler checks whether a backup is due.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Every snapshot's name starts with the time it was made.
+const SNAPSHOT_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+const ARCHIVE_EXTENSION: &str = ".tar.gz";
+/// Lists the notes in an archive, with their hashes.
+const MANIFEST_FILE: &str = "manifest.json";
+/// Backups made before a store migration are kept for good.
+const PRE_MIGRATION_MARKER: &str = "_pre_migration_";
+
+/// When backups are made and which ones are kept, stored in the settings.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct BackupPolicy {
+    /// Minutes between scheduled backups; 0 turns them off.
+    pub interval_minutes: u64,
+    /// Saved edits after which a backup is made before the interval is up;
+    /// 0 turns this off.
+    pub change_threshold: usize,
+    /// How many of the latest hours, days, weeks and months keep their
+    /// newest backup.
+    pub keep_hourly: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+    pub keep_monthly: usize,
+}
+
+impl Default for BackupPolicy {
+    fn default() -> Self {
+        Self {
+            interval_minutes: 60,
+            change_threshold: 50,
+            keep_hourly: 24,
+            keep_daily: 7,
+            keep_weekly: 4,
+            keep_monthly: 12,
+        }
+    }
+}
+
+/// What the scheduler needs to know about past backups.
+#[derive(Default)]
+pub struct BackupState {
+    /// Note edits saved since the last backup.
+    changes: AtomicUsize,
+    /// When the last backup was made or found unnecessary.
+    last_backup: Mutex<Option<Instant>>,
+    /// Held while a backup is made, so two never run at once.
+    running: Mutex<()>,
+}
+
+impl BackupState {
+    /// Starts a backup, waiting for one that is still running.
+    pub fn begin(&self) -> MutexGuard<'_, ()> {
+        let guard = self.running.lock().unwrap();
+        self.changes.store(0, Ordering::SeqCst);
+        *self.last_backup.lock().unwrap() = Some(Instant::now());
+        guard
+    }
+
+    fn is_due(&self, policy: &BackupPolicy) -> bool {
+        let interval = Duration::from_secs(policy.interval_minutes * 60);
+        policy.interval_minutes > 0
+            && self
+                .last_backup
+                .lock()
+                .unwrap()
+                .is_none_or(|last| last.elapsed() >= interval)
+    }
+
+    /// Counts an edit; true when it makes the edits since the last backup
+    /// reach `threshold`.
+    fn count_change(&self, threshold: usize) -> bool {
+        let changes = self.changes.fetch_add(1, Ordering::SeqCst) + 1;
+        threshold > 0 && changes == threshold
+    }
+}
+
+/// Makes a backup whenever the policy's interval has passed since the
+/// last one, so an app left running for weeks keeps getting them.
+pub fn start_backup_scheduler(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SCHEDULE_CHECK_INTERVAL);
+        let due = backup_policy(&app)
+            .map(|policy| app.state::<BackupState>().is_due(&policy))
+            .unwrap_or(false);
+        if due {
+            if let Err(e) = create_backup(&app) {
+                log::error!("Scheduled backup failed: {:#}", e);
+            }
+        }
+    });
+}
+
+/// Counts a saved edit and backs up once enough edits piled up.
+pub fn note_changed(app: &AppHandle) {
+    let threshold = backup_policy(app)
+        .map(|policy| policy.change_threshold)
+        .unwrap_or_default();
+    if !app.state::<BackupState>().count_change(threshold) {
+        return;
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        if let Err(e) = create_backup(&app) {
+            log::error!("Backup after {} edits failed: {:#}", threshold, e);
+        }
+    });
+}
+
+/// A backup in the backups folder: an archive named after the time it was
+/// made and the hash of its contents. Older backups are folders, or a file
+/// and a folder sharing a time.
+struct Snapshot {
+    time: NaiveDateTime,
+    hash: Option<String>,
+    paths: Vec<PathBuf>,
+}
+
+/// A file to back up and its path inside the archive.
+pub struct BackupEntry {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// A note as the manifest lists it: where its record and its file are in
+/// the archive. A file the archive does not have is listed as null.
+pub struct BackedUpNote {
+    pub id: String,
+    pub record: String,
+    pub file: String,
+}
+
+/// Every file under `folder`, named `prefix/<path inside folder>`.
+pub fn folder_entries(folder: &Path, prefix: &str) -> anyhow::Result<Vec<BackupEntry>> {
+    let mut entries = Vec::new();
+    if !folder.is_dir() {
+        return Ok(entries);
+    }
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().map(|name| name.to_string_lossy()) else {
+            continue;
+        };
+        let name = format!("{prefix}/{name}");
+        if path.is_dir() {
+            entries.extend(folder_entries(&path, &name)?);
+        } else if path.is_file() {
+            entries.push(BackupEntry { name, path });
+        }
+    }
+    Ok(entries)
+}
+
+/// Writes `entries` and a manifest of `notes` with their hashes into a new
+/// compressed archive, unless the newest backup holds the same contents.
+/// Returns the new archive.
+pub fn create_snapshot(
+    backup_dir: &Path,
+    entries: &[BackupEntry],
+    notes: &[BackedUpNote],
+    now: NaiveDateTime,
+) -> anyhow::Result<Option<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in entries {
+        match fs::read(&entry.path) {
+            Ok(contents) => files.push((entry.name.clone(), contents)),
+            // Deleted since the entries were listed.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", entry.path)),
+        }
+    }
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for (name, contents) in &files {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(contents);
+    }
+    let hash = hex(&hasher.finalize()[..8]);
+
+    fs::create_dir_all(backup_dir).context("Failed to create backup directory")?;
+    if snapshots(backup_dir)?
+        .first()
+        .and_then(|newest| newest.hash.as_ref())
+        == Some(&hash)
+    {
+        return Ok(None);
+    }
+
+    let manifest = serde_json::to_vec_pretty(&manifest(notes, &files))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    let mtime = now.and_utc().timestamp().max(0) as u64;
+    for (name, contents) in std::iter::once((MANIFEST_FILE, &manifest)).chain(
+        files
+            .iter()
+            .map(|(name, contents)| (name.as_str(), contents)),
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        archive
+            .append_data(&mut header, name, contents.as_slice())
+            .with_context(|| format!("Failed to archive {name}"))?;
+    }
+    let compressed = archive.into_inner()?.finish()?;
+
+    let snapshot = backup_dir.join(format!(
+        "{}_{hash}{ARCHIVE_EXTENSION}",
+        now.format(SNAPSHOT_TIME_FORMAT)
+    ));
+    write_atomically(&snapshot, compressed).context("Failed to create backup")?;
+    Ok(Some(snapshot))
+}
+
+/// Each note's id with the paths and SHA-256 hashes of its record and file.
+fn manifest(notes: &[BackedUpNote], files: &[(String, Vec<u8>)]) -> serde_json::Value {
+    let file_hash = |name: &str| {
+        files
+            .iter()
+            .find(|(file, _)| file == name)
+            .map(|(_, contents)| hex(&Sha256::digest(contents)))
+    };
+    let notes: Vec<serde_json::Value> = notes
+        .iter()
+        .map(|note| {
+            let file = Some(note.file.as_str()).filter(|file| file_hash(file).is_some());
+            serde_json::json!({
+                "id": note.id,
+                "record": note.record,
+                "record_sha256": file_hash(&note.record),
+                "file": file,
+                "file_sha256": file.and_then(file_hash),
+            })
+        })
+        .collect();
+    serde_json::json!({ "version": 1, "notes": notes })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Deletes the snapshots `policy` does not keep.
+pub fn prune_snapshots(backup_dir: &Path, policy: &BackupPolicy) -> anyhow::Result<()> {
+    let snapshots = snapshots(backup_dir)?;
+    let times: Vec<NaiveDateTime> = snapshots.iter().map(|snapshot| snapshot.time).collect();
+
+    for (snapshot, keep) in snapshots.iter().zip(kept_snapshots(&times, policy)) {
+        if keep {
+            continue;
+        }
+        for path in &snapshot.paths {
+            let removed = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+            match removed {
+                Ok(_) => log::info!("Deleted old backup: {:?}", path),
+                Err(e) => log::warn!("Failed to delete backup {:?}: {}", path, e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Which of `times`, newest first, `policy` keeps: the newest of each of
+/// the latest `keep_hourly` hours, `keep_daily` days, and so on. The
+/// newest one is always kept.
+fn kept_snapshots(times: &[NaiveDateTime], policy: &BackupPolicy) -> Vec<bool> {
+    let mut kept = vec![false; times.len()];
+    if let Some(newest) = kept.first_mut() {
+        *newest = true;
+    }
+
+    let periods = [
+        (policy.keep_hourly, "%Y-%m-%d %H"),
+        (policy.keep_daily, "%Y-%m-%d"),
+        (policy.keep_weekly, "%G-W%V"),
+        (policy.keep_monthly, "%Y-%m"),
+    ];
+    for (keep, period_format) in periods {
+        let mut periods_kept = 0;
+        let mut last_period = None;
+        for (index, time) in times.iter().enumerate() {
+            if periods_kept == keep {
+                break;
+            }
+            let period = time.format(period_format).to_string();
+            if last_period.as_ref() != Some(&period) {
+                kept[index] = true;
+                periods_kept += 1;
+                last_period = Some(period);
+            }
+        }
+    }
+    kept
+}
+
+/// Snapshots in the backups folder, newest first.
+fn snapshots(backup_dir: &Path) -> anyhow::Result<Vec<Snapshot>> {
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    for entry in fs::read_dir(backup_dir).context("Failed to read backup directory")? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if name.contains(PRE_MIGRATION_MARKER) {
+            continue;
+        }
+        let Some((time, rest)) = name
+            .split_at_checked(19)
+            .and_then(|(time, rest)| Some((time, rest.strip_prefix('_')?)))
+        else {
+            continue;
+        };
+        let Ok(time) = NaiveDateTime::parse_from_str(time, SNAPSHOT_TIME_FORMAT) else {
+            continue;
+        };
+        let rest = rest.strip_suffix(ARCHIVE_EXTENSION).unwrap_or(rest);
+        let hash = (rest.len() == 16 && rest.chars().all(|ch| ch.is_ascii_hexdigit()))
+            .then(|| rest.to_string());
+
+        match snapshots.iter_mut().find(|snapshot| snapshot.time == time) {
+            Some(snapshot) => {
+                snapshot.hash = snapshot.hash.take().or(hash);
+                snapshot.paths.push(path);
+            }
+            None => snapshots.push(Snapshot {
+                time,
+                hash,
+                paths: vec![path],
+            }),
+        }
+    }
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.time));
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs, io::Read};
+
+    use chrono::NaiveDateTime;
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+
+    use super::{
+        create_snapshot, folder_entries, kept_snapshots, prune_snapshots, BackedUpNote,
+        BackupEntry, BackupPolicy,
+    };
+
+    fn time(value: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn keeps_the_newest_backup_of_each_recent_period() {
+        let policy = BackupPolicy {
+            keep_hourly: 2,
+            keep_daily: 2,
+            keep_weekly: 0,
+            keep_monthly: 2,
+            ..BackupPolicy::default()
+        };
+        let times = [
+            time("2025-03-10 12:40"),
+            time("2025-03-10 12:10"),
+            time("2025-03-10 11:30"),
+            time("2025-03-10 09:00"),
+            time("2025-03-09 18:00"),
+            time("2025-03-08 18:00"),
+            time("2025-02-20 08:00"),
+            time("2025-01-20 08:00"),
+        ];
+
+        assert_eq!(
+            kept_snapshots(&times, &policy),
+            [true, false, true, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn archives_files_with_a_manifest_unless_unchanged() {
+        let dir = std::env::temp_dir().join(format!("backup_snapshots_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let records = dir.join("note_records");
+        fs::create_dir_all(&records).unwrap();
+        fs::write(records.join("a.json"), "{}").unwrap();
+        fs::write(dir.join("a.md"), "# A").unwrap();
+        let backups = dir.join("backups");
+        let notes = [BackedUpNote {
+            id: "a".to_string(),
+            record: "note_records/a.json".to_string(),
+            file: "notes/a.md".to_string(),
+        }];
+        let snapshot = |at: &str| {
+            let mut entries = folder_entries(&records, "note_records").unwrap();
+            entries.push(BackupEntry {
+                name: "notes/a.md".to_string(),
+                path: dir.join("a.md"),
+            });
+            create_snapshot(&backups, &entries, &notes, time(at)).unwrap()
+        };
+
+        let first = snapshot("2025-03-10 12:00").unwrap();
+        assert!(snapshot("2025-03-10 13:00").is_none());
+        fs::write(records.join("a.json"), "{\"changed\":true}").unwrap();
+        assert!(snapshot("2025-03-10 14:00").is_some());
+
+        let mut archive = tar::Archive::new(GzDecoder::new(fs::File::open(&first).unwrap()));
+        let mut files = HashMap::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+            files.insert(
+                entry.path().unwrap().to_string_lossy().to_string(),
+                contents,
+            );
+        }
+        assert_eq!(files["notes/a.md"], "# A");
+        assert_eq!(files["note_records/a.json"], "{}");
+        let manifest: Value = serde_json::from_str(&files["manifest.json"]).unwrap();
+        assert_eq!(manifest["notes"][0]["id"], "a");
+        assert_eq!(manifest["notes"][0]["file"], "notes/a.md");
+        assert_eq!(
+            manifest["notes"][0]["file_sha256"],
+            "327f031b25e00b1a7cd9b0c18f05948b60f55d09f9b3d177d21083f83a3cb6df"
+        );
+
+        let keep_latest = BackupPolicy {
+            keep_hourly: 0,
+            keep_daily: 0,
+            keep_weekly: 0,
+            keep_monthly: 0,
+            ..BackupPolicy::default()
+        };
+        prune_snapshots(&backups, &keep_latest).unwrap();
+        assert!(!first.exists());
+        assert_eq!(fs::read_dir(&backups).unwrap().count(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
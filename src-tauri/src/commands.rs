@@ -4,17 +4,25 @@ use anyhow::Context;
 use tauri::{Emitter, Manager};
 
 use crate::{
-    anchor,
+    anchor::{self, AnchorCandidate, AnchorPlacement},
+    app_binding,
+    attachments::DroppedFile,
+    backups::BackupPolicy,
+    filenames::FilenameStrategy,
+    flush,
     save_load::{
-        delete_note as delete_note_record, get_notes_directory_path, list_notes,
+        attach_files as attach_dropped_files, backup_policy, delete_note as delete_note_record,
+        dismiss_note_conflict, filename_strategy, get_notes_directory_path, list_notes,
         load_theme_stylesheet as load_theme_stylesheet_content, mark_note_archived, mark_note_open,
-        notes_directory, restart_notes_directory_watcher, save_sticky, set_notes_directory_path,
-        Note, NoteListItem, NoteStatus,
+        notes_directory, restart_notes_directory_watcher, save_sticky,
+        set_backup_policy as set_backup_policy_setting,
+        set_filename_strategy as set_filename_strategy_setting, set_notes_directory_path, Note,
+        NoteListItem, NoteStatus,
     },
     settings::MenuSettings,
     windows::{
-        close_sticky, close_sticky_by_note_id, create_sticky, open_note_manager, set_always_on_top,
-        sorted_windows,
+        close_sticky, close_sticky_by_note_id, create_sticky, note_id_from_label,
+        open_note_manager, set_always_on_top, snapped_notes, sorted_windows, sticky_label,
     },
 };
 
@@ -32,6 +40,9 @@ pub fn bring_all_to_front(
         .into_iter()
         .chain(once(window))
         .for_each(|w| {
+            #[cfg(not(target_os = "macos"))]
+            let _ = w;
+
             #[cfg(target_os = "macos")]
             {
                 use objc2_app_kit::NSWindow;
@@ -60,6 +71,7 @@ pub fn save_contents(
     color: String,
     contents: String,
     zoom: Option<f64>,
+    base_contents: Option<String>,
 ) -> Result<(), String> {
     let scale_factor = window.scale_factor().map_err(|e| e.to_string())?;
 
@@ -99,13 +111,38 @@ pub fn save_contents(
         zoom: zoom.unwrap_or(1.0),
     };
 
-    save_sticky(window.app_handle(), &note_id, note).map_err(|e| e.to_string())?;
+    save_sticky(window.app_handle(), &note_id, note, base_contents).map_err(|e| e.to_string())?;
 
     let _ = window.app_handle().emit("notes_changed", ());
 
     Ok(())
 }
 
+#[tauri::command]
+pub fn dismiss_conflict(window: tauri::WebviewWindow) -> Result<(), String> {
+    let note_id = note_id_from_label(window.label())
+        .ok_or_else(|| format!("Not a note window: {}", window.label()))?;
+    dismiss_note_conflict(window.app_handle(), &note_id).map_err(|e| e.to_string())
+}
+
+/// Files dropped on a note, stored as attachments for the note to insert.
+#[tauri::command]
+pub fn attach_files(
+    app: tauri::AppHandle,
+    paths: Vec<PathBuf>,
+) -> Result<Vec<DroppedFile>, String> {
+    attach_dropped_files(&app, &paths).map_err(|e| e.to_string())
+}
+
+/// Sent by a sticky window once it has saved in answer to a `save_request`
+/// that carried a flush id.
+#[tauri::command]
+pub fn flush_complete(window: tauri::WebviewWindow, flush_id: u64) {
+    window
+        .state::<flush::FlushState>()
+        .acknowledge(flush_id, window.label());
+}
+
 #[tauri::command]
 pub fn set_note_always_on_top(app: tauri::AppHandle, always_on_top: bool) -> Result<(), String> {
     set_always_on_top(&app, always_on_top).map_err(|e| e.to_string())
@@ -119,11 +156,93 @@ pub fn anchor_to_nearest(
     anchor::anchor_to_nearest(&app, &window).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn list_anchor_candidates(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+) -> Result<Vec<AnchorCandidate>, String> {
+    anchor::anchor_candidates(&app, &window).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn list_snapped_notes(app: tauri::AppHandle, window: tauri::WebviewWindow) -> Vec<String> {
+    snapped_notes(&app, &window)
+        .iter()
+        .filter_map(|note| note_id_from_label(note.label()))
+        .collect()
+}
+
+/// Open sticky windows of the given notes, other than `window` itself.
+fn group_members(
+    app: &tauri::AppHandle,
+    window: &tauri::WebviewWindow,
+    note_ids: Option<Vec<String>>,
+) -> Vec<tauri::WebviewWindow> {
+    note_ids
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|note_id| app.get_webview_window(&sticky_label(note_id)))
+        .filter(|member| member.label() != window.label())
+        .collect()
+}
+
+#[tauri::command]
+pub fn anchor_to_window(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    target_window_id: u32,
+    placement: Option<AnchorPlacement>,
+    group_note_ids: Option<Vec<String>>,
+) -> Result<String, String> {
+    let members = group_members(&app, &window, group_note_ids);
+    anchor::anchor_to_window_id(
+        &app,
+        &window,
+        &members,
+        target_window_id,
+        placement.unwrap_or_default(),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn anchor_to_app(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    app_name: String,
+    placement: Option<AnchorPlacement>,
+    group_note_ids: Option<Vec<String>>,
+) -> Result<String, String> {
+    let members = group_members(&app, &window, group_note_ids);
+    anchor::anchor_to_app(
+        &app,
+        &window,
+        &members,
+        &app_name,
+        placement.unwrap_or_default(),
+    )
+    .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn unanchor(app: tauri::AppHandle, window: tauri::WebviewWindow) -> Result<(), String> {
     anchor::unanchor(&app, &window).map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+pub fn list_running_apps(app: tauri::AppHandle) -> Vec<String> {
+    app_binding::running_apps(&app)
+}
+
+#[tauri::command]
+pub fn set_show_with_app(
+    app: tauri::AppHandle,
+    window: tauri::WebviewWindow,
+    app_name: Option<String>,
+) -> Result<(), String> {
+    app_binding::set_app_binding(&app, &window, app_name).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn open_note_manager_window(app: tauri::AppHandle) -> Result<(), String> {
     open_note_manager(&app).map_err(|e| e.to_string())
@@ -162,14 +281,18 @@ pub fn delete_note(app: tauri::AppHandle, note_id: String) -> Result<(), String>
         .map(|record| record.status)
         .ok_or_else(|| format!("Note not found: {note_id}"))?;
 
-    if status != NoteStatus::Archived {
-        return Err("Only archived notes can be deleted permanently".to_string());
-    }
+    let kind = match status {
+        NoteStatus::Archived => "archived",
+        NoteStatus::Missing => "missing",
+        _ => return Err("Only archived or missing notes can be deleted permanently".to_string()),
+    };
 
     let confirmed = matches!(
         rfd::MessageDialog::new()
-            .set_title("Delete archived note?")
-            .set_description("Are you sure you want to permanently delete this archived note?")
+            .set_title(format!("Delete {kind} note?"))
+            .set_description(format!(
+                "Are you sure you want to permanently delete this {kind} note?"
+            ))
             .set_level(rfd::MessageLevel::Warning)
             .set_buttons(rfd::MessageButtons::YesNo)
             .show(),
@@ -259,6 +382,31 @@ pub fn choose_notes_folder(app: tauri::AppHandle) -> Result<Option<String>, Stri
     Ok(Some(configured.to_string_lossy().to_string()))
 }
 
+#[tauri::command]
+pub fn get_filename_strategy(app: tauri::AppHandle) -> Result<FilenameStrategy, String> {
+    filename_strategy(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_filename_strategy(
+    app: tauri::AppHandle,
+    strategy: FilenameStrategy,
+) -> Result<(), String> {
+    set_filename_strategy_setting(&app, strategy).map_err(|e| e.to_string())?;
+    let _ = app.emit("notes_changed", ());
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_backup_policy(app: tauri::AppHandle) -> Result<BackupPolicy, String> {
+    backup_policy(&app).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn set_backup_policy(app: tauri::AppHandle, policy: BackupPolicy) -> Result<(), String> {
+    set_backup_policy_setting(&app, policy).map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 pub fn load_theme_stylesheet(app: tauri::AppHandle) -> Result<String, String> {
     load_theme_stylesheet_content(&app).map_err(|e| e.to_string())
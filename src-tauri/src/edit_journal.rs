@@ -0,0 +1,106 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Context;
+use tauri_plugin_log::log;
+
+use crate::atomic_file::write_atomically;
+use crate::save_load::Note;
+
+/// Edits that were received but may not have reached both the note store and
+/// the markdown file yet. Each pending edit is one file, written before the
+/// edit is applied and removed once it is; whatever is left after a crash is
+/// replayed on the next launch.
+pub struct EditJournal {
+    dir: PathBuf,
+}
+
+impl EditJournal {
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).context("Failed to create edit journal directory")?;
+        Ok(Self { dir })
+    }
+
+    fn path_for(&self, note_id: &str) -> PathBuf {
+        self.dir.join(format!("{note_id}.json"))
+    }
+
+    /// Records the latest edit of a note. A newer edit replaces an older one,
+    /// since only the last state of a note needs replaying.
+    pub fn record(&self, note_id: &str, note: &Note) -> anyhow::Result<()> {
+        write_atomically(&self.path_for(note_id), serde_json::to_vec(note)?)
+    }
+
+    /// Marks the note's pending edit as applied.
+    pub fn clear(&self, note_id: &str) -> anyhow::Result<()> {
+        let path = self.path_for(note_id);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to clear journal entry {}", note_id))?;
+        }
+        Ok(())
+    }
+
+    /// Edits left over from a previous run.
+    pub fn pending(&self) -> anyhow::Result<Vec<(String, Note)>> {
+        let mut pending = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(note_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let note = fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|raw| Ok(serde_json::from_slice::<Note>(&raw)?));
+            match note {
+                Ok(note) => pending.push((note_id.to_string(), note)),
+                Err(error) => {
+                    log::warn!(
+                        "Discarding unreadable journal entry {:?}: {:#}",
+                        path,
+                        error
+                    );
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+
+        Ok(pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::EditJournal;
+    use crate::save_load::Note;
+
+    #[test]
+    fn keeps_latest_unapplied_edit_per_note() {
+        let dir = std::env::temp_dir().join(format!("edit_journal_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let journal = EditJournal::open(dir.clone()).unwrap();
+
+        let edit = |contents: &str| Note {
+            contents: contents.to_string(),
+            ..Note::default()
+        };
+        journal.record("a", &edit("first")).unwrap();
+        journal.record("a", &edit("second")).unwrap();
+        journal.record("b", &edit("other")).unwrap();
+        journal.clear("b").unwrap();
+
+        let pending = journal.pending().unwrap();
+
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, "a");
+        assert_eq!(pending[0].1.contents, "second");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
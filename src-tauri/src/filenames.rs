@@ -0,0 +1,128 @@
+use chrono::{DateTime, Local};
+
+/// How the file of a note is named. Every name starts with the
+/// note's creation date and ends with its id, so names never collide.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FilenameStrategy {
+    /// `<date>_<id>.md`, never renamed.
+    IdOnly,
+    /// The first title the note had; later title edits keep the file name.
+    #[default]
+    FrozenTitle,
+    /// The `title` key of the note's frontmatter, or the id without one.
+    FrontmatterTitle,
+    /// The note's current first line; the file is renamed when it changes.
+    FollowTitle,
+}
+
+/// What a note's file name can be built from.
+pub struct FilenameParts<'a> {
+    pub created_at: &'a str,
+    /// Note id as it may appear in a file name.
+    pub id: &'a str,
+    pub first_line: &'a str,
+    pub frontmatter_title: Option<&'a str>,
+    /// Name of the note's file as it is now, if it has one.
+    pub current: Option<&'a str>,
+    /// Extension of the note's file format.
+    pub extension: &'a str,
+}
+
+/// File name for a note under `strategy`.
+pub fn markdown_filename(strategy: FilenameStrategy, parts: &FilenameParts) -> String {
+    let date_prefix = file_date_prefix(parts.created_at);
+    let with_title = |title: &str| {
+        let title_part = slugify_filename_part(title);
+        if title_part.is_empty() {
+            format!("{date_prefix}_{}.{}", parts.id, parts.extension)
+        } else {
+            format!(
+                "{date_prefix}_{title_part}_{}.{}",
+                parts.id, parts.extension
+            )
+        }
+    };
+
+    match strategy {
+        FilenameStrategy::IdOnly => with_title(""),
+        FilenameStrategy::FrozenTitle => match parts.current {
+            // A note without a title yet freezes the first one it gets.
+            Some(current) if current != with_title("") => current.to_string(),
+            _ => with_title(parts.first_line),
+        },
+        FilenameStrategy::FrontmatterTitle => with_title(parts.frontmatter_title.unwrap_or("")),
+        FilenameStrategy::FollowTitle => with_title(parts.first_line),
+    }
+}
+
+fn file_date_prefix(created_at: &str) -> String {
+    DateTime::parse_from_rfc3339(created_at)
+        .map(|date| date.with_timezone(&Local).format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|_| Local::now().format("%Y-%m-%d").to_string())
+}
+
+fn slugify_filename_part(value: &str) -> String {
+    let mut slug = String::new();
+    let mut previous_was_separator = false;
+
+    for ch in value.chars() {
+        if ch.is_ascii_alphanumeric() {
+            slug.push(ch.to_ascii_lowercase());
+            previous_was_separator = false;
+        } else if !previous_was_separator {
+            slug.push('_');
+            previous_was_separator = true;
+        }
+    }
+
+    slug = slug.trim_matches('_').to_string();
+    slug.chars().take(48).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{markdown_filename, FilenameParts, FilenameStrategy};
+
+    fn parts<'a>(first_line: &'a str, current: Option<&'a str>) -> FilenameParts<'a> {
+        FilenameParts {
+            created_at: "2025-01-10T12:00:00+00:00",
+            id: "abc",
+            first_line,
+            frontmatter_title: Some("Project Plan"),
+            current,
+            extension: "md",
+        }
+    }
+
+    #[test]
+    fn names_files_by_strategy() {
+        let name = |strategy| markdown_filename(strategy, &parts("Groceries!", None));
+
+        assert!(name(FilenameStrategy::IdOnly).ends_with("-10_abc.md"));
+        assert!(name(FilenameStrategy::FollowTitle).ends_with("_groceries_abc.md"));
+        assert!(name(FilenameStrategy::FrozenTitle).ends_with("_groceries_abc.md"));
+        assert!(name(FilenameStrategy::FrontmatterTitle).ends_with("_project_plan_abc.md"));
+    }
+
+    #[test]
+    fn frozen_titles_survive_title_edits() {
+        let first = markdown_filename(FilenameStrategy::FrozenTitle, &parts("", None));
+        let titled = markdown_filename(FilenameStrategy::FrozenTitle, &parts("Todo", Some(&first)));
+        let edited = markdown_filename(
+            FilenameStrategy::FrozenTitle,
+            &parts("Todo today", Some(&titled)),
+        );
+
+        assert!(first.ends_with("_abc.md"));
+        assert!(titled.ends_with("_todo_abc.md"));
+        assert_eq!(edited, titled);
+        assert_ne!(
+            markdown_filename(
+                FilenameStrategy::FollowTitle,
+                &parts("Todo today", Some(&titled))
+            ),
+            titled
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::HashSet,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use tauri::{AppHandle, Emitter, EventTarget, Manager};
+use tauri_plugin_log::log;
+
+use crate::windows::is_sticky_window_label;
+
+/// Tracks which sticky windows still have to confirm that they saved, so the
+/// app can wait for every note before exiting instead of guessing a delay.
+#[derive(Default)]
+pub struct FlushState {
+    round: Mutex<FlushRound>,
+    acknowledged: Condvar,
+}
+
+#[derive(Default)]
+struct FlushRound {
+    id: u64,
+    waiting_for: HashSet<String>,
+}
+
+impl FlushState {
+    /// Starts a new flush round waiting for `labels` and returns its id.
+    fn begin(&self, labels: impl IntoIterator<Item = String>) -> u64 {
+        let mut round = self.round.lock().unwrap();
+        round.id += 1;
+        round.waiting_for = labels.into_iter().collect();
+        round.id
+    }
+
+    /// Records that `label` finished saving for round `flush_id`. Answers to
+    /// older rounds are ignored.
+    pub fn acknowledge(&self, flush_id: u64, label: &str) {
+        let mut round = self.round.lock().unwrap();
+        if round.id == flush_id {
+            round.waiting_for.remove(label);
+            self.acknowledged.notify_all();
+        }
+    }
+
+    /// Blocks until every window of round `flush_id` answered, or `timeout`
+    /// passed. Returns the windows that did not answer.
+    fn wait(&self, flush_id: u64, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut round = self.round.lock().unwrap();
+
+        while round.id == flush_id && !round.waiting_for.is_empty() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            round = self.acknowledged.wait_timeout(round, remaining).unwrap().0;
+        }
+
+        round.waiting_for.iter().cloned().collect()
+    }
+}
+
+/// Asks every open sticky window to save and waits for all of them to
+/// confirm through `flush_complete`. Returns `false` if some window did not
+/// answer within `timeout`.
+pub fn flush_open_notes(app: &AppHandle, timeout: Duration) -> bool {
+    let state = app.state::<FlushState>();
+    let labels: Vec<String> = app
+        .webview_windows()
+        .into_keys()
+        .filter(|label| is_sticky_window_label(label))
+        .collect();
+
+    let flush_id = state.begin(labels.clone());
+    for label in labels {
+        let _ = app.emit_to(EventTarget::webview_window(label), "save_request", flush_id);
+    }
+
+    let missing = state.wait(flush_id, timeout);
+    if missing.is_empty() {
+        log::info!("All notes flushed");
+        true
+    } else {
+        log::warn!("Notes did not confirm saving before exit: {:?}", missing);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::FlushState;
+
+    #[test]
+    fn waits_until_every_window_acknowledges() {
+        let state = Arc::new(FlushState::default());
+        let flush_id = state.begin(["sticky_a".to_string(), "sticky_b".to_string()]);
+
+        let acknowledging = state.clone();
+        let handle = std::thread::spawn(move || {
+            acknowledging.acknowledge(flush_id, "sticky_a");
+            acknowledging.acknowledge(flush_id - 1, "sticky_b");
+            acknowledging.acknowledge(flush_id, "sticky_b");
+        });
+
+        let missing = state.wait(flush_id, Duration::from_secs(5));
+        handle.join().unwrap();
+
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn reports_windows_that_never_answer() {
+        let state = FlushState::default();
+        let flush_id = state.begin(["sticky_a".to_string(), "sticky_b".to_string()]);
+        state.acknowledge(flush_id, "sticky_a");
+
+        let missing = state.wait(flush_id, Duration::from_millis(20));
+
+        assert_eq!(missing, vec!["sticky_b".to_string()]);
+    }
+}
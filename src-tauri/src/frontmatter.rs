@@ -0,0 +1,260 @@
+use serde_yaml::Value;
+
+/// YAML frontmatter of a markdown note. Every top-level key keeps the exact
+/// text it was written with, so keys the app does not use (tags, aliases,
+/// lists, comments) can be written back unchanged.
+#[derive(Debug, Default)]
+pub struct Frontmatter {
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    value: Value,
+    source: String,
+}
+
+impl Frontmatter {
+    pub fn parse(yaml: &str) -> Self {
+        let entries = split_entries(yaml)
+            .into_iter()
+            .filter_map(parse_entry)
+            .collect();
+        Self { entries }
+    }
+
+    /// Value of `key`, compared case-insensitively.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|entry| entry.key.eq_ignore_ascii_case(key))
+            .map(|entry| &entry.value)
+    }
+
+    /// Value of `key` if it is a scalar, as text.
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        match self.get(key)? {
+            Value::String(value) => Some(value.clone()),
+            Value::Number(value) => Some(value.to_string()),
+            Value::Bool(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Source text of the entries whose key `is_known` does not accept.
+    pub fn unknown_entries(&self, is_known: impl Fn(&str) -> bool) -> String {
+        self.entries
+            .iter()
+            .filter(|entry| !is_known(&entry.key.to_ascii_lowercase()))
+            .map(|entry| entry.source.as_str())
+            .collect()
+    }
+
+    /// All entries as JSON values, for passing on to the frontend.
+    pub fn to_json(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some((entry.key.clone(), serde_json::to_value(&entry.value).ok()?)))
+            .collect()
+    }
+}
+
+/// Splits a note into its frontmatter and body. A note without a leading
+/// `---` block has empty frontmatter. A leading thematic break is not
+/// frontmatter: the block must close with a `---` or `...` line and hold
+/// YAML keys.
+pub fn split_frontmatter(raw: &str) -> (Frontmatter, String) {
+    let normalized = raw.replace("\r\n", "\n");
+    let Some(rest) = normalized.strip_prefix("---\n") else {
+        return (Frontmatter::default(), normalized);
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end_matches('\n'), "---" | "...") {
+            let yaml = &rest[..offset];
+            if !is_yaml_mapping(yaml) {
+                break;
+            }
+            let body = &rest[offset + line.len()..];
+            return (
+                Frontmatter::parse(yaml),
+                body.trim_start_matches('\n').to_string(),
+            );
+        }
+        offset += line.len();
+    }
+    (Frontmatter::default(), normalized)
+}
+
+/// Whether `yaml` is empty or a mapping. YAML that does not parse counts
+/// when its first line is a key, so a typo keeps the frontmatter apart.
+fn is_yaml_mapping(yaml: &str) -> bool {
+    match serde_yaml::from_str::<Value>(yaml) {
+        Ok(Value::Mapping(_) | Value::Null) => true,
+        Ok(_) => false,
+        Err(_) => yaml
+            .lines()
+            .find(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .and_then(|line| line.split_once(':'))
+            .is_some_and(|(key, _)| !key.trim().is_empty() && !key.starts_with([' ', '-'])),
+    }
+}
+
+/// `value` as a YAML scalar that reads back as the same string.
+pub fn yaml_scalar(value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+
+    match serde_yaml::from_str::<Value>(value) {
+        Ok(Value::String(parsed)) if parsed == value => value.to_string(),
+        _ => serde_json::to_string(value).unwrap_or_default(),
+    }
+}
+
+/// Cuts the frontmatter into one piece per top-level key, each with its
+/// nested lines and the comments right above it.
+fn split_entries(yaml: &str) -> Vec<String> {
+    let mut entries: Vec<String> = Vec::new();
+    let mut pending = String::new();
+
+    for line in yaml.lines() {
+        let starts_key = !line.is_empty() && !line.starts_with([' ', '\t', '#', '-']);
+        let is_detached = line.trim().is_empty() || line.starts_with('#');
+
+        if starts_key {
+            entries.push(std::mem::take(&mut pending));
+            let entry = entries.last_mut().unwrap();
+            entry.push_str(line);
+            entry.push('\n');
+        } else if is_detached || entries.is_empty() {
+            pending.push_str(line);
+            pending.push('\n');
+        } else {
+            let entry = entries.last_mut().unwrap();
+            entry.push_str(&std::mem::take(&mut pending));
+            entry.push_str(line);
+            entry.push('\n');
+        }
+    }
+
+    if let Some(entry) = entries.last_mut() {
+        if !pending.trim().is_empty() {
+            entry.push_str(&pending);
+        }
+    }
+
+    entries
+}
+
+fn parse_entry(source: String) -> Option<Entry> {
+    if let Ok(mapping) = serde_yaml::from_str::<serde_yaml::Mapping>(&source) {
+        let (key, value) = mapping.into_iter().next()?;
+        let key = match key {
+            Value::String(key) => key,
+            Value::Number(key) => key.to_string(),
+            Value::Bool(key) => key.to_string(),
+            _ => return None,
+        };
+        return Some(Entry { key, value, source });
+    }
+
+    // Not valid YAML on its own; read it as a plain `key: value` line like
+    // older versions did, and keep the text as it is.
+    let first_line = source
+        .lines()
+        .find(|line| !line.trim().is_empty() && !line.starts_with('#'))?;
+    let (key, value) = first_line.split_once(':')?;
+    Some(Entry {
+        key: key.trim().to_string(),
+        value: Value::String(value.trim().to_string()),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_yaml::Value;
+
+    use super::{split_frontmatter, yaml_scalar, Frontmatter};
+
+    #[test]
+    fn reads_yaml_values() {
+        let (frontmatter, body) = split_frontmatter(
+            "---\nid: 1700000000000\ntitle: \"Weekly: plan\"\ntags:\n  - work\n  - todo\naliases: [plan, week]\n---\n\nBody",
+        );
+
+        assert_eq!(body, "Body");
+        assert_eq!(
+            frontmatter.get_string("id").as_deref(),
+            Some("1700000000000")
+        );
+        assert_eq!(
+            frontmatter.get_string("title").as_deref(),
+            Some("Weekly: plan")
+        );
+        let tags = frontmatter.get("tags").unwrap().as_sequence().unwrap();
+        assert_eq!(tags, &vec![Value::from("work"), Value::from("todo")]);
+        assert_eq!(
+            frontmatter
+                .get("aliases")
+                .unwrap()
+                .as_sequence()
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_entries_verbatim() {
+        let yaml = "# from Obsidian\nid: abc\ntags:\n- work   # inline comment\n- 'quoted'\nstatus: open\ncssclass: wide\n";
+
+        let unknown =
+            Frontmatter::parse(yaml).unknown_entries(|key| key == "id" || key == "status");
+
+        assert_eq!(
+            unknown,
+            "tags:\n- work   # inline comment\n- 'quoted'\ncssclass: wide\n"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_plain_lines_for_invalid_yaml() {
+        let frontmatter = Frontmatter::parse("id: abc\ntitle: a: b: [\n");
+
+        assert_eq!(frontmatter.get_string("id").as_deref(), Some("abc"));
+        assert_eq!(frontmatter.get_string("title").as_deref(), Some("a: b: ["));
+    }
+
+    #[test]
+    fn leading_thematic_break_is_not_frontmatter() {
+        for raw in [
+            "---\nSome text\n---\nmore",
+            "---\n\nafter the rule",
+            "---\n- item\n---",
+        ] {
+            let (frontmatter, body) = split_frontmatter(raw);
+            assert!(frontmatter.to_json().is_empty(), "{raw}");
+            assert_eq!(body, raw);
+        }
+
+        let (frontmatter, body) = split_frontmatter("---\nid: abc\n...\n---\nBody");
+        assert_eq!(frontmatter.get_string("id").as_deref(), Some("abc"));
+        assert_eq!(body, "---\nBody");
+    }
+
+    #[test]
+    fn quotes_scalars_only_when_needed() {
+        assert_eq!(
+            yaml_scalar("2025-01-10T09:00:00+00:00"),
+            "2025-01-10T09:00:00+00:00"
+        );
+        assert_eq!(yaml_scalar("abc_1"), "abc_1");
+        assert_eq!(yaml_scalar("true"), "\"true\"");
+        assert_eq!(yaml_scalar("#tag"), "\"#tag\"");
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use tauri::{App, Emitter, Manager};
+use tauri::{App, Manager};
 use tauri_plugin_autostart::{MacosLauncher, ManagerExt};
 use tauri_plugin_log::log::{self, LevelFilter};
 use tauri_plugin_updater::UpdaterExt;
@@ -8,18 +8,45 @@ use tauri_plugin_updater::UpdaterExt;
 use crate::commands::*;
 use crate::menu::{create_menu, handle_menu_event};
 use crate::save_load::{
-    load_settings, load_stickies, restart_notes_directory_watcher, NotesFolderWatcherState,
+    load_settings, load_stickies, open_edit_journal, restart_notes_directory_watcher,
+    NotesFolderWatcherState,
 };
 
 mod anchor;
+mod anchor_tracker;
+mod app_binding;
+mod atomic_file;
+mod attachments;
+mod backups;
 mod commands;
+mod edit_journal;
+mod filenames;
+mod flush;
+mod frontmatter;
+mod markdown;
 mod menu;
+mod merge;
+mod migrations;
+mod missing_notes;
+mod note_formats;
+mod note_store;
 mod save_load;
 mod settings;
+mod window_source;
 mod windows;
 
+/// How long exit waits for open notes to confirm their last save.
+const EXIT_FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+
 fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
+    app.manage(anchor::AnchorState::default());
+    app.manage(app_binding::AppBindingState::default());
+    app.manage(backups::BackupState::default());
+    app.manage(flush::FlushState::default());
+    app.manage(missing_notes::MissingNotesState::default());
+    app.manage(open_edit_journal(app.handle())?);
     load_stickies(app.handle())?;
+    backups::start_backup_scheduler(app.handle());
 
     let menu_settings = load_settings(app.handle())?;
 
@@ -46,7 +73,6 @@ fn setup(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
     );
 
     app.manage(menu_settings);
-    app.manage(anchor::AnchorState::default());
     app.manage(NotesFolderWatcherState::default());
     restart_notes_directory_watcher(app.handle())?;
 
@@ -100,10 +126,19 @@ pub fn run() {
         .invoke_handler(tauri::generate_handler![
             bring_all_to_front,
             save_contents,
+            flush_complete,
+            dismiss_conflict,
+            attach_files,
             close_window,
             set_note_always_on_top,
             anchor_to_nearest,
+            list_anchor_candidates,
+            anchor_to_window,
+            anchor_to_app,
+            list_snapped_notes,
             unanchor,
+            list_running_apps,
+            set_show_with_app,
             open_note_manager_window,
             list_saved_notes,
             restore_note,
@@ -113,14 +148,18 @@ pub fn run() {
             get_notes_folder,
             set_notes_folder,
             choose_notes_folder,
+            get_filename_strategy,
+            set_filename_strategy,
+            get_backup_policy,
+            set_backup_policy,
             load_theme_stylesheet,
         ])
         .setup(setup)
         .build(tauri::generate_context!())
         .expect("error while running tauri application")
-        .run(move |app, event| match event {
+        .run(move |app, event| {
             // prevent app from exiting when no windows are open
-            tauri::RunEvent::ExitRequested { api, code, .. } => {
+            if let tauri::RunEvent::ExitRequested { api, code, .. } = event {
                 if allow_exit_after_flush {
                     return;
                 }
@@ -135,13 +174,11 @@ pub fn run() {
                     let app_handle = app.clone();
                     let exit_code = code.unwrap_or_default();
 
-                    let _ = app_handle.emit("save_request", ());
                     tauri::async_runtime::spawn_blocking(move || {
-                        std::thread::sleep(Duration::from_millis(220));
+                        flush::flush_open_notes(&app_handle, EXIT_FLUSH_TIMEOUT);
                         app_handle.exit(exit_code);
                     });
                 }
             }
-            _ => {}
         });
 }
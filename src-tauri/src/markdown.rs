@@ -0,0 +1,2475 @@
+use std::ops::Range;
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+use crate::save_load::{
+    bool_attr, make_delta_text_op, quill_delta_to_lines, str_attr, u64_attr, DeltaLine,
+    InlineSegment,
+};
+
+type Attributes = serde_json::Map<String, serde_json::Value>;
+
+fn parser_options() -> Options {
+    Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES
+}
+
+/// Converts markdown to Quill delta JSON following CommonMark. Every line of
+/// a paragraph becomes a line of the note, and blank lines between blocks
+/// are kept as empty lines, so the note reads the way the file was written.
+/// The HTML tags `quill_delta_to_markdown` writes for formats markdown has
+/// no syntax for are read back as those formats.
+pub fn markdown_to_quill_delta(markdown: &str) -> String {
+    let normalized = markdown.replace("\r\n", "\n");
+    if normalized.trim().is_empty() {
+        return String::new();
+    }
+
+    let mut builder = DeltaBuilder::new(&normalized);
+    for (event, range) in Parser::new_ext(&normalized, parser_options()).into_offset_iter() {
+        builder.event(event, range);
+    }
+    builder.finish()
+}
+
+enum Container {
+    Quote,
+    List {
+        ordered: bool,
+    },
+    Item {
+        checked: Option<bool>,
+        /// Column the item's text starts at, after any quote markers.
+        content_column: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Leaf {
+    Paragraph,
+    Heading(u64),
+    Code,
+    Html,
+    Cell,
+}
+
+/// The table being read: its column alignments and where in it the
+/// current cell is.
+struct Table {
+    alignments: Vec<Alignment>,
+    row: String,
+    column: usize,
+}
+
+struct DeltaBuilder<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    ops: Vec<serde_json::Value>,
+    containers: Vec<Container>,
+    leaf: Option<Leaf>,
+    /// Text formats of the enclosing emphasis, links and HTML tags.
+    inline: Vec<(String, serde_json::Value)>,
+    /// Open HTML tags and the text formats each one added.
+    html_tags: Vec<(String, Vec<String>)>,
+    /// Formats the current line got from a `data-line-format` span.
+    line_format: Attributes,
+    /// The current line has content that still needs its closing newline.
+    line_open: bool,
+    /// The next inline content starts a new line after a line break.
+    after_break: bool,
+    /// The current line is a lazy continuation outside the innermost list
+    /// item or quote, as a plain line after a list item is.
+    outside_item: bool,
+    outside_quote: bool,
+    /// Last source line whose blank lines have been accounted for.
+    last_line: Option<usize>,
+    /// Lines emitted for the innermost item so far.
+    item_lines: Vec<usize>,
+    /// Source and alt text of the image being read.
+    image: Option<(String, String)>,
+    /// Info string of the fenced code block being read, such as `rust`.
+    code_language: Option<String>,
+    table: Option<Table>,
+    /// Table rows read so far, which number the rows' ids.
+    table_rows: usize,
+}
+
+impl<'a> DeltaBuilder<'a> {
+    fn new(source: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self {
+            source,
+            line_starts,
+            ops: Vec::new(),
+            containers: Vec::new(),
+            leaf: None,
+            inline: Vec::new(),
+            html_tags: Vec::new(),
+            line_format: Attributes::new(),
+            line_open: false,
+            after_break: false,
+            outside_item: false,
+            outside_quote: false,
+            last_line: None,
+            item_lines: Vec::new(),
+            image: None,
+            code_language: None,
+            table: None,
+            table_rows: 0,
+        }
+    }
+
+    fn event(&mut self, event: Event, range: Range<usize>) {
+        if let Some((_, alt)) = &mut self.image {
+            match &event {
+                Event::Text(text) | Event::Code(text) | Event::InlineHtml(text) => {
+                    return alt.push_str(text)
+                }
+                Event::SoftBreak | Event::HardBreak => return alt.push(' '),
+                _ => {}
+            }
+        }
+        match event {
+            Event::Start(tag) => self.start(tag, range),
+            Event::End(tag) => self.end(tag, range),
+            Event::Text(text) => match self.leaf {
+                Some(Leaf::Code | Leaf::Html) => self.push_block_text(&text, range),
+                _ => self.push_text(&text, range, None),
+            },
+            Event::Code(text) => self.push_text(&text, range, Some(("code", true.into()))),
+            Event::InlineHtml(html) => self.inline_html(&html, range),
+            Event::InlineMath(math) | Event::DisplayMath(math) => {
+                self.push_text(&math, range, None)
+            }
+            Event::Html(html) => self.push_block_text(&html, range),
+            Event::FootnoteReference(label) => self.push_text(&label, range, None),
+            Event::SoftBreak | Event::HardBreak => {
+                self.end_line();
+                self.after_break = true;
+            }
+            Event::TaskListMarker(checked) => {
+                if let Some(Container::Item { checked: state, .. }) = self.containers.last_mut() {
+                    *state = Some(checked);
+                }
+            }
+            Event::Rule => {
+                self.end_open_line();
+                self.blank_lines_before(range.start);
+                self.mark_consumed(&range);
+                self.ops
+                    .push(serde_json::json!({ "insert": { "divider": true } }));
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag, range: Range<usize>) {
+        if matches!(
+            tag,
+            Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link { .. }
+        ) {
+            self.begin_line(range.start);
+        }
+        match tag {
+            Tag::Paragraph => self.start_leaf(Leaf::Paragraph, range.start),
+            Tag::Heading { level, .. } => {
+                self.start_leaf(Leaf::Heading(level as u64), range.start);
+                // A heading is a line even when it has no text.
+                self.begin_line(range.start);
+                self.mark_consumed(&(range.start..range.start + 1));
+            }
+            Tag::CodeBlock(kind) => {
+                self.start_leaf(Leaf::Code, range.start);
+                self.code_language = match kind {
+                    CodeBlockKind::Fenced(info) if !info.trim().is_empty() => {
+                        Some(info.trim().to_string())
+                    }
+                    _ => None,
+                };
+            }
+            Tag::HtmlBlock => self.start_leaf(Leaf::Html, range.start),
+            Tag::Table(alignments) => {
+                self.end_open_line();
+                self.absorb_blank_line_before(range.start);
+                // The blank line between two tables was taken by the first,
+                // but without a line between them they would read as one.
+                let after_table = self
+                    .ops
+                    .last()
+                    .is_some_and(|op| op["attributes"].get("table").is_some());
+                if after_table {
+                    self.ops
+                        .push(make_delta_text_op("\n".to_string(), Attributes::new()));
+                }
+                self.table = Some(Table {
+                    alignments,
+                    row: String::new(),
+                    column: 0,
+                });
+            }
+            Tag::TableHead | Tag::TableRow => {
+                self.table_rows += 1;
+                if let Some(table) = &mut self.table {
+                    table.row = format!("row-{}", self.table_rows);
+                    table.column = 0;
+                }
+            }
+            Tag::TableCell => {
+                self.start_leaf(Leaf::Cell, range.start);
+                // Every cell is a line, empty ones too.
+                self.begin_line(range.start);
+                self.mark_consumed(&range);
+            }
+            Tag::BlockQuote(_) => {
+                self.end_open_line();
+                self.blank_lines_before(range.start);
+                self.containers.push(Container::Quote);
+            }
+            Tag::List(start) => {
+                self.end_open_line();
+                // An empty item's line comes before the items nested in it.
+                if matches!(self.containers.last(), Some(Container::Item { .. }))
+                    && self.item_lines.last() == Some(&0)
+                {
+                    self.end_line();
+                }
+                self.containers.push(Container::List {
+                    ordered: start.is_some(),
+                });
+            }
+            Tag::Item => {
+                self.end_open_line();
+                self.blank_lines_before(range.start);
+                let content_column = self.item_content_column(range.start);
+                self.containers.push(Container::Item {
+                    checked: None,
+                    content_column,
+                });
+                self.item_lines.push(0);
+                self.after_break = false;
+            }
+            Tag::Emphasis => self.inline.push(("italic".to_string(), true.into())),
+            Tag::Strong => self.inline.push(("bold".to_string(), true.into())),
+            Tag::Strikethrough => self.inline.push(("strike".to_string(), true.into())),
+            Tag::Link { dest_url, .. } => self
+                .inline
+                .push(("link".to_string(), dest_url.to_string().into())),
+            Tag::Image { dest_url, .. } => {
+                self.begin_line(range.start);
+                self.mark_consumed(&range);
+                self.image = Some((dest_url.to_string(), String::new()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd, range: Range<usize>) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock | TagEnd::HtmlBlock => {
+                self.end_leaf()
+            }
+            TagEnd::TableCell => {
+                self.end_leaf();
+                if let Some(table) = &mut self.table {
+                    table.column += 1;
+                }
+            }
+            TagEnd::Table => {
+                self.table = None;
+                self.absorb_blank_line_after(range.end.saturating_sub(1));
+            }
+            TagEnd::BlockQuote(_) => {
+                self.end_open_line();
+                // Blank quote lines at the end of the quote belong to it.
+                let last_line = self.line_of(range.end.saturating_sub(1));
+                self.blank_lines_until(last_line + 1);
+                self.containers.pop();
+            }
+            TagEnd::List(_) => {
+                self.containers.pop();
+            }
+            TagEnd::Item => {
+                self.end_open_line();
+                if self.item_lines.last() == Some(&0) {
+                    self.end_line();
+                }
+                self.item_lines.pop();
+                self.containers.pop();
+            }
+            TagEnd::Emphasis => self.remove_inline("italic"),
+            TagEnd::Strong => self.remove_inline("bold"),
+            TagEnd::Strikethrough => self.remove_inline("strike"),
+            TagEnd::Link => self.remove_inline("link"),
+            TagEnd::Image => {
+                if let Some((source, alt)) = self.image.take() {
+                    let mut attributes = Attributes::new();
+                    if !alt.is_empty() {
+                        attributes.insert("alt".to_string(), alt.into());
+                    }
+                    self.push_embed(serde_json::json!({ "image": source }), attributes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the innermost format named `key`. HTML tags and markdown
+    /// emphasis may overlap, so it need not be the last one.
+    fn remove_inline(&mut self, key: &str) {
+        if let Some(index) = self.inline.iter().rposition(|(name, _)| name == key) {
+            self.inline.remove(index);
+        }
+    }
+
+    /// Applies a formatting tag written by `quill_delta_to_markdown`; any
+    /// other HTML is kept as text.
+    fn inline_html(&mut self, html: &str, range: Range<usize>) {
+        let Some(tag) = HtmlTag::parse(html) else {
+            return self.push_text(html, range, None);
+        };
+
+        if let Some(embed) = tag.embed() {
+            self.begin_line(range.start);
+            self.mark_consumed(&range);
+            self.html_tags.push((tag.name, Vec::new()));
+            return self.push_embed(embed, Attributes::new());
+        }
+
+        if tag.closing {
+            let Some(index) = self
+                .html_tags
+                .iter()
+                .rposition(|(name, _)| *name == tag.name)
+            else {
+                return self.push_text(html, range, None);
+            };
+            let (_, keys) = self.html_tags.remove(index);
+            for key in keys.iter().rev() {
+                self.remove_inline(key);
+            }
+            return;
+        }
+
+        let Some((inline, line)) = tag.formats() else {
+            return self.push_text(html, range, None);
+        };
+        self.begin_line(range.start);
+        self.mark_consumed(&range);
+        self.line_format.extend(line);
+        let keys = inline.keys().cloned().collect();
+        self.inline.extend(inline);
+        self.html_tags.push((tag.name, keys));
+    }
+
+    fn end_leaf(&mut self) {
+        self.end_open_line();
+        self.leaf = None;
+        self.inline.clear();
+        self.html_tags.clear();
+    }
+
+    fn start_leaf(&mut self, leaf: Leaf, start: usize) {
+        self.end_open_line();
+        self.blank_lines_before(start);
+        self.leaf = Some(leaf);
+        self.after_break = false;
+    }
+
+    fn line_of(&self, position: usize) -> usize {
+        self.line_starts
+            .partition_point(|&start| start <= position)
+            .saturating_sub(1)
+    }
+
+    fn line_text(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .map_or(self.source.len(), |next| next - 1);
+        &self.source[start..end]
+    }
+
+    /// Emits an empty line for every blank source line between the last
+    /// block and the one starting at `position`.
+    fn blank_lines_before(&mut self, position: usize) {
+        self.blank_lines_until(self.line_of(position));
+    }
+
+    fn blank_lines_until(&mut self, line: usize) {
+        let first = self.last_line.map_or(0, |last| last + 1);
+        for blank in first..line {
+            if self.is_blank(blank) {
+                let text = self.line_text(blank);
+                let mut attributes = Attributes::new();
+                if text.contains('>') {
+                    attributes.insert("blockquote".to_string(), true.into());
+                }
+                self.ops
+                    .push(make_delta_text_op("\n".to_string(), attributes));
+            }
+        }
+        if let Some(before) = line.checked_sub(1) {
+            self.last_line = Some(self.last_line.map_or(before, |last| last.max(before)));
+        }
+    }
+
+    /// Whether a source line is blank, but for quote markers.
+    fn is_blank(&self, line: usize) -> bool {
+        self.line_text(line)
+            .trim_matches(|ch: char| ch.is_whitespace() || ch == '>')
+            .is_empty()
+    }
+
+    /// A table is set apart from what comes before it by a blank line,
+    /// which is not an empty line of the note. A table at the start of the
+    /// file has none.
+    fn absorb_blank_line_before(&mut self, position: usize) {
+        let line = self.line_of(position);
+        let Some(blank) = line.checked_sub(1).filter(|blank| *blank > 0) else {
+            return;
+        };
+        if self.is_blank(blank) && self.last_line.is_none_or(|last| last < blank) {
+            self.blank_lines_until(blank);
+            self.last_line = Some(blank);
+        }
+    }
+
+    /// The blank line setting a table apart from what follows it.
+    fn absorb_blank_line_after(&mut self, position: usize) {
+        let blank = self.line_of(position) + 1;
+        let lines = self.line_starts.len() - usize::from(self.source.ends_with('\n'));
+        if blank < lines && self.is_blank(blank) {
+            self.blank_lines_until(blank);
+            self.last_line = Some(blank);
+        }
+    }
+
+    fn mark_consumed(&mut self, range: &Range<usize>) {
+        let line = self.line_of(range.end.saturating_sub(1).max(range.start));
+        self.last_line = Some(self.last_line.map_or(line, |last| last.max(line)));
+    }
+
+    /// Quote depth and indentation of the source text in front of `position`
+    /// on its line.
+    fn line_prefix(&self, position: usize) -> (usize, usize) {
+        let line_start = self.line_starts[self.line_of(position)];
+        let mut rest = &self.source[line_start..position];
+        let mut depth = 0;
+        while let Some(after) = rest.trim_start_matches(' ').strip_prefix('>') {
+            depth += 1;
+            rest = after.strip_prefix(' ').unwrap_or(after);
+        }
+        (depth, rest.chars().count())
+    }
+
+    fn item_content_column(&self, item_start: usize) -> usize {
+        let (_, marker_column) = self.line_prefix(item_start);
+        let line = self.line_of(item_start);
+        let line_end = self.line_starts[line] + self.line_text(line).len();
+        let after_start = &self.source[item_start..line_end];
+        let marker_length = after_start
+            .find(|ch: char| !ch.is_ascii_digit())
+            .map_or(after_start.len(), |digits| digits + 1);
+        let spaces = after_start[marker_length.min(after_start.len())..]
+            .chars()
+            .take_while(|ch| *ch == ' ')
+            .count();
+        let spaces = if spaces == 0 || spaces > 4 { 1 } else { spaces };
+        marker_column + marker_length + spaces
+    }
+
+    /// Starts a line for inline content at `position` if none is open.
+    fn begin_line(&mut self, position: usize) {
+        if self.line_open {
+            return;
+        }
+        self.line_open = true;
+        self.outside_item = false;
+        self.outside_quote = false;
+        if !std::mem::take(&mut self.after_break) {
+            return;
+        }
+
+        let (depth, indent) = self.line_prefix(position);
+        let quotes = self
+            .containers
+            .iter()
+            .filter(|container| matches!(container, Container::Quote))
+            .count();
+        self.outside_quote = depth < quotes;
+        self.outside_item = self
+            .containers
+            .iter()
+            .rev()
+            .find_map(|container| match container {
+                Container::Item { content_column, .. } => Some(indent < *content_column),
+                _ => None,
+            })
+            == Some(true);
+    }
+
+    fn inline_attributes(&self) -> Attributes {
+        self.inline.iter().cloned().collect()
+    }
+
+    fn push_text(
+        &mut self,
+        text: &str,
+        range: Range<usize>,
+        extra: Option<(&'static str, serde_json::Value)>,
+    ) {
+        self.begin_line(range.start);
+        self.mark_consumed(&range);
+        if text.is_empty() {
+            return;
+        }
+
+        let mut attributes = self.inline_attributes();
+        if let Some((key, value)) = extra {
+            attributes.insert(key.to_string(), value);
+        }
+        self.push_op(text, attributes);
+    }
+
+    /// Text of code and HTML blocks, which holds its own line breaks.
+    fn push_block_text(&mut self, text: &str, range: Range<usize>) {
+        self.mark_consumed(&range);
+        for piece in text.split_inclusive('\n') {
+            self.begin_line(range.start);
+            let line = piece.strip_suffix('\n');
+            let content = line.unwrap_or(piece);
+            if !content.is_empty() {
+                self.push_op(content, Attributes::new());
+            }
+            if line.is_some() {
+                self.end_line();
+            }
+        }
+    }
+
+    /// An image or other embed, formatted like the text around it.
+    fn push_embed(&mut self, embed: serde_json::Value, attributes: Attributes) {
+        let attributes: Attributes = self
+            .inline_attributes()
+            .into_iter()
+            .chain(attributes)
+            .collect();
+        let mut op = serde_json::json!({ "insert": embed });
+        if !attributes.is_empty() {
+            op["attributes"] = attributes.into();
+        }
+        self.ops.push(op);
+    }
+
+    fn push_op(&mut self, text: &str, attributes: Attributes) {
+        if let Some(previous) = self.ops.last_mut() {
+            let same_attributes = previous.get("attributes").and_then(|a| a.as_object())
+                == (!attributes.is_empty()).then_some(&attributes);
+            if let Some(previous_text) = previous
+                .get("insert")
+                .and_then(serde_json::Value::as_str)
+                .filter(|previous_text| !previous_text.ends_with('\n') && same_attributes)
+            {
+                previous["insert"] = format!("{previous_text}{text}").into();
+                return;
+            }
+        }
+        self.ops
+            .push(make_delta_text_op(text.to_string(), attributes));
+    }
+
+    fn end_open_line(&mut self) {
+        if self.line_open {
+            self.end_line();
+        }
+    }
+
+    fn end_line(&mut self) {
+        let mut attributes = self.block_attributes();
+        if !matches!(self.leaf, Some(Leaf::Code)) {
+            attributes.extend(std::mem::take(&mut self.line_format));
+            // A `false` line format removes one the line would otherwise
+            // get, such as the alignment of its table column.
+            attributes.retain(|_, value| *value != serde_json::Value::Bool(false));
+        }
+        self.ops
+            .push(make_delta_text_op("\n".to_string(), attributes));
+        self.line_open = false;
+        self.outside_item = false;
+        self.outside_quote = false;
+        if let Some(count) = self.item_lines.last_mut() {
+            *count += 1;
+        }
+    }
+
+    /// Block format of the current line. Quill gives a line one block
+    /// format, so the innermost one wins.
+    fn block_attributes(&self) -> Attributes {
+        let mut attributes = Attributes::new();
+        match self.leaf {
+            Some(Leaf::Code) => {
+                let language = self.code_language.clone();
+                attributes.insert(
+                    "code-block".to_string(),
+                    language.map_or(true.into(), Into::into),
+                );
+                return attributes;
+            }
+            Some(Leaf::Cell) => {
+                if let Some(table) = &self.table {
+                    attributes.insert("table".to_string(), table.row.clone().into());
+                    let align = match table.alignments.get(table.column) {
+                        Some(Alignment::Center) => Some("center"),
+                        Some(Alignment::Right) => Some("right"),
+                        _ => None,
+                    };
+                    if let Some(align) = align {
+                        attributes.insert("align".to_string(), align.into());
+                    }
+                }
+                return attributes;
+            }
+            Some(Leaf::Heading(level)) if !self.in_item() => {
+                attributes.insert("header".to_string(), level.into());
+                return attributes;
+            }
+            _ => {}
+        }
+
+        let lists = self
+            .containers
+            .iter()
+            .filter(|container| matches!(container, Container::List { .. }))
+            .count();
+        let mut containers = self.containers.iter().rev().peekable();
+        while let Some(container) = containers.next() {
+            match container {
+                Container::Item { checked, .. } if !self.outside_item => {
+                    let ordered =
+                        matches!(containers.peek(), Some(Container::List { ordered: true }));
+                    let kind = match checked {
+                        Some(true) => "checked",
+                        Some(false) => "unchecked",
+                        None if ordered => "ordered",
+                        None => "bullet",
+                    };
+                    attributes.insert("list".to_string(), kind.into());
+                    if lists > 1 {
+                        attributes.insert("indent".to_string(), (lists - 1).into());
+                    }
+                    return attributes;
+                }
+                Container::Quote if !self.outside_quote => {
+                    attributes.insert("blockquote".to_string(), true.into());
+                    return attributes;
+                }
+                _ => {}
+            }
+        }
+        attributes
+    }
+
+    fn in_item(&self) -> bool {
+        !self.outside_item
+            && self
+                .containers
+                .iter()
+                .any(|container| matches!(container, Container::Item { .. }))
+    }
+
+    fn finish(mut self) -> String {
+        self.end_open_line();
+        // Blank lines at the end, except the empty line after the file's
+        // final newline.
+        let lines = self.line_starts.len() - usize::from(self.source.ends_with('\n'));
+        self.blank_lines_until(lines);
+        if self.ops.is_empty() {
+            return String::new();
+        }
+        serde_json::to_string(&serde_json::json!({ "ops": self.ops })).unwrap_or_default()
+    }
+}
+
+/// Converts Quill delta JSON to markdown without losing anything the delta
+/// holds. Formats markdown has syntax for are written as markdown; the rest
+/// are written as inline HTML, which `markdown_to_quill_delta` reads back:
+///
+/// - underline, sub- and superscript as `<u>`, `<sub>` and `<sup>`;
+/// - text color and background as a `<span style="...">`;
+/// - any other text format as JSON in a `<span data-format='...'>`;
+/// - line formats without markdown syntax, such as indent on a paragraph
+///   or alignment, as JSON in an empty `<span data-line-format='...'>`
+///   at the start of the line.
+///
+/// A line whose formatting `*` and `~~` cannot express exactly, such as
+/// bold text ending in a space, uses HTML tags for all of it instead.
+/// Code block lines hold plain text only, as they do in the editor.
+pub fn quill_delta_to_markdown(contents: &str) -> String {
+    let Some(lines) = quill_delta_to_lines(contents) else {
+        return contents.to_string();
+    };
+
+    let mut rendered = Vec::new();
+    let mut ordered_counters: Vec<usize> = Vec::new();
+    let mut ordered_delimiters: Vec<Option<char>> = Vec::new();
+    let mut content_columns: Vec<usize> = Vec::new();
+    let mut code_block: Vec<String> = Vec::new();
+    let mut code_language = String::new();
+    let mut table: Vec<(String, DeltaLine)> = Vec::new();
+    // An empty list item cannot interrupt a paragraph; it would continue it.
+    let mut in_paragraph = false;
+
+    for line in lines {
+        let row = str_attr(&line.block_attributes, "table").map(str::to_string);
+        if !table.is_empty() && row.is_none() {
+            rendered.extend(render_table(&std::mem::take(&mut table)));
+            rendered.push(String::new());
+        }
+
+        let language = code_block_language(&line.block_attributes);
+        if !code_block.is_empty() && language != Some(code_language.as_str()) {
+            rendered.extend(fenced_code_block(
+                &std::mem::take(&mut code_block),
+                &code_language,
+            ));
+            ordered_counters.clear();
+            ordered_delimiters.clear();
+            content_columns.clear();
+            in_paragraph = false;
+        }
+        if let Some(language) = language {
+            code_language = language.to_string();
+            code_block.push(render_plain_segments(&line.segments));
+            continue;
+        }
+
+        // A table is set apart by blank lines, which are not lines of the
+        // note.
+        if let Some(row) = row {
+            if table.is_empty() {
+                if !rendered.is_empty() {
+                    rendered.push(String::new());
+                }
+                ordered_counters.clear();
+                ordered_delimiters.clear();
+                content_columns.clear();
+                in_paragraph = false;
+            }
+            table.push((row, line));
+            continue;
+        }
+
+        if line.segments.first().is_some_and(InlineSegment::is_divider) {
+            // `---` under a paragraph would make it a heading.
+            rendered.push(if in_paragraph { "***" } else { "---" }.to_string());
+            ordered_counters.clear();
+            ordered_delimiters.clear();
+            content_columns.clear();
+            in_paragraph = false;
+            continue;
+        }
+
+        let mut line_format = line.block_attributes.clone();
+        let header = u64_attr(&line_format, "header").filter(|level| (1..=6).contains(level));
+        let list = str_attr(&line_format, "list")
+            .filter(|kind| ["bullet", "ordered", "checked", "unchecked"].contains(kind))
+            .map(str::to_string);
+        let quote = bool_attr(&line_format, "blockquote");
+        let text = render_line_text(&line.segments);
+
+        if let Some(level) = header {
+            line_format.remove("header");
+            ordered_counters.clear();
+            ordered_delimiters.clear();
+            content_columns.clear();
+            in_paragraph = false;
+
+            let hashes = "#".repeat(level as usize);
+            let text = with_line_format(escape_closing_hashes(&text), &line_format);
+            if text.is_empty() {
+                rendered.push(hashes);
+            } else {
+                rendered.push(format!("{hashes} {text}"));
+            }
+            continue;
+        }
+
+        if let Some(list_kind) = list {
+            line_format.remove("list");
+            let indent = u64_attr(&line_format, "indent").unwrap_or(0) as usize;
+            // An item nests at most one level deeper than the item before it.
+            let level = indent.min(content_columns.len());
+            if level == indent {
+                line_format.remove("indent");
+            }
+            // Nested items start at their parent's text, which sits further
+            // in after `10.` than after `-`.
+            let indent_width = level
+                .checked_sub(1)
+                .map_or(0, |parent| content_columns[parent]);
+
+            let marker = if list_kind == "ordered" {
+                ordered_counters.resize(level + 1, 0);
+                ordered_counters[level] += 1;
+                if ordered_counters[level] == 1 {
+                    // A list right after another one at this level would
+                    // continue it, so it switches between `.` and `)`.
+                    let previous = ordered_delimiters.get(level).copied().flatten();
+                    ordered_delimiters.resize(level + 1, None);
+                    ordered_delimiters[level] = Some(if previous == Some('.') { ')' } else { '.' });
+                }
+                ordered_delimiters.truncate(level + 1);
+                let delimiter = ordered_delimiters[level].unwrap_or('.');
+                format!("{}{delimiter}", ordered_counters[level])
+            } else {
+                ordered_counters.truncate(level);
+                ordered_delimiters.truncate(level);
+                "-".to_string()
+            };
+            content_columns.truncate(level);
+            content_columns.push(indent_width + marker.len() + 1);
+
+            let task = match list_kind.as_str() {
+                "checked" => " [x]",
+                "unchecked" => " [ ]",
+                _ => "",
+            };
+            let mut text = with_line_format(text, &line_format);
+            // A task box needs text after it, and an empty item cannot
+            // interrupt a paragraph; an empty span gives them some.
+            if text.is_empty() && (!task.is_empty() || in_paragraph) {
+                text = "<span></span>".to_string();
+            }
+            let prefix = format!("{}{marker}{task}", " ".repeat(indent_width));
+            in_paragraph = !text.is_empty();
+            if text.is_empty() {
+                rendered.push(prefix);
+            } else {
+                rendered.push(format!("{prefix} {text}"));
+            }
+            continue;
+        }
+
+        let blank = text.is_empty() && line_format.is_empty();
+        ordered_counters.clear();
+        content_columns.clear();
+        if !blank {
+            ordered_delimiters.clear();
+        }
+
+        if quote {
+            line_format.remove("blockquote");
+            let text = with_line_format(text, &line_format);
+            in_paragraph = !text.is_empty();
+            if text.is_empty() {
+                rendered.push(">".to_string());
+            } else {
+                rendered.push(format!("> {text}"));
+            }
+            continue;
+        }
+
+        let text = with_line_format(text, &line_format);
+        in_paragraph = !text.is_empty();
+        rendered.push(text);
+    }
+
+    if !code_block.is_empty() {
+        rendered.extend(fenced_code_block(&code_block, &code_language));
+    }
+    if !table.is_empty() {
+        rendered.extend(render_table(&table));
+    }
+    // The file's last newline ends its last line, so a trailing empty line
+    // needs one more.
+    if rendered.last().is_some_and(String::is_empty) {
+        rendered.push(String::new());
+    }
+
+    rendered.join("\n")
+}
+
+fn render_plain_segments(segments: &[InlineSegment]) -> String {
+    segments
+        .iter()
+        .map(|segment| segment.text.as_str())
+        .collect::<String>()
+}
+
+/// Language of a code block line, empty for a block without one.
+fn code_block_language(attributes: &Attributes) -> Option<&str> {
+    match attributes.get("code-block")? {
+        serde_json::Value::Bool(true) => Some(""),
+        serde_json::Value::String(language) => Some(language),
+        _ => None,
+    }
+}
+
+/// A fence longer than any run of its character in the code, so no code
+/// line can close it, followed by the block's language. A language with a
+/// backtick needs a tilde fence.
+fn fenced_code_block(lines: &[String], language: &str) -> Vec<String> {
+    let marker = if language.contains('`') { '~' } else { '`' };
+    let longest_run = lines
+        .iter()
+        .flat_map(|line| line.split(|ch| ch != marker))
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = marker.to_string().repeat(longest_run.max(2) + 1);
+
+    let mut block = vec![format!("{fence}{}", escape_info_string(language))];
+    block.extend(lines.iter().cloned());
+    block.push(fence);
+    block
+}
+
+/// Backslash-escapes what the info string of a fence would otherwise decode.
+fn escape_info_string(info: &str) -> String {
+    let chars: Vec<char> = info.chars().collect();
+    let mut escaped = String::with_capacity(info.len());
+    for (index, &ch) in chars.iter().enumerate() {
+        if ch == '\\' || (ch == '&' && is_entity(&chars[index + 1..])) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// A GFM table of consecutive table cell lines; a row's cells share its id.
+/// Columns take the alignment of their cell in the first row, and a cell
+/// aligned otherwise keeps its own in a line format span. Markdown rows all
+/// have a cell for each column, so shorter rows are padded with empty cells,
+/// which the note gets back when the file is read.
+fn render_table(cells: &[(String, DeltaLine)]) -> Vec<String> {
+    let mut rows: Vec<Vec<&DeltaLine>> = Vec::new();
+    let mut previous_row = None;
+    for (row, line) in cells {
+        if previous_row != Some(row) {
+            rows.push(Vec::new());
+        }
+        if let Some(last) = rows.last_mut() {
+            last.push(line);
+        }
+        previous_row = Some(row);
+    }
+
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let alignments: Vec<Option<&str>> = (0..columns)
+        .map(|column| {
+            rows[0]
+                .get(column)
+                .and_then(|cell| str_attr(&cell.block_attributes, "align"))
+                .filter(|align| ["center", "right"].contains(align))
+        })
+        .collect();
+    let render_row = |row: &[&DeltaLine]| {
+        let cells: Vec<String> = alignments
+            .iter()
+            .enumerate()
+            .map(|(column, align)| {
+                row.get(column)
+                    .map_or(String::new(), |cell| render_cell(cell, *align))
+            })
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let delimiters: Vec<&str> = alignments
+        .iter()
+        .map(|align| match align {
+            Some("center") => ":---:",
+            Some(_) => "---:",
+            None => "---",
+        })
+        .collect();
+    let mut rendered = vec![
+        render_row(&rows[0]),
+        format!("| {} |", delimiters.join(" | ")),
+    ];
+    rendered.extend(rows[1..].iter().map(|row| render_row(row)));
+    rendered
+}
+
+/// A table cell's inline markdown, with its line formats other than its
+/// row and its column's alignment. A `|` in a cell ends it unless escaped,
+/// which text already is; only code spans are left to escape.
+fn render_cell(cell: &DeltaLine, column_align: Option<&str>) -> String {
+    let mut line_format = cell.block_attributes.clone();
+    line_format.remove("table");
+    match (line_format.get("align"), column_align) {
+        (Some(align), Some(column_align)) if align == column_align => {
+            line_format.remove("align");
+        }
+        (None, Some(_)) => {
+            line_format.insert("align".to_string(), false.into());
+        }
+        _ => {}
+    }
+
+    let segments = merged_segments(&cell.segments);
+    let markdown = escape_cell_pipes(&render_inline_segments(&segments, Syntax::Markdown));
+    let text = if reads_back_in_cell(&markdown, &segments) {
+        markdown
+    } else {
+        render_inline_segments(&segments, Syntax::Html)
+    };
+    with_line_format(String::new(), &line_format) + &text
+}
+
+/// Backslash-escapes each `|` that is not escaped yet.
+fn escape_cell_pipes(markdown: &str) -> String {
+    let mut escaped = String::with_capacity(markdown.len());
+    let mut backslashes = 0;
+    for ch in markdown.chars() {
+        if ch == '|' && backslashes % 2 == 0 {
+            escaped.push('\\');
+        }
+        backslashes = if ch == '\\' { backslashes + 1 } else { 0 };
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn reads_back_in_cell(cell: &str, segments: &[InlineSegment]) -> bool {
+    let table = format!("| |\n| --- |\n| {cell} |");
+    let lines = quill_delta_to_lines(&markdown_to_quill_delta(&table)).unwrap_or_default();
+    match lines.as_slice() {
+        [_, line] => merged_segments(&line.segments) == segments,
+        _ => false,
+    }
+}
+
+/// Prefixes `text` with the line formats markdown has no syntax for.
+fn with_line_format(text: String, line_format: &Attributes) -> String {
+    if line_format.is_empty() {
+        return text;
+    }
+    let json = serde_json::Value::Object(line_format.clone()).to_string();
+    format!(
+        "<span data-line-format='{}'></span>{text}",
+        escape_html_attribute(&json, '\'')
+    )
+}
+
+/// Inline markdown of a line, escaped so it reads back as the same text
+/// wherever the line ends up: at the start of a paragraph, list item,
+/// quote or heading.
+fn render_line_text(segments: &[InlineSegment]) -> String {
+    let segments = merged_segments(segments);
+    let markdown = render_inline_segments(&segments, Syntax::Markdown);
+    if reads_back_as(&markdown, &segments) {
+        markdown
+    } else {
+        render_inline_segments(&segments, Syntax::Html)
+    }
+}
+
+/// Segments without empty text, with neighbouring text of equal format
+/// joined.
+fn merged_segments(segments: &[InlineSegment]) -> Vec<InlineSegment> {
+    let mut merged: Vec<InlineSegment> = Vec::new();
+    for segment in segments
+        .iter()
+        .filter(|segment| !segment.text.is_empty() || segment.embed.is_some())
+    {
+        match merged.last_mut() {
+            Some(last)
+                if last.embed.is_none()
+                    && segment.embed.is_none()
+                    && last.attributes == segment.attributes =>
+            {
+                last.text.push_str(&segment.text)
+            }
+            _ => merged.push(segment.clone()),
+        }
+    }
+    merged
+}
+
+fn reads_back_as(markdown: &str, segments: &[InlineSegment]) -> bool {
+    let lines = quill_delta_to_lines(&markdown_to_quill_delta(markdown)).unwrap_or_default();
+    match lines.as_slice() {
+        [] => segments.is_empty(),
+        [line] => line.block_attributes.is_empty() && merged_segments(&line.segments) == segments,
+        _ => false,
+    }
+}
+
+/// Whether `text` at the start of a line would open a block: a heading,
+/// quote, list item, thematic break or setext underline.
+fn starts_block(text: &str) -> bool {
+    let first = text.chars().next();
+    let followed_by_space = |marker_length: usize| {
+        text[marker_length..]
+            .chars()
+            .next()
+            .is_none_or(|ch| ch == ' ' || ch == '\t')
+    };
+
+    let hashes = text.chars().take_while(|ch| *ch == '#').count();
+    if (1..=6).contains(&hashes) && followed_by_space(hashes) {
+        return true;
+    }
+
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    if (1..=9).contains(&digits)
+        && text[digits..].starts_with(['.', ')'])
+        && followed_by_space(digits + 1)
+    {
+        return true;
+    }
+
+    match first {
+        Some('>') => true,
+        Some('-' | '+') if followed_by_space(1) => true,
+        Some(marker @ ('-' | '=')) => text
+            .chars()
+            .all(|ch| ch == marker || ch == ' ' || ch == '\t'),
+        _ => false,
+    }
+}
+
+/// A heading ending in ` #` would lose the hashes as a closing sequence.
+fn escape_closing_hashes(text: &str) -> String {
+    let without = text.trim_end_matches('#');
+    if without.len() == text.len() || !(without.is_empty() || without.ends_with(' ')) {
+        return text.to_string();
+    }
+    format!("{without}\\{}", &text[without.len()..])
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Syntax {
+    Markdown,
+    Html,
+}
+
+/// Inline markup of a segment that spans other segments, like `**` around
+/// several differently formatted pieces of bold text. Listed outermost
+/// first.
+#[derive(Clone, PartialEq)]
+enum Mark {
+    Link(String),
+    /// Color, background and formats without markdown or HTML syntax.
+    Span(Attributes),
+    Underline,
+    Script(String),
+    Strike,
+    Bold,
+    Italic,
+}
+
+impl Mark {
+    /// Marks of a segment and whether it is inline code.
+    fn of(attributes: &Attributes) -> (Vec<Mark>, bool) {
+        use serde_json::Value;
+
+        let mut marks = Vec::new();
+        let mut span = Attributes::new();
+        let mut code = false;
+        for (key, value) in attributes {
+            let mark = match (key.as_str(), value) {
+                ("link", Value::String(url)) => Mark::Link(url.clone()),
+                ("script", Value::String(kind)) if kind == "sub" || kind == "super" => {
+                    Mark::Script(kind.clone())
+                }
+                ("underline", Value::Bool(true)) => Mark::Underline,
+                ("strike", Value::Bool(true)) => Mark::Strike,
+                ("bold", Value::Bool(true)) => Mark::Bold,
+                ("italic", Value::Bool(true)) => Mark::Italic,
+                ("code", Value::Bool(true)) => {
+                    code = true;
+                    continue;
+                }
+                _ => {
+                    span.insert(key.clone(), value.clone());
+                    continue;
+                }
+            };
+            marks.push(mark);
+        }
+        if !span.is_empty() {
+            marks.push(Mark::Span(span));
+        }
+        marks.sort_by_key(Mark::rank);
+        (marks, code)
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Mark::Link(_) => 0,
+            Mark::Span(_) => 1,
+            Mark::Underline => 2,
+            Mark::Script(_) => 3,
+            Mark::Strike => 4,
+            Mark::Bold => 5,
+            Mark::Italic => 6,
+        }
+    }
+
+    fn open(&self, syntax: Syntax) -> String {
+        match (self, syntax) {
+            (Mark::Link(_), Syntax::Markdown) => "[".to_string(),
+            (Mark::Link(url), Syntax::Html) => {
+                format!("<a href=\"{}\">", escape_html_attribute(url, '"'))
+            }
+            (Mark::Span(attributes), _) => span_tag(attributes),
+            (Mark::Underline, _) => "<u>".to_string(),
+            (Mark::Script(kind), _) => format!("<{}>", script_tag(kind)),
+            (Mark::Strike, Syntax::Markdown) => "~~".to_string(),
+            (Mark::Strike, Syntax::Html) => "<s>".to_string(),
+            (Mark::Bold, Syntax::Markdown) => "**".to_string(),
+            (Mark::Bold, Syntax::Html) => "<strong>".to_string(),
+            (Mark::Italic, Syntax::Markdown) => "*".to_string(),
+            (Mark::Italic, Syntax::Html) => "<em>".to_string(),
+        }
+    }
+
+    fn close(&self, syntax: Syntax) -> String {
+        match (self, syntax) {
+            (Mark::Link(url), Syntax::Markdown) => format!("]({})", link_destination(url)),
+            (Mark::Link(_), Syntax::Html) => "</a>".to_string(),
+            (Mark::Span(_), _) => "</span>".to_string(),
+            (Mark::Underline, _) => "</u>".to_string(),
+            (Mark::Script(kind), _) => format!("</{}>", script_tag(kind)),
+            (Mark::Strike, Syntax::Html) => "</s>".to_string(),
+            (Mark::Bold, Syntax::Html) => "</strong>".to_string(),
+            (Mark::Italic, Syntax::Html) => "</em>".to_string(),
+            (mark, Syntax::Markdown) => mark.open(syntax),
+        }
+    }
+}
+
+fn script_tag(kind: &str) -> &'static str {
+    if kind == "sub" {
+        "sub"
+    } else {
+        "sup"
+    }
+}
+
+/// `<span>` carrying color and background as CSS, and any other format as
+/// JSON.
+fn span_tag(attributes: &Attributes) -> String {
+    let mut styles = Vec::new();
+    let mut other = Attributes::new();
+    for (key, value) in attributes {
+        let css_value = value.as_str().filter(|value| !value.contains([';', ':']));
+        match (key.as_str(), css_value) {
+            ("color", Some(color)) => styles.push(format!("color: {color}")),
+            ("background", Some(color)) => styles.push(format!("background-color: {color}")),
+            _ => {
+                other.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    let mut tag = "<span".to_string();
+    if !styles.is_empty() {
+        let style = escape_html_attribute(&styles.join("; "), '"');
+        tag.push_str(&format!(" style=\"{style}\""));
+    }
+    if !other.is_empty() {
+        let json = serde_json::Value::Object(other).to_string();
+        tag.push_str(&format!(
+            " data-format='{}'",
+            escape_html_attribute(&json, '\'')
+        ));
+    }
+    tag.push('>');
+    tag
+}
+
+fn render_inline_segments(segments: &[InlineSegment], syntax: Syntax) -> String {
+    let mut out = String::new();
+    let mut open: Vec<Mark> = Vec::new();
+
+    for segment in segments {
+        let image = segment
+            .image_source()
+            .filter(|_| syntax == Syntax::Markdown);
+        let mut attributes = segment.attributes.clone();
+        // The alt text of a markdown image is part of its syntax.
+        let alt = match image.and(attributes.get("alt")).cloned() {
+            Some(serde_json::Value::String(alt)) => {
+                attributes.remove("alt");
+                alt
+            }
+            _ => String::new(),
+        };
+        let (wanted, code) = Mark::of(&attributes);
+        let keep = open
+            .iter()
+            .zip(&wanted)
+            .take_while(|(open, wanted)| open == wanted)
+            .count();
+
+        while open.len() > keep {
+            if let Some(mark) = open.pop() {
+                out.push_str(&mark.close(syntax));
+            }
+        }
+        for mark in &wanted[keep..] {
+            out.push_str(&mark.open(syntax));
+            open.push(mark.clone());
+        }
+
+        let embed = match (&segment.embed, image) {
+            (_, Some(source)) => Some(format!(
+                "![{}]({})",
+                escape_inline(&alt),
+                link_destination(source)
+            )),
+            (Some(embed), None) => {
+                let json = serde_json::Value::Object(embed.clone()).to_string();
+                Some(format!(
+                    "<span data-embed='{}'></span>",
+                    escape_html_attribute(&json, '\'')
+                ))
+            }
+            (None, None) => None,
+        };
+        match (embed, code, syntax) {
+            (Some(embed), true, _) => out.push_str(&format!("<code>{embed}</code>")),
+            (Some(embed), false, _) => out.push_str(&embed),
+            (None, true, Syntax::Markdown) => out.push_str(&code_span(&segment.text)),
+            (None, true, Syntax::Html) => {
+                out.push_str(&format!("<code>{}</code>", escape_inline(&segment.text)))
+            }
+            (None, false, _) => out.push_str(&escape_inline(&segment.text)),
+        }
+    }
+    while let Some(mark) = open.pop() {
+        out.push_str(&mark.close(syntax));
+    }
+
+    if let Some(first) = out.chars().next().filter(|ch| *ch == ' ' || *ch == '\t') {
+        out.replace_range(..1, if first == ' ' { "&#32;" } else { "&#9;" });
+    }
+    if let Some(last) = out.chars().last().filter(|ch| *ch == ' ' || *ch == '\t') {
+        let entity = if last == ' ' { "&#32;" } else { "&#9;" };
+        out.replace_range(out.len() - 1.., entity);
+    }
+    if starts_block(&out) {
+        let digits = out.chars().take_while(char::is_ascii_digit).count();
+        out.insert(digits, '\\');
+    }
+    out
+}
+
+fn code_span(code: &str) -> String {
+    let runs: Vec<usize> = code
+        .split(|ch| ch != '`')
+        .map(str::len)
+        .filter(|run| *run > 0)
+        .collect();
+    let fence_length = (1..).find(|length| !runs.contains(length)).unwrap_or(1);
+    let fence = "`".repeat(fence_length);
+    // One space is stripped from each end of a code span that has both.
+    let padded = code.starts_with('`')
+        || code.ends_with('`')
+        || (code.starts_with(' ') && code.ends_with(' ') && code.trim_start() != "");
+    if padded {
+        format!("{fence} {code} {fence}")
+    } else {
+        format!("{fence}{code}{fence}")
+    }
+}
+
+fn link_destination(url: &str) -> String {
+    let chars: Vec<char> = url.chars().collect();
+    let mut escaped = String::with_capacity(url.len());
+    for (index, &ch) in chars.iter().enumerate() {
+        if "\\<>()|".contains(ch) || (ch == '&' && is_entity(&chars[index + 1..])) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    if escaped.is_empty() || escaped.contains(char::is_whitespace) {
+        format!("<{escaped}>")
+    } else {
+        escaped
+    }
+}
+
+/// Backslash-escapes the characters that would otherwise start markup. A
+/// `|` is escaped too, so no two lines of text can read as a table.
+fn escape_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+
+    for (index, &ch) in chars.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|previous| chars[previous]);
+        let next = chars.get(index + 1).copied();
+        let escape = match ch {
+            '\\' | '`' | '*' | '[' | ']' | '~' | '|' => true,
+            // Underscores inside words never delimit emphasis.
+            '_' => {
+                !(previous.is_some_and(char::is_alphanumeric)
+                    && next.is_some_and(char::is_alphanumeric))
+            }
+            '<' => next.is_some_and(|next| next.is_ascii_alphabetic() || "/!?".contains(next)),
+            '&' => is_entity(&chars[index + 1..]),
+            _ => false,
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(ch);
+    }
+
+    out
+}
+
+/// Whether `rest`, following an `&`, completes a character reference.
+fn is_entity(rest: &[char]) -> bool {
+    let name_length = rest
+        .iter()
+        .take_while(|ch| ch.is_ascii_alphanumeric() || **ch == '#')
+        .count();
+    name_length > 0 && rest.get(name_length) == Some(&';')
+}
+
+/// `value` for an HTML attribute quoted with `quote`. A `|` is written as
+/// an entity so it cannot end a table cell.
+fn escape_html_attribute(value: &str, quote: char) -> String {
+    let escaped = value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('|', "&#124;");
+    match quote {
+        '"' => escaped.replace('"', "&quot;"),
+        _ => escaped.replace('\'', "&#39;"),
+    }
+}
+
+/// An inline HTML tag, as written by `quill_delta_to_markdown`.
+struct HtmlTag {
+    name: String,
+    closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl HtmlTag {
+    fn parse(html: &str) -> Option<Self> {
+        let inner = html.strip_prefix('<')?.strip_suffix('>')?;
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name_end = inner
+            .find(|ch: char| !ch.is_ascii_alphanumeric())
+            .unwrap_or(inner.len());
+        let name = inner[..name_end].to_ascii_lowercase();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut attributes = Vec::new();
+        let mut rest = inner[name_end..].trim_start();
+        while !rest.is_empty() {
+            let key_end = rest
+                .find(|ch: char| ch.is_whitespace() || ch == '=')
+                .unwrap_or(rest.len());
+            let key = rest[..key_end].to_ascii_lowercase();
+            rest = rest[key_end..].trim_start();
+            let mut value = String::new();
+            if let Some(after) = rest.strip_prefix('=') {
+                let after = after.trim_start();
+                let (raw, remaining) = match after.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let body = &after[1..];
+                        let end = body.find(quote)?;
+                        (&body[..end], &body[end + 1..])
+                    }
+                    _ => {
+                        let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                        after.split_at(end)
+                    }
+                };
+                value = decode_entities(raw);
+                rest = remaining.trim_start();
+            }
+            if key.is_empty() || closing {
+                return None;
+            }
+            attributes.push((key, value));
+        }
+
+        Some(Self {
+            name,
+            closing,
+            attributes,
+        })
+    }
+
+    /// The embed a `data-embed` span written by `quill_delta_to_markdown`
+    /// stands for.
+    fn embed(&self) -> Option<serde_json::Value> {
+        match (self.name.as_str(), self.closing, self.attributes.as_slice()) {
+            ("span", false, [(key, json)]) if key == "data-embed" => {
+                json_object(json).map(serde_json::Value::Object)
+            }
+            _ => None,
+        }
+    }
+
+    /// Text and line formats the opening tag stands for, if it is one of the
+    /// tags `quill_delta_to_markdown` writes. Other HTML stays text.
+    fn formats(&self) -> Option<(Attributes, Attributes)> {
+        let mut inline = Attributes::new();
+        let mut line = Attributes::new();
+        let flag = match self.name.as_str() {
+            "strong" | "b" => Some(("bold", true.into())),
+            "em" | "i" => Some(("italic", true.into())),
+            "s" | "del" | "strike" => Some(("strike", true.into())),
+            "u" => Some(("underline", true.into())),
+            "code" => Some(("code", true.into())),
+            "sub" => Some(("script", "sub".into())),
+            "sup" => Some(("script", "super".into())),
+            "a" | "span" => None,
+            _ => return None,
+        };
+        if let Some((key, value)) = flag {
+            inline.insert(key.to_string(), value);
+        }
+
+        for (key, value) in &self.attributes {
+            match (self.name.as_str(), key.as_str()) {
+                ("a", "href") => {
+                    inline.insert("link".to_string(), value.clone().into());
+                }
+                ("span", "style") => {
+                    for declaration in value.split(';').filter(|d| !d.trim().is_empty()) {
+                        let (property, css_value) = declaration.split_once(':')?;
+                        let key = match property.trim() {
+                            "color" => "color",
+                            "background-color" => "background",
+                            _ => return None,
+                        };
+                        inline.insert(key.to_string(), css_value.trim().into());
+                    }
+                }
+                ("span", "data-format") => inline.extend(json_object(value)?),
+                ("span", "data-line-format") => line.extend(json_object(value)?),
+                _ => return None,
+            }
+        }
+
+        if self.name == "a" && !inline.contains_key("link") {
+            return None;
+        }
+        Some((inline, line))
+    }
+}
+
+fn json_object(json: &str) -> Option<Attributes> {
+    match serde_json::from_str(json).ok()? {
+        serde_json::Value::Object(object) => Some(object),
+        _ => None,
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = rest.find(';').and_then(|end| {
+            let name = &rest[1..end];
+            let ch = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ => name
+                    .strip_prefix("#x")
+                    .or_else(|| name.strip_prefix("#X"))
+                    .map(|hex| u32::from_str_radix(hex, 16))
+                    .or_else(|| name.strip_prefix('#').map(str::parse))
+                    .and_then(Result::ok)
+                    .and_then(char::from_u32),
+            };
+            ch.map(|ch| (ch, end + 1))
+        });
+        match decoded {
+            Some((ch, length)) => {
+                out.push(ch);
+                rest = &rest[length..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::{markdown_to_quill_delta, merged_segments, quill_delta_to_markdown};
+    use crate::save_load::{make_delta_text_op, quill_delta_to_lines};
+
+    /// The note's lines in a compact form: block format in brackets, then
+    /// the text with formatted runs as `<attributes:text>`.
+    fn lines(markdown: &str) -> Vec<String> {
+        let delta = markdown_to_quill_delta(markdown);
+        quill_delta_to_lines(&delta)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|line| {
+                let block = describe(&line.block_attributes);
+                let text: String = line
+                    .segments
+                    .iter()
+                    .map(|segment| {
+                        let text = segment
+                            .embed
+                            .as_ref()
+                            .map_or(segment.text.clone(), |embed| {
+                                Value::Object(embed.clone()).to_string()
+                            });
+                        match describe(&segment.attributes) {
+                            attributes if attributes.is_empty() => text,
+                            attributes => format!("<{attributes}:{text}>"),
+                        }
+                    })
+                    .collect();
+                if block.is_empty() {
+                    text
+                } else {
+                    format!("[{block}] {text}")
+                }
+            })
+            .collect()
+    }
+
+    fn describe(attributes: &serde_json::Map<String, serde_json::Value>) -> String {
+        attributes
+            .iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::Bool(true) => key.clone(),
+                serde_json::Value::String(value) => format!("{key}={value}"),
+                value => format!("{key}={value}"),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Examples from the CommonMark 0.31.2 spec and the GFM extensions the
+    /// editor supports, with the note lines each should produce.
+    const CONFORMANCE: &[(&str, &str, &[&str])] = &[
+        // Emphasis and strong emphasis
+        ("example 350", "*foo bar*", &["<italic:foo bar>"]),
+        ("example 351", "a * foo bar*", &["a * foo bar*"]),
+        ("example 378", "**foo bar**", &["<bold:foo bar>"]),
+        ("example 379", "** foo bar**", &["** foo bar**"]),
+        ("example 383", "foo**bar**", &["foo<bold:bar>"]),
+        ("example 355", "foo*bar*", &["foo<italic:bar>"]),
+        ("example 357", "_foo bar_", &["<italic:foo bar>"]),
+        ("example 362", "foo_bar_", &["foo_bar_"]),
+        (
+            "example 414",
+            "*foo **bar** baz*",
+            &["<italic:foo ><bold,italic:bar><italic: baz>"],
+        ),
+        (
+            "example 418",
+            "*foo**bar**baz*",
+            &["<italic:foo><bold,italic:bar><italic:baz>"],
+        ),
+        (
+            "example 431",
+            "**foo *bar* baz**",
+            &["<bold:foo ><bold,italic:bar><bold: baz>"],
+        ),
+        ("example 413", "***foo bar***", &["<bold,italic:foo bar>"]),
+        ("example 443", "foo ***", &["foo ***"]),
+        ("example 452", "**foo*", &["*<italic:foo>"]),
+        ("strikethrough", "~~gone~~ kept", &["<strike:gone> kept"]),
+        // Backslash escapes and entities
+        ("example 12", "\\*not emphasized*", &["*not emphasized*"]),
+        ("example 14", "\\\\*emphasis*", &["\\<italic:emphasis>"]),
+        ("example 15", "foo\\\nbar", &["foo", "bar"]),
+        ("example 25", "&amp; &copy; &#35;", &["& © #"]),
+        // Code spans
+        ("example 328", "`foo`", &["<code:foo>"]),
+        ("example 329", "`` foo ` bar ``", &["<code:foo ` bar>"]),
+        ("example 338", "`foo\\`bar`", &["<code:foo\\>bar`"]),
+        ("example 342", "*foo`*`", &["*foo<code:*>"]),
+        // Links
+        (
+            "example 482",
+            "[link](/uri \"title\")",
+            &["<link=/uri:link>"],
+        ),
+        (
+            "example 594",
+            "<https://example.com>",
+            &["<link=https://example.com:https://example.com>"],
+        ),
+        (
+            "emphasis in link",
+            "[**bold** link](/u)",
+            &["<bold,link=/u:bold><link=/u: link>"],
+        ),
+        // ATX and setext headings
+        (
+            "example 62",
+            "# foo\n## foo\n###### foo",
+            &["[header=1] foo", "[header=2] foo", "[header=6] foo"],
+        ),
+        ("example 63", "####### foo", &["####### foo"]),
+        ("example 64", "#5 bolt", &["#5 bolt"]),
+        ("example 71", "### foo ###", &["[header=3] foo"]),
+        (
+            "example 80",
+            "Foo *bar*\n=========\n\nFoo *bar*\n---------",
+            &[
+                "[header=1] Foo <italic:bar>",
+                "",
+                "[header=2] Foo <italic:bar>",
+            ],
+        ),
+        // Lists and task items
+        (
+            "example 301",
+            "- foo\n- bar\n+ baz",
+            &[
+                "[list=bullet] foo",
+                "[list=bullet] bar",
+                "[list=bullet] baz",
+            ],
+        ),
+        (
+            "example 302",
+            "1. foo\n2. bar\n3) baz",
+            &[
+                "[list=ordered] foo",
+                "[list=ordered] bar",
+                "[list=ordered] baz",
+            ],
+        ),
+        (
+            "example 304",
+            "The number of windows in my house is\n14.  The number of doors is 6.",
+            &[
+                "The number of windows in my house is",
+                "14.  The number of doors is 6.",
+            ],
+        ),
+        (
+            "nested lists",
+            "- a\n  - b\n    1. c",
+            &[
+                "[list=bullet] a",
+                "[indent=1,list=bullet] b",
+                "[indent=2,list=ordered] c",
+            ],
+        ),
+        (
+            "task list items",
+            "- [ ] todo\n- [x] done",
+            &["[list=unchecked] todo", "[list=checked] done"],
+        ),
+        (
+            "lazy continuation",
+            "- item\nafter",
+            &["[list=bullet] item", "after"],
+        ),
+        (
+            "indented continuation",
+            "- item\n  more",
+            &["[list=bullet] item", "[list=bullet] more"],
+        ),
+        // Block quotes
+        (
+            "example 228",
+            "> # Foo\n> bar\n> baz",
+            &["[header=1] Foo", "[blockquote] bar", "[blockquote] baz"],
+        ),
+        ("example 233", "> bar\nbaz", &["[blockquote] bar", "baz"]),
+        (
+            "blank quote line",
+            "> a\n>\n> b",
+            &["[blockquote] a", "[blockquote] ", "[blockquote] b"],
+        ),
+        // Code blocks
+        (
+            "example 119",
+            "```\n<\n >\n```",
+            &["[code-block] <", "[code-block]  >"],
+        ),
+        (
+            "example 107",
+            "    a simple\n      indented code block",
+            &[
+                "[code-block] a simple",
+                "[code-block]   indented code block",
+            ],
+        ),
+        (
+            "fence in paragraph",
+            "text\n```\ncode\n```",
+            &["text", "[code-block] code"],
+        ),
+        (
+            "example 143",
+            "```ruby\ndef foo(x)\n  return 3\nend\n```",
+            &[
+                "[code-block=ruby] def foo(x)",
+                "[code-block=ruby]   return 3",
+                "[code-block=ruby] end",
+            ],
+        ),
+        (
+            "example 146",
+            "~~~ aa ``` ~~~\nfoo\n~~~",
+            &["[code-block=aa ``` ~~~] foo"],
+        ),
+        (
+            "example 128",
+            "````\naaa\n```\n``````",
+            &["[code-block] aaa", "[code-block] ```"],
+        ),
+        // Thematic breaks
+        (
+            "example 43",
+            "***\n---\n___",
+            &[
+                r#"{"divider":true}"#,
+                r#"{"divider":true}"#,
+                r#"{"divider":true}"#,
+            ],
+        ),
+        (
+            "example 57",
+            "- foo\n***\n- bar",
+            &[
+                "[list=bullet] foo",
+                r#"{"divider":true}"#,
+                "[list=bullet] bar",
+            ],
+        ),
+        ("example 59", "Foo\n---\nbar", &["[header=2] Foo", "bar"]),
+        // Tables
+        (
+            "example 198",
+            "| foo | bar |\n| --- | --- |\n| baz | bim |",
+            &[
+                "[table=row-1] foo",
+                "[table=row-1] bar",
+                "[table=row-2] baz",
+                "[table=row-2] bim",
+            ],
+        ),
+        (
+            "example 199",
+            "| abc | defghi |\n:-: | -----------:\nbar | baz",
+            &[
+                "[align=center,table=row-1] abc",
+                "[align=right,table=row-1] defghi",
+                "[align=center,table=row-2] bar",
+                "[align=right,table=row-2] baz",
+            ],
+        ),
+        (
+            "example 200",
+            "| f\\|oo  |\n| ------ |\n| b `\\|` az |\n| b **\\|** im |",
+            &[
+                "[table=row-1] f|oo",
+                "[table=row-2] b <code:|> az",
+                "[table=row-3] b <bold:|> im",
+            ],
+        ),
+        (
+            "example 204",
+            "| abc | def |\n| --- | --- |\n| bar |\n| bar | baz | boo |",
+            &[
+                "[table=row-1] abc",
+                "[table=row-1] def",
+                "[table=row-2] bar",
+                "[table=row-2] ",
+                "[table=row-3] bar",
+                "[table=row-3] baz",
+            ],
+        ),
+        (
+            "blank lines around a table",
+            "a\n\n| x |\n| - |\n\n\nb",
+            &["a", "[table=row-1] x", "", "b"],
+        ),
+        (
+            "paragraph right before a table",
+            "a\n| x | y |\n| - | - |",
+            &["a", "[table=row-1] x", "[table=row-1] y"],
+        ),
+        (
+            "tables a blank line apart",
+            "| x |\n| - |\n\n| y |\n| - |",
+            &["[table=row-1] x", "", "[table=row-2] y"],
+        ),
+        (
+            "indented code in list",
+            "- a\n\n      code",
+            &["[list=bullet] a", "", "[code-block] code"],
+        ),
+        // Paragraphs and blank lines
+        (
+            "example 219",
+            "aaa\nbbb\n\nccc\nddd",
+            &["aaa", "bbb", "", "ccc", "ddd"],
+        ),
+        ("example 220", "aaa\n\n\nbbb", &["aaa", "", "", "bbb"]),
+        (
+            "example 222",
+            "aaa\n             bbb\n                                       ccc",
+            &["aaa", "bbb", "ccc"],
+        ),
+    ];
+
+    #[test]
+    fn follows_the_commonmark_spec() {
+        let failures: Vec<String> = CONFORMANCE
+            .iter()
+            .filter_map(|(name, markdown, expected)| {
+                let actual = lines(markdown);
+                (actual != *expected).then(|| {
+                    format!("{name}: {markdown:?}\n  expected {expected:?}\n  got      {actual:?}")
+                })
+            })
+            .collect();
+
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn app_markdown_round_trips() {
+        for markdown in [
+            "# Title\nBold **text** and *italic*\n- [x] Done item\n- [ ] Todo item",
+            "a line\nanother line\n\n> quoted\n>\n> more\n\nafter",
+            "1. one\n2. two\n   - nested\n\ntext with \\*stars\\* and snake_case_name",
+            "**bold *both*** and [**a** link](https://example.com/a_b)",
+            "```\nlet x = 1;\n\nlet y = 2;\n```\n\\# not a header\n1\\. not a list",
+            "&#32;   leading spaces and trailing&#32;",
+            "`code with `` ticks` and \\<b> & \\&amp;",
+        ] {
+            let delta = markdown_to_quill_delta(markdown);
+            assert_eq!(quill_delta_to_markdown(&delta), markdown, "{delta}");
+        }
+    }
+
+    #[test]
+    fn escapes_text_that_would_read_as_markup() {
+        for text in [
+            "**not bold**",
+            "# not a header",
+            "- not a list",
+            "2024. a year",
+            "> not quoted",
+            "===",
+            "    not code",
+            "a [bracket](link) <b>tag</b> &amp; \\ ` ~",
+            "trailing space ",
+            "under_score _word_",
+        ] {
+            let delta =
+                serde_json::json!({ "ops": [{ "insert": format!("{text}\n") }] }).to_string();
+            let markdown = quill_delta_to_markdown(&delta);
+            assert_eq!(lines(&markdown), vec![text.to_string()], "{markdown}");
+        }
+    }
+
+    #[test]
+    fn converts_rich_text_blocks_to_markdown() {
+        let delta = r#"{"ops":[{"insert":"Title"},{"insert":"\n","attributes":{"header":1}},{"insert":"Bold "},{"insert":"text","attributes":{"bold":true}},{"insert":" and "},{"insert":"italic","attributes":{"italic":true}},{"insert":"\n"},{"insert":"Done item"},{"insert":"\n","attributes":{"list":"checked"}},{"insert":"Todo item"},{"insert":"\n","attributes":{"list":"unchecked"}}]}"#;
+
+        let markdown = quill_delta_to_markdown(delta);
+
+        assert_eq!(
+            markdown,
+            "# Title\nBold **text** and *italic*\n- [x] Done item\n- [ ] Todo item"
+        );
+    }
+
+    #[test]
+    fn converts_code_blocks_to_fenced_markdown() {
+        let delta = r#"{"ops":[{"insert":"let x = 1;"},{"insert":"\n","attributes":{"code-block":true}},{"insert":"let y = 2;"},{"insert":"\n","attributes":{"code-block":true}}]}"#;
+
+        let markdown = quill_delta_to_markdown(delta);
+
+        assert_eq!(markdown, "```\nlet x = 1;\nlet y = 2;\n```");
+    }
+
+    #[test]
+    fn keeps_code_block_languages() {
+        let delta = json!({ "ops": [
+            { "insert": "fn main() {}" },
+            { "insert": "\n", "attributes": { "code-block": "rust" } },
+            { "insert": "```" },
+            { "insert": "\n", "attributes": { "code-block": "rust" } },
+            { "insert": "ls" },
+            { "insert": "\n", "attributes": { "code-block": "sh" } },
+            { "insert": "x" },
+            { "insert": "\n", "attributes": { "code-block": "a`b" } },
+        ]});
+
+        assert_eq!(
+            quill_delta_to_markdown(&delta.to_string()),
+            "````rust\nfn main() {}\n```\n````\n```sh\nls\n```\n~~~a`b\nx\n~~~"
+        );
+    }
+
+    #[test]
+    fn writes_tables_and_dividers() {
+        let cell = |text: &str, format: Value| {
+            [
+                make_delta_text_op(text.into(), Default::default()),
+                make_delta_text_op("\n".into(), format.as_object().cloned().unwrap_or_default()),
+            ]
+        };
+        let mut ops = cell("Intro", json!(null)).to_vec();
+        ops.push(json!({ "insert": { "divider": true } }));
+        ops.extend(cell("Item", json!({ "table": "row-1" })));
+        ops.extend(cell("Cost", json!({ "table": "row-1", "align": "right" })));
+        ops.extend(cell("a|b", json!({ "table": "row-2", "align": "center" })));
+        ops.extend(cell("3", json!({ "table": "row-2", "align": "right" })));
+        ops.extend(cell("Total", json!(null)));
+        ops.push(json!({ "insert": { "divider": true } }));
+        let delta = json!({ "ops": ops }).to_string();
+
+        let markdown = quill_delta_to_markdown(&delta);
+
+        assert_eq!(
+            markdown,
+            "Intro\n***\n\n| Item | Cost |\n| --- | ---: |\n| <span data-line-format='{\"align\":\"center\"}'></span>a\\|b | 3 |\n\nTotal\n***"
+        );
+        assert_eq!(markdown_to_quill_delta(&markdown), delta);
+    }
+
+    #[test]
+    fn text_with_pipes_never_reads_as_a_table() {
+        for text in [["a | b", "--- | ---"], ["| a | b |", "| - | - |"]] {
+            let ops: Vec<Value> = text
+                .iter()
+                .map(|line| json!({ "insert": format!("{line}\n") }))
+                .collect();
+            let delta = json!({ "ops": ops }).to_string();
+
+            let markdown = quill_delta_to_markdown(&delta);
+
+            assert_eq!(lines(&markdown), text, "{markdown}");
+        }
+    }
+
+    #[test]
+    fn text_next_to_a_table_stays_out_of_it() {
+        for markdown in [
+            "a\n| x | y |\n| - | - |",
+            "| x |\n| - |\n\nb",
+            "a\n\n| x |\n| - |\n\n| y |\n| - |\nb",
+            "a | b\n\n| x |\n| - |",
+        ] {
+            let written = quill_delta_to_markdown(&markdown_to_quill_delta(markdown));
+            assert_eq!(lines(&written), lines(markdown), "{written}");
+        }
+    }
+
+    /// Every row of a markdown table has a cell for each column, so a row
+    /// the note left short gets empty cells.
+    #[test]
+    fn short_table_rows_are_padded() {
+        let ops = json!([
+            { "insert": "a" },
+            { "insert": "\n", "attributes": { "table": "row-1" } },
+            { "insert": "b" },
+            { "insert": "\n", "attributes": { "table": "row-2" } },
+            { "insert": "c" },
+            { "insert": "\n", "attributes": { "table": "row-2" } },
+        ]);
+        let markdown = quill_delta_to_markdown(&json!({ "ops": ops }).to_string());
+
+        assert_eq!(markdown, "| a |  |\n| --- | --- |\n| b | c |");
+        assert_eq!(
+            lines(&markdown),
+            [
+                "[table=row-1] a",
+                "[table=row-1] ",
+                "[table=row-2] b",
+                "[table=row-2] c"
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_original_contents_when_not_a_delta() {
+        let raw = "# already markdown";
+        assert_eq!(quill_delta_to_markdown(raw), raw);
+    }
+
+    #[test]
+    fn images_are_embeds() {
+        let markdown =
+            "see ![a chart](attachments/chart.png) and [![](logo.svg)](https://example.com)";
+        let delta: Value = serde_json::from_str(&markdown_to_quill_delta(markdown)).unwrap();
+        assert_eq!(
+            delta["ops"][1],
+            json!({ "insert": { "image": "attachments/chart.png" }, "attributes": { "alt": "a chart" } })
+        );
+        assert_eq!(
+            delta["ops"][3],
+            json!({ "insert": { "image": "logo.svg" }, "attributes": { "link": "https://example.com" } })
+        );
+        assert_eq!(quill_delta_to_markdown(&delta.to_string()), markdown);
+
+        let video = json!({ "ops": [{ "insert": { "video": "https://example.com/v" } }, { "insert": "\n" }] });
+        assert_eq!(
+            quill_delta_to_markdown(&video.to_string()),
+            "<span data-embed='{\"video\":\"https://example.com/v\"}'></span>"
+        );
+    }
+
+    #[test]
+    fn writes_formats_without_markdown_syntax_as_html() {
+        let delta = json!({ "ops": [
+            { "insert": "under", "attributes": { "underline": true } },
+            { "insert": " " },
+            { "insert": "red", "attributes": { "color": "#e60000", "background": "#ffff00" } },
+            { "insert": " H" },
+            { "insert": "2", "attributes": { "script": "sub" } },
+            { "insert": "O " },
+            { "insert": "serif", "attributes": { "font": "serif" } },
+            { "insert": "\n", "attributes": { "indent": 1, "align": "center" } },
+        ] })
+        .to_string();
+
+        let markdown = quill_delta_to_markdown(&delta);
+
+        assert_eq!(
+            markdown,
+            "<span data-line-format='{\"align\":\"center\",\"indent\":1}'></span>\
+             <u>under</u> <span style=\"background-color: #ffff00; color: #e60000\">red</span> \
+             H<sub>2</sub>O <span data-format='{\"font\":\"serif\"}'>serif</span>"
+        );
+        assert_eq!(
+            comparable_lines(&markdown_to_quill_delta(&markdown)),
+            comparable_lines(&delta)
+        );
+    }
+
+    #[test]
+    fn falls_back_to_html_tags_when_delimiters_cannot_express_a_line() {
+        let delta = json!({ "ops": [
+            { "insert": "bold ", "attributes": { "bold": true } },
+            { "insert": "text\n" },
+        ] })
+        .to_string();
+
+        assert_eq!(
+            quill_delta_to_markdown(&delta),
+            "<strong>bold </strong>text"
+        );
+    }
+
+    #[test]
+    fn restarted_ordered_lists_switch_delimiters() {
+        let ordered = json!({ "list": "ordered" });
+        let delta = json!({ "ops": [
+            { "insert": "a" }, { "insert": "\n", "attributes": ordered },
+            { "insert": "b" }, { "insert": "\n", "attributes": ordered },
+            { "insert": "\n" },
+            { "insert": "c" }, { "insert": "\n", "attributes": ordered },
+        ] })
+        .to_string();
+
+        let markdown = quill_delta_to_markdown(&delta);
+
+        assert_eq!(markdown, "1. a\n2. b\n\n1) c");
+        assert_eq!(
+            lines(&markdown),
+            [
+                "[list=ordered] a",
+                "[list=ordered] b",
+                "",
+                "[list=ordered] c"
+            ]
+        );
+    }
+
+    /// Small deterministic generator, so a failing document can be rebuilt
+    /// from its seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn chance(&mut self, percent: usize) -> bool {
+            self.below(100) < percent
+        }
+
+        fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())].clone()
+        }
+    }
+
+    const TEXT_PIECES: &[&str] = &[
+        "a",
+        "word",
+        "snake_case",
+        " ",
+        "  ",
+        "\t",
+        "*",
+        "**",
+        "_",
+        "__",
+        "`",
+        "``",
+        "#",
+        "# ",
+        "- ",
+        "+ ",
+        "1. ",
+        "2)",
+        "-",
+        "=",
+        "---",
+        "[",
+        "]",
+        "[x] ",
+        "(",
+        ")",
+        "!",
+        "<",
+        "<b>",
+        "</span>",
+        ">",
+        "&",
+        "&amp;",
+        "&#32;",
+        "\\",
+        "~",
+        "~~",
+        "|",
+        "'",
+        "\"",
+        "é",
+        "{}",
+    ];
+
+    fn random_line_format(rng: &mut Rng) -> Value {
+        let mut format = match rng.below(8) {
+            0 => json!({ "header": rng.below(6) + 1 }),
+            1 | 2 => {
+                let kind = rng.pick(&["bullet", "ordered", "checked", "unchecked"]);
+                json!({ "list": kind })
+            }
+            3 => json!({ "blockquote": true }),
+            4 => {
+                let language = rng.pick(&[
+                    json!(true),
+                    json!("rust"),
+                    json!("c++ {.numberLines}"),
+                    json!("a`b"),
+                    json!("\\&amp;"),
+                ]);
+                return json!({ "code-block": language });
+            }
+            _ => json!({}),
+        };
+        let object = format.as_object_mut().unwrap();
+        if (object.contains_key("list") && rng.chance(40)) || rng.chance(10) {
+            object.insert("indent".into(), (rng.below(3) + 1).into());
+        }
+        if rng.chance(10) {
+            object.insert(
+                "align".into(),
+                rng.pick(&["center", "right", "justify"]).into(),
+            );
+        }
+        format
+    }
+
+    fn random_text_format(rng: &mut Rng) -> Value {
+        let mut format = serde_json::Map::new();
+        for key in ["bold", "italic", "strike", "underline", "code"] {
+            if rng.chance(if key == "code" { 8 } else { 20 }) {
+                format.insert(key.into(), true.into());
+            }
+        }
+        let choices: [(&str, &[&str]); 6] = [
+            (
+                "link",
+                &[
+                    "https://example.com",
+                    "https://example.com/a_(b)?q=1&amp;",
+                    "my notes/<a>.md",
+                ],
+            ),
+            ("color", &["#e60000", "rgb(0, 138, 0)"]),
+            ("background", &["#ffff00"]),
+            ("script", &["sub", "super"]),
+            ("font", &["serif", "monospace"]),
+            ("size", &["small", "large"]),
+        ];
+        for (key, values) in choices {
+            if rng.chance(6) {
+                format.insert(key.into(), rng.pick(values).into());
+            }
+        }
+        Value::Object(format)
+    }
+
+    fn random_embed(rng: &mut Rng) -> Value {
+        let mut format = random_text_format(rng);
+        if rng.chance(50) {
+            let alt = rng.pick(TEXT_PIECES);
+            format
+                .as_object_mut()
+                .unwrap()
+                .insert("alt".into(), alt.into());
+        }
+        let embed = match rng.below(4) {
+            0 => json!({ "video": "https://example.com/v" }),
+            _ => json!({ "image": rng.pick(&[
+                "attachments/3f9a.png",
+                "data:image/png;base64,iVBORw0KGgo=",
+                "my pictures/a (1).png",
+            ]) }),
+        };
+        json!({ "insert": embed, "attributes": format })
+    }
+
+    /// Rows of cells, numbered on from `rows`; the first row's alignments
+    /// are the columns', which other cells may differ from.
+    fn random_table(rng: &mut Rng, rows: &mut usize) -> Vec<Value> {
+        let mut ops = Vec::new();
+        let columns = rng.below(3) + 1;
+        for _ in 0..rng.below(3) + 1 {
+            *rows += 1;
+            for _ in 0..columns {
+                let text: String = (0..rng.below(3)).map(|_| rng.pick(TEXT_PIECES)).collect();
+                if !text.is_empty() {
+                    let format = random_text_format(rng);
+                    ops.push(make_delta_text_op(
+                        text,
+                        format.as_object().unwrap().clone(),
+                    ));
+                }
+                let mut format = json!({ "table": format!("row-{rows}") });
+                if rng.chance(30) {
+                    format["align"] = rng.pick(&["center", "right", "justify"]).into();
+                }
+                ops.push(make_delta_text_op(
+                    "\n".into(),
+                    format.as_object().unwrap().clone(),
+                ));
+            }
+        }
+        ops
+    }
+
+    fn random_delta(rng: &mut Rng) -> String {
+        let mut ops = Vec::new();
+        let mut rows = 0;
+        let mut after_table = false;
+        for _ in 0..rng.below(8) + 1 {
+            if rng.chance(8) {
+                ops.push(json!({ "insert": { "divider": true } }));
+                after_table = false;
+                continue;
+            }
+            // Neighbouring tables would be one table.
+            if !after_table && rng.chance(8) {
+                ops.extend(random_table(rng, &mut rows));
+                after_table = true;
+                continue;
+            }
+            after_table = false;
+            let line_format = random_line_format(rng);
+            let code = line_format.get("code-block").is_some();
+            for _ in 0..rng.below(4) {
+                if !code && rng.chance(10) {
+                    ops.push(random_embed(rng));
+                    continue;
+                }
+                let text: String = (0..rng.below(3) + 1)
+                    .map(|_| rng.pick(TEXT_PIECES))
+                    .collect();
+                let format = if code {
+                    json!({})
+                } else {
+                    random_text_format(rng)
+                };
+                ops.push(make_delta_text_op(
+                    text,
+                    format.as_object().unwrap().clone(),
+                ));
+            }
+            ops.push(make_delta_text_op(
+                "\n".into(),
+                line_format.as_object().unwrap().clone(),
+            ));
+        }
+        json!({ "ops": ops }).to_string()
+    }
+
+    fn comparable_lines(delta: &str) -> Vec<(Vec<(String, String)>, String)> {
+        quill_delta_to_lines(delta)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|line| {
+                let segments = merged_segments(&line.segments)
+                    .into_iter()
+                    .map(|segment| {
+                        let content = match segment.embed {
+                            Some(embed) => Value::Object(embed).to_string(),
+                            None => segment.text,
+                        };
+                        (content, Value::Object(segment.attributes).to_string())
+                    })
+                    .collect();
+                (segments, Value::Object(line.block_attributes).to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn generated_documents_round_trip_through_markdown() {
+        for seed in 1..=1000u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let delta = random_delta(&mut rng);
+            let expected = comparable_lines(&delta);
+            if expected
+                .iter()
+                .all(|(segments, format)| segments.is_empty() && format == "{}")
+            {
+                continue;
+            }
+
+            let markdown = quill_delta_to_markdown(&delta);
+            let actual = comparable_lines(&markdown_to_quill_delta(&markdown));
+
+            assert_eq!(actual, expected, "seed {seed}\n{delta}\n---\n{markdown}");
+        }
+    }
+}
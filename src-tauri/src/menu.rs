@@ -36,9 +36,9 @@ pub enum MenuCommand {
     ToggleAnchor,
 }
 
-impl Into<MenuId> for MenuCommand {
-    fn into(self) -> MenuId {
-        MenuId(serde_json::to_string(&self).expect("Could not serialize MenuCommand enum"))
+impl From<MenuCommand> for MenuId {
+    fn from(command: MenuCommand) -> Self {
+        MenuId(serde_json::to_string(&command).expect("Could not serialize MenuCommand enum"))
     }
 }
 
@@ -321,7 +321,7 @@ pub fn handle_menu_event(app: &AppHandle, event: MenuEvent) {
                 log::error!("Error executing command: {:?} : {:#}", command, e);
             };
             if let MenuCommand::NewNote | MenuCommand::CloseNote | MenuCommand::Color(_) = command {
-                _ = app.emit("save_request", {});
+                _ = app.emit("save_request", ());
             };
         }
         Err(e) => {
@@ -0,0 +1,211 @@
+/// Result of merging two edits of the same text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MergeOutcome {
+    Merged(String),
+    /// Both sides changed the same lines differently.
+    Conflict,
+}
+
+/// A change of one side relative to the base: `base[start..end]` replaced
+/// by `lines`.
+#[derive(Debug)]
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+/// Line-wise three-way merge of `ours` and `theirs`, which were both edited
+/// from `base`. Changes to different lines are combined; changes to the same
+/// or adjacent lines merge only if both sides made the same change.
+pub fn merge_lines(base: &str, ours: &str, theirs: &str) -> MergeOutcome {
+    if ours == theirs || theirs == base {
+        return MergeOutcome::Merged(ours.to_string());
+    }
+    if ours == base {
+        return MergeOutcome::Merged(theirs.to_string());
+    }
+
+    let base_lines: Vec<&str> = base.split('\n').collect();
+    let our_hunks = hunks(&base_lines, &ours.split('\n').collect::<Vec<_>>());
+    let their_hunks = hunks(&base_lines, &theirs.split('\n').collect::<Vec<_>>());
+
+    let mut merged: Vec<&str> = Vec::new();
+    let mut position = 0;
+    let (mut next_ours, mut next_theirs) = (0, 0);
+
+    while next_ours < our_hunks.len() || next_theirs < their_hunks.len() {
+        // Start a cluster at whichever side's next change comes first, then
+        // pull in every change from either side that overlaps or touches it.
+        let starts_with_ours = match (our_hunks.get(next_ours), their_hunks.get(next_theirs)) {
+            (Some(our), Some(their)) => our.start <= their.start,
+            (Some(_), None) => true,
+            _ => false,
+        };
+        let first = if starts_with_ours {
+            &our_hunks[next_ours]
+        } else {
+            &their_hunks[next_theirs]
+        };
+        let cluster_start = first.start;
+        let mut cluster_end = first.end;
+        let (ours_from, theirs_from) = (next_ours, next_theirs);
+
+        loop {
+            if let Some(hunk) = our_hunks.get(next_ours).filter(|h| h.start <= cluster_end) {
+                cluster_end = cluster_end.max(hunk.end);
+                next_ours += 1;
+            } else if let Some(hunk) = their_hunks
+                .get(next_theirs)
+                .filter(|h| h.start <= cluster_end)
+            {
+                cluster_end = cluster_end.max(hunk.end);
+                next_theirs += 1;
+            } else {
+                break;
+            }
+        }
+
+        merged.extend_from_slice(&base_lines[position..cluster_start]);
+
+        let ours_changed = &our_hunks[ours_from..next_ours];
+        let theirs_changed = &their_hunks[theirs_from..next_theirs];
+        let our_version = apply(&base_lines, cluster_start, cluster_end, ours_changed);
+        if theirs_changed.is_empty() {
+            merged.extend(our_version);
+        } else {
+            let their_version = apply(&base_lines, cluster_start, cluster_end, theirs_changed);
+            if !ours_changed.is_empty() && our_version != their_version {
+                return MergeOutcome::Conflict;
+            }
+            merged.extend(their_version);
+        }
+
+        position = cluster_end;
+    }
+
+    merged.extend_from_slice(&base_lines[position..]);
+    MergeOutcome::Merged(merged.join("\n"))
+}
+
+/// One side's version of `base[start..end]`, given its changes in that range.
+fn apply<'a>(base: &[&'a str], start: usize, end: usize, changes: &[Hunk<'a>]) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    let mut position = start;
+    for hunk in changes {
+        lines.extend_from_slice(&base[position..hunk.start]);
+        lines.extend_from_slice(&hunk.lines);
+        position = hunk.end;
+    }
+    lines.extend_from_slice(&base[position..end]);
+    lines
+}
+
+/// The changes turning `base` into `other`, from a longest common subsequence
+/// of their lines.
+fn hunks<'a>(base: &[&str], other: &[&'a str]) -> Vec<Hunk<'a>> {
+    let (n, m) = (base.len(), other.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if base[i] == other[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut hunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut hunk_i, mut hunk_j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && base[i] == other[j] {
+            if hunk_i < i || hunk_j < j {
+                hunks.push(Hunk {
+                    start: hunk_i,
+                    end: i,
+                    lines: other[hunk_j..j].to_vec(),
+                });
+            }
+            i += 1;
+            j += 1;
+            hunk_i = i;
+            hunk_j = j;
+        } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            j += 1;
+        } else {
+            i += 1;
+        }
+    }
+    if hunk_i < n || hunk_j < m {
+        hunks.push(Hunk {
+            start: hunk_i,
+            end: n,
+            lines: other[hunk_j..m].to_vec(),
+        });
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{merge_lines, MergeOutcome};
+
+    fn merged(text: &str) -> MergeOutcome {
+        MergeOutcome::Merged(text.to_string())
+    }
+
+    #[test]
+    fn combines_edits_to_different_lines() {
+        let base = "# Groceries\n- milk\n- eggs\n- bread";
+        let ours = "# Groceries\n- oat milk\n- eggs\n- bread";
+        let theirs = "# Groceries\n- milk\n- eggs\n- bread\n- butter";
+
+        assert_eq!(
+            merge_lines(base, ours, theirs),
+            merged("# Groceries\n- oat milk\n- eggs\n- bread\n- butter")
+        );
+    }
+
+    #[test]
+    fn takes_the_only_changed_side() {
+        assert_eq!(merge_lines("a\nb", "a\nb", "a\nc"), merged("a\nc"));
+        assert_eq!(merge_lines("a\nb", "a\nc", "a\nb"), merged("a\nc"));
+    }
+
+    #[test]
+    fn accepts_identical_changes_from_both_sides() {
+        let base = "one\ntwo\nthree\nfour";
+        let both = "one\n2\nthree\nfour";
+        let theirs = "one\n2\nthree\nfour!";
+
+        assert_eq!(merge_lines(base, both, both), merged(both));
+        assert_eq!(merge_lines(base, both, theirs), merged(theirs));
+    }
+
+    #[test]
+    fn reports_conflicting_edits_of_the_same_line() {
+        assert_eq!(
+            merge_lines("title\nbody", "title\nours", "title\ntheirs"),
+            MergeOutcome::Conflict
+        );
+        assert_eq!(
+            merge_lines("a\nb\nc", "a\nb\nours", "a\nb\nc\ntheirs"),
+            MergeOutcome::Conflict
+        );
+    }
+
+    #[test]
+    fn merges_deletions_and_insertions_far_apart() {
+        let base = "1\n2\n3\n4\n5\n6";
+        let ours = "1\n3\n4\n5\n6";
+        let theirs = "1\n2\n3\n4\n5\n5.5\n6";
+
+        assert_eq!(
+            merge_lines(base, ours, theirs),
+            merged("1\n3\n4\n5\n5.5\n6")
+        );
+    }
+}
@@ -0,0 +1,329 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use chrono::Local;
+use serde_json::{Map, Value};
+use tauri_plugin_log::log;
+
+use crate::atomic_file::write_atomically;
+use crate::note_store::NoteStore;
+use crate::save_load::{make_default_record, storage_key_to_note_id, Note};
+
+/// Version of the on-disk note store this build reads and writes.
+///
+/// 1. `save_data` holds flat notes keyed `sticky_<id>` (no status or timestamps).
+/// 2. `save_data` holds note records with status and timestamps.
+/// 3. One JSON file per note under `note_records/`.
+pub const CURRENT_SCHEMA_VERSION: u32 = 3;
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+const LEGACY_DATA_KEY: &str = "data";
+
+/// Where the note store lives on disk.
+pub struct StoreLayout {
+    /// The `save_data` store file; holds the schema version, and the notes
+    /// themselves up to version 2.
+    pub legacy_store: PathBuf,
+    pub records_dir: PathBuf,
+    pub backup_dir: PathBuf,
+}
+
+struct Migration {
+    to_version: u32,
+    description: &'static str,
+    run: fn(&StoreLayout) -> anyhow::Result<()>,
+}
+
+/// Every upgrade step, oldest first. Each one brings the store from
+/// `to_version - 1` to `to_version`.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        to_version: 2,
+        description: "turn flat notes into note records",
+        run: records_from_flat_notes,
+    },
+    Migration {
+        to_version: 3,
+        description: "move note records into one file per note",
+        run: split_into_record_files,
+    },
+];
+
+/// Brings the store at `layout` up to [`CURRENT_SCHEMA_VERSION`], backing it
+/// up first if any step has to run. Returns the version found on disk.
+pub fn migrate(layout: &StoreLayout) -> anyhow::Result<u32> {
+    let mut legacy_store = read_legacy_store(&layout.legacy_store)?;
+    let recorded_version = legacy_store.get(SCHEMA_VERSION_KEY).and_then(Value::as_u64);
+    let found_version = match recorded_version {
+        Some(version) => version as u32,
+        None => infer_version(&legacy_store),
+    };
+
+    if found_version > CURRENT_SCHEMA_VERSION {
+        bail!(
+            "Note store has schema version {}, but this version of the app only supports up to {}",
+            found_version,
+            CURRENT_SCHEMA_VERSION
+        );
+    }
+
+    if found_version < CURRENT_SCHEMA_VERSION {
+        backup_before_migration(layout, found_version)?;
+    }
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| migration.to_version > found_version)
+    {
+        log::info!(
+            "Migrating note store to schema version {}: {}",
+            migration.to_version,
+            migration.description
+        );
+        (migration.run)(layout).with_context(|| {
+            format!(
+                "Failed to migrate note store to schema version {}",
+                migration.to_version
+            )
+        })?;
+        set_schema_version(layout, migration.to_version)?;
+    }
+
+    if recorded_version.is_none() && found_version == CURRENT_SCHEMA_VERSION {
+        legacy_store.insert(
+            SCHEMA_VERSION_KEY.to_string(),
+            CURRENT_SCHEMA_VERSION.into(),
+        );
+        write_legacy_store(&layout.legacy_store, &legacy_store)?;
+    }
+
+    Ok(found_version)
+}
+
+/// Stores written before the version key existed: notes still in `save_data`
+/// are version 1 or 2 depending on their shape, anything else is either the
+/// per-note layout or a fresh install.
+fn infer_version(legacy_store: &Map<String, Value>) -> u32 {
+    match legacy_store.get(LEGACY_DATA_KEY).and_then(Value::as_object) {
+        Some(data) if data.values().any(is_flat_note) => 1,
+        Some(_) => 2,
+        None => CURRENT_SCHEMA_VERSION,
+    }
+}
+
+fn is_flat_note(value: &Value) -> bool {
+    value.get("status").is_none() && value.get("created_at").is_none()
+}
+
+fn records_from_flat_notes(layout: &StoreLayout) -> anyhow::Result<()> {
+    let mut legacy_store = read_legacy_store(&layout.legacy_store)?;
+    let Some(data) = legacy_store
+        .get_mut(LEGACY_DATA_KEY)
+        .and_then(Value::as_object_mut)
+    else {
+        return Ok(());
+    };
+
+    for (key, value) in data.iter_mut().filter(|(_, value)| is_flat_note(value)) {
+        let note: Note = serde_json::from_value(value.clone())
+            .with_context(|| format!("Failed to read legacy note {}", key))?;
+        let mut record = make_default_record(storage_key_to_note_id(key));
+        record.note = note;
+        *value = serde_json::to_value(record)?;
+    }
+
+    write_legacy_store(&layout.legacy_store, &legacy_store)
+}
+
+fn split_into_record_files(layout: &StoreLayout) -> anyhow::Result<()> {
+    let mut legacy_store = read_legacy_store(&layout.legacy_store)?;
+    let Some(data) = legacy_store.remove(LEGACY_DATA_KEY) else {
+        return Ok(());
+    };
+
+    let data = data
+        .as_object()
+        .context("json key 'data' contained a non-object")?;
+    let imported = NoteStore::open(layout.records_dir.clone())?.import_legacy_records(data)?;
+    log::info!("Moved {} notes into {:?}", imported, layout.records_dir);
+
+    write_legacy_store(&layout.legacy_store, &legacy_store)
+}
+
+fn backup_before_migration(layout: &StoreLayout, from_version: u32) -> anyhow::Result<()> {
+    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+    let backup_path = layout
+        .backup_dir
+        .join(format!("{}_pre_migration_v{}", timestamp, from_version));
+    fs::create_dir_all(&backup_path).context("Failed to create pre-migration backup")?;
+
+    if let Some(file_name) = layout.legacy_store.file_name() {
+        if layout.legacy_store.exists() {
+            fs::copy(&layout.legacy_store, backup_path.join(file_name))
+                .context("Failed to create pre-migration backup")?;
+        }
+    }
+
+    if let Some(dir_name) = layout.records_dir.file_name() {
+        if layout.records_dir.exists() {
+            let records_backup = backup_path.join(dir_name);
+            fs::create_dir_all(&records_backup)?;
+            for entry in fs::read_dir(&layout.records_dir)? {
+                let path = entry?.path();
+                if let Some(file_name) = path.file_name().filter(|_| path.is_file()) {
+                    fs::copy(&path, records_backup.join(file_name))
+                        .context("Failed to create pre-migration backup")?;
+                }
+            }
+        }
+    }
+
+    log::info!("Created pre-migration backup: {:?}", backup_path);
+    Ok(())
+}
+
+fn set_schema_version(layout: &StoreLayout, version: u32) -> anyhow::Result<()> {
+    let mut legacy_store = read_legacy_store(&layout.legacy_store)?;
+    legacy_store.insert(SCHEMA_VERSION_KEY.to_string(), version.into());
+    write_legacy_store(&layout.legacy_store, &legacy_store)
+}
+
+/// The `save_data` file as written by the store plugin: a plain JSON object.
+fn read_legacy_store(path: &Path) -> anyhow::Result<Map<String, Value>> {
+    if !path.exists() {
+        return Ok(Map::new());
+    }
+
+    let raw = fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    match serde_json::from_slice(&raw)? {
+        Value::Object(map) => Ok(map),
+        _ => bail!("{:?} does not contain a JSON object", path),
+    }
+}
+
+fn write_legacy_store(path: &Path, legacy_store: &Map<String, Value>) -> anyhow::Result<()> {
+    write_atomically(path, serde_json::to_vec_pretty(legacy_store)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{migrate, read_legacy_store, StoreLayout, CURRENT_SCHEMA_VERSION};
+    use crate::note_store::NoteStore;
+    use crate::save_load::NoteStatus;
+
+    const V1_SAVE_DATA: &str = include_str!("../fixtures/schema/v1_save_data.json");
+    const V2_SAVE_DATA: &str = include_str!("../fixtures/schema/v2_save_data.json");
+    const V3_NOTE_RECORD: &str = include_str!("../fixtures/schema/v3_note_record.json");
+
+    fn temp_layout(name: &str) -> (PathBuf, StoreLayout) {
+        let dir = std::env::temp_dir().join(format!("migrations_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let layout = StoreLayout {
+            legacy_store: dir.join("save_data"),
+            records_dir: dir.join("note_records"),
+            backup_dir: dir.join("backups"),
+        };
+        (dir, layout)
+    }
+
+    fn recorded_version(layout: &StoreLayout) -> Option<u64> {
+        read_legacy_store(&layout.legacy_store).unwrap()["schema_version"].as_u64()
+    }
+
+    fn backup_count(layout: &StoreLayout) -> usize {
+        fs::read_dir(&layout.backup_dir)
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn migrates_flat_notes_from_version_1() {
+        let (dir, layout) = temp_layout("v1");
+        fs::write(&layout.legacy_store, V1_SAVE_DATA).unwrap();
+
+        assert_eq!(migrate(&layout).unwrap(), 1);
+
+        let store = NoteStore::open(layout.records_dir.clone()).unwrap();
+        let first = store.get("1700000000000").unwrap();
+        assert_eq!(first.status, NoteStatus::Open);
+        assert_eq!(first.note.color, "#bfe6bf");
+        assert!(first.note.always_on_top);
+        assert_eq!(first.note.zoom, 1.0);
+        assert_eq!(store.get("1700000000001").unwrap().note.color, "#f9e7a7");
+        assert!(read_legacy_store(&layout.legacy_store)
+            .unwrap()
+            .get("data")
+            .is_none());
+        assert_eq!(recorded_version(&layout), Some(3));
+        assert_eq!(backup_count(&layout), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn migrates_note_records_from_version_2() {
+        let (dir, layout) = temp_layout("v2");
+        fs::write(&layout.legacy_store, V2_SAVE_DATA).unwrap();
+
+        assert_eq!(migrate(&layout).unwrap(), 2);
+
+        let store = NoteStore::open(layout.records_dir.clone()).unwrap();
+        let open = store.get("1700000000000").unwrap();
+        assert_eq!(open.note.zoom, 1.25);
+        assert_eq!(open.created_at, "2025-01-10T09:00:00+00:00");
+        let archived = store.get("1700000000001").unwrap();
+        assert_eq!(archived.status, NoteStatus::Archived);
+        assert_eq!(
+            archived.archived_at.as_deref(),
+            Some("2025-01-11T09:00:00+00:00")
+        );
+        assert_eq!(recorded_version(&layout), Some(3));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn records_version_of_unversioned_per_note_store_without_backup() {
+        let (dir, layout) = temp_layout("v3");
+        fs::create_dir_all(&layout.records_dir).unwrap();
+        fs::write(
+            layout.records_dir.join("1700000000000.json"),
+            V3_NOTE_RECORD,
+        )
+        .unwrap();
+
+        assert_eq!(migrate(&layout).unwrap(), 3);
+        assert_eq!(migrate(&layout).unwrap(), 3);
+
+        let record = NoteStore::open(layout.records_dir.clone())
+            .unwrap()
+            .get("1700000000000")
+            .unwrap();
+        assert_eq!(record.anchor.unwrap().target_window_id, 4242);
+        assert_eq!(record.show_with_app.as_deref(), Some("Safari"));
+        assert_eq!(
+            recorded_version(&layout),
+            Some(u64::from(CURRENT_SCHEMA_VERSION))
+        );
+        assert_eq!(backup_count(&layout), 0);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn refuses_stores_from_newer_versions() {
+        let (dir, layout) = temp_layout("newer");
+        fs::write(&layout.legacy_store, r#"{"schema_version": 99}"#).unwrap();
+
+        assert!(migrate(&layout).is_err());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use tauri::{AppHandle, Emitter, Manager};
+use tauri_plugin_log::log;
+
+use crate::save_load::{mark_notes_missing, restore_markdown_files};
+
+/// This many notes vanishing from the notes folder in one go looks more like
+/// an unfinished sync or an unmounted folder than deliberate deletes, so the
+/// user is asked before they are marked missing.
+pub const MASS_DISAPPEARANCE_THRESHOLD: usize = 3;
+
+/// Whether `missing` notes disappearing out of `tracked` needs confirmation.
+/// Losing every note at once always does, unless there was only one.
+pub fn needs_confirmation(missing: usize, tracked: usize) -> bool {
+    missing >= MASS_DISAPPEARANCE_THRESHOLD || (missing > 1 && missing == tracked)
+}
+
+/// Notes whose files vanished in a mass disappearance the user has not
+/// answered yet. They are left untouched until then.
+#[derive(Default)]
+pub struct MissingNotesState {
+    awaiting_confirmation: Mutex<HashSet<String>>,
+}
+
+impl MissingNotesState {
+    pub fn is_awaiting_confirmation(&self, note_id: &str) -> bool {
+        self.awaiting_confirmation.lock().unwrap().contains(note_id)
+    }
+
+    /// Forgets a note again, e.g. because its file came back.
+    pub fn release(&self, note_id: &str) {
+        self.awaiting_confirmation.lock().unwrap().remove(note_id);
+    }
+
+    fn hold(&self, note_ids: &[String]) {
+        self.awaiting_confirmation
+            .lock()
+            .unwrap()
+            .extend(note_ids.iter().cloned());
+    }
+
+    /// Ids still awaiting confirmation among `note_ids`, removing them.
+    fn take(&self, note_ids: &[String]) -> Vec<String> {
+        let mut awaiting = self.awaiting_confirmation.lock().unwrap();
+        note_ids
+            .iter()
+            .filter(|note_id| awaiting.remove(note_id.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Asks whether the notes whose files disappeared should be marked missing,
+/// or their files written back from the app's copy.
+pub fn confirm_mass_disappearance(app: &AppHandle, note_ids: Vec<String>) {
+    app.state::<MissingNotesState>().hold(&note_ids);
+    log::warn!(
+        "{} note files disappeared at once, asking before marking them missing",
+        note_ids.len()
+    );
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let answer = rfd::AsyncMessageDialog::new()
+            .set_title("Notes disappeared from the notes folder")
+            .set_description(format!(
+                "The files of {} notes are no longer in the notes folder. This can happen while a sync is still running.\n\nMark these notes as missing? Choose No to write their files back from the app's copy.",
+                note_ids.len()
+            ))
+            .set_level(rfd::MessageLevel::Warning)
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show()
+            .await;
+
+        // Notes whose files reappeared in the meantime no longer need a decision.
+        let note_ids = app.state::<MissingNotesState>().take(&note_ids);
+        let result = if answer == rfd::MessageDialogResult::Yes {
+            mark_notes_missing(&app, &note_ids)
+        } else {
+            restore_markdown_files(&app, &note_ids)
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to handle disappeared notes: {:#}", e);
+        }
+        let _ = app.emit("notes_changed", ());
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{needs_confirmation, MissingNotesState};
+
+    #[test]
+    fn asks_only_for_mass_disappearances() {
+        assert!(!needs_confirmation(1, 10));
+        assert!(!needs_confirmation(2, 10));
+        assert!(needs_confirmation(3, 10));
+        assert!(needs_confirmation(2, 2));
+        assert!(!needs_confirmation(1, 1));
+    }
+
+    #[test]
+    fn releasing_a_note_drops_it_from_the_pending_decision() {
+        let state = MissingNotesState::default();
+        let ids = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        state.hold(&ids);
+        state.release("b");
+
+        assert!(!state.is_awaiting_confirmation("b"));
+        assert_eq!(state.take(&ids), vec!["a".to_string(), "c".to_string()]);
+        assert!(!state.is_awaiting_confirmation("a"));
+    }
+}
@@ -0,0 +1,496 @@
+use std::path::Path;
+
+use crate::{
+    markdown::{markdown_to_quill_delta, quill_delta_to_markdown},
+    save_load::{make_delta_text_op, quill_delta_to_lines, DeltaLine, InlineSegment},
+};
+
+/// A kind of text file the notes folder can hold notes in.
+pub struct NoteFormat {
+    /// File extension, without the dot.
+    pub extension: &'static str,
+    /// Whether the file starts with YAML frontmatter holding the note's id,
+    /// status and layout. Files without it are identified by their name.
+    pub frontmatter: bool,
+    /// Quill delta JSON from the file's body.
+    pub parse: fn(&str) -> String,
+    /// File body from Quill delta JSON.
+    pub serialize: fn(&str) -> String,
+}
+
+/// Every supported format. New notes use the first one.
+pub const NOTE_FORMATS: &[NoteFormat] = &[
+    NoteFormat {
+        extension: "md",
+        frontmatter: true,
+        parse: markdown_to_quill_delta,
+        serialize: quill_delta_to_markdown,
+    },
+    NoteFormat {
+        extension: "markdown",
+        frontmatter: true,
+        parse: markdown_to_quill_delta,
+        serialize: quill_delta_to_markdown,
+    },
+    NoteFormat {
+        extension: "txt",
+        frontmatter: false,
+        parse: text_to_quill_delta,
+        serialize: quill_delta_to_text,
+    },
+    NoteFormat {
+        extension: "org",
+        frontmatter: false,
+        parse: org_to_quill_delta,
+        serialize: quill_delta_to_org,
+    },
+];
+
+pub fn default_format() -> &'static NoteFormat {
+    &NOTE_FORMATS[0]
+}
+
+/// The format of a file, by its extension.
+pub fn format_for_path(path: &Path) -> Option<&'static NoteFormat> {
+    let extension = path.extension()?.to_str()?;
+    NOTE_FORMATS
+        .iter()
+        .find(|format| format.extension.eq_ignore_ascii_case(extension))
+}
+
+type Attributes = serde_json::Map<String, serde_json::Value>;
+
+fn delta_from_ops(ops: Vec<serde_json::Value>) -> String {
+    if ops.is_empty() {
+        return String::new();
+    }
+    serde_json::to_string(&serde_json::json!({ "ops": ops })).unwrap_or_default()
+}
+
+fn block(key: &str, value: impl Into<serde_json::Value>) -> Attributes {
+    let mut attributes = Attributes::new();
+    attributes.insert(key.to_string(), value.into());
+    attributes
+}
+
+/// Plain text: every line is a paragraph, nothing is formatted.
+fn text_to_quill_delta(text: &str) -> String {
+    let normalized = text.replace("\r\n", "\n");
+    if normalized.trim().is_empty() {
+        return String::new();
+    }
+
+    let ops = normalized
+        .split('\n')
+        .map(|line| make_delta_text_op(format!("{line}\n"), Attributes::new()))
+        .collect();
+    delta_from_ops(ops)
+}
+
+fn quill_delta_to_text(contents: &str) -> String {
+    let Some(lines) = quill_delta_to_lines(contents) else {
+        return contents.to_string();
+    };
+
+    lines
+        .iter()
+        .map(|line| {
+            line.segments
+                .iter()
+                .map(|segment| match segment.image_source() {
+                    Some(source) => source,
+                    None if segment.is_divider() => "---",
+                    None => &segment.text,
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Org mode: headings, lists, checkboxes, quote and source blocks, links and
+/// the `*bold*`, `/italic/`, `+strike+` and `~code~` markup.
+fn org_to_quill_delta(org: &str) -> String {
+    let normalized = org.replace("\r\n", "\n");
+    if normalized.trim().is_empty() {
+        return String::new();
+    }
+
+    let mut ops = Vec::new();
+    let mut open_block: Option<(&str, serde_json::Value)> = None;
+
+    for line in normalized.split('\n') {
+        let keyword = line.trim().to_ascii_lowercase();
+        if let Some(kind) = keyword.strip_prefix("#+begin_") {
+            if open_block.is_none() {
+                // The language follows `#+BEGIN_SRC`, as in `#+BEGIN_SRC rust`.
+                let language = kind
+                    .starts_with("src")
+                    .then(|| line.trim()["#+begin_src".len()..].trim())
+                    .filter(|language| !language.is_empty());
+                open_block = Some(if kind.starts_with("src") || kind.starts_with("example") {
+                    ("code-block", language.map_or(true.into(), Into::into))
+                } else {
+                    ("blockquote", true.into())
+                });
+                continue;
+            }
+        }
+        if keyword.starts_with("#+end_") && open_block.is_some() {
+            open_block = None;
+            continue;
+        }
+
+        if let Some((attribute, value)) = &open_block {
+            if *attribute == "code-block" {
+                ops.push(make_delta_text_op(line.to_string(), Attributes::new()));
+            } else {
+                push_org_inline(&mut ops, line.trim());
+            }
+            ops.push(make_delta_text_op(
+                "\n".to_string(),
+                block(attribute, value.clone()),
+            ));
+            continue;
+        }
+
+        // A line of five or more dashes is a horizontal rule.
+        if line.trim().len() >= 5 && line.trim().chars().all(|ch| ch == '-') {
+            ops.push(serde_json::json!({ "insert": { "divider": true } }));
+            continue;
+        }
+
+        let (text, attributes) = parse_org_line(line);
+        push_org_inline(&mut ops, text);
+        ops.push(make_delta_text_op("\n".to_string(), attributes));
+    }
+
+    delta_from_ops(ops)
+}
+
+fn parse_org_line(line: &str) -> (&str, Attributes) {
+    let stars = line.chars().take_while(|ch| *ch == '*').count();
+    if stars > 0 {
+        if let Some(title) = line[stars..].strip_prefix(' ') {
+            return (title.trim(), block("header", stars.min(6)));
+        }
+    }
+
+    let trimmed = line.trim_start();
+    let indent = (line.len() - trimmed.len()) / 2;
+    let ordered_marker = trimmed
+        .find(['.', ')'])
+        .filter(|&end| end > 0 && trimmed[..end].chars().all(|ch| ch.is_ascii_digit()))
+        .and_then(|end| trimmed[end + 1..].strip_prefix(' '));
+    let (kind, item) = if let Some(item) = ordered_marker {
+        ("ordered", item)
+    } else if let Some(item) = trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("+ "))
+    {
+        ("bullet", item)
+    } else {
+        return (line, Attributes::new());
+    };
+
+    let (kind, item) = if let Some(rest) = item.strip_prefix("[ ] ") {
+        ("unchecked", rest)
+    } else if let Some(rest) = item
+        .strip_prefix("[X] ")
+        .or_else(|| item.strip_prefix("[x] "))
+    {
+        ("checked", rest)
+    } else {
+        (kind, item)
+    };
+
+    let mut attributes = block("list", kind);
+    if indent > 0 {
+        attributes.insert("indent".to_string(), indent.into());
+    }
+    (item, attributes)
+}
+
+const ORG_MARKUP: [(char, &str); 5] = [
+    ('*', "bold"),
+    ('/', "italic"),
+    ('+', "strike"),
+    ('~', "code"),
+    ('=', "code"),
+];
+
+fn push_org_inline(ops: &mut Vec<serde_json::Value>, text: &str) {
+    let mut plain = String::new();
+    let mut rest = text;
+
+    while let Some(ch) = rest.chars().next() {
+        let at_word_start = plain
+            .chars()
+            .last()
+            .is_none_or(|previous| previous.is_whitespace() || "(\"'{".contains(previous));
+
+        if let Some((label, link, length)) = org_link(rest) {
+            flush_plain(ops, &mut plain);
+            match link.strip_prefix("file:").filter(|_| label == link) {
+                Some(image) => ops.push(serde_json::json!({ "insert": { "image": image } })),
+                None => ops.push(make_delta_text_op(label, block("link", link))),
+            }
+            rest = &rest[length..];
+            continue;
+        }
+
+        if let Some((attribute, inner)) = ORG_MARKUP
+            .iter()
+            .find(|(marker, _)| *marker == ch && at_word_start)
+            .and_then(|(marker, attribute)| Some((*attribute, org_emphasis(rest, *marker)?)))
+        {
+            flush_plain(ops, &mut plain);
+            ops.push(make_delta_text_op(
+                inner.to_string(),
+                block(attribute, true),
+            ));
+            rest = &rest[inner.len() + 2..];
+            continue;
+        }
+
+        plain.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+
+    flush_plain(ops, &mut plain);
+}
+
+fn flush_plain(ops: &mut Vec<serde_json::Value>, plain: &mut String) {
+    if !plain.is_empty() {
+        ops.push(make_delta_text_op(std::mem::take(plain), Attributes::new()));
+    }
+}
+
+/// `[[target][label]]` or `[[target]]` at the start of `text`, as label,
+/// target and the length of the markup.
+fn org_link(text: &str) -> Option<(String, String, usize)> {
+    let body = text.strip_prefix("[[")?;
+    let end = body.find("]]")?;
+    let link = &body[..end];
+    let (target, label) = match link.split_once("][") {
+        Some((target, label)) => (target, label),
+        None => (link, link),
+    };
+    if target.is_empty() || label.is_empty() {
+        return None;
+    }
+    Some((label.to_string(), target.to_string(), end + 4))
+}
+
+/// Text between `marker` at the start of `text` and the matching closing
+/// marker, if the markup is well formed.
+fn org_emphasis(text: &str, marker: char) -> Option<&str> {
+    let body = text.strip_prefix(marker)?;
+    if body.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    body.match_indices(marker).find_map(|(end, _)| {
+        let inner = &body[..end];
+        let after = body[end + 1..].chars().next();
+        let closes = !inner.is_empty()
+            && !inner.ends_with(char::is_whitespace)
+            && after.is_none_or(|next| next.is_whitespace() || "-.,:;!?')\"}".contains(next));
+        closes.then_some(inner)
+    })
+}
+
+fn quill_delta_to_org(contents: &str) -> String {
+    let Some(lines) = quill_delta_to_lines(contents) else {
+        return contents.to_string();
+    };
+
+    let mut rendered = Vec::new();
+    let mut open_block: Option<&str> = None;
+    let mut open_language: Option<&str> = None;
+    let mut ordered_counters: Vec<usize> = Vec::new();
+
+    for line in &lines {
+        let attributes = &line.block_attributes;
+        let block_kind = ["code-block", "blockquote"]
+            .into_iter()
+            .find(|key| attributes.get(*key).is_some_and(|value| value != false));
+        let language = attributes
+            .get("code-block")
+            .and_then(serde_json::Value::as_str);
+
+        if open_block != block_kind || open_language != language {
+            match open_block {
+                Some("code-block") => rendered.push("#+END_SRC".to_string()),
+                Some(_) => rendered.push("#+END_QUOTE".to_string()),
+                None => {}
+            }
+            match (block_kind, language) {
+                (Some("code-block"), Some(language)) => {
+                    rendered.push(format!("#+BEGIN_SRC {language}"))
+                }
+                (Some("code-block"), None) => rendered.push("#+BEGIN_SRC".to_string()),
+                (Some(_), _) => rendered.push("#+BEGIN_QUOTE".to_string()),
+                (None, _) => {}
+            }
+            open_block = block_kind;
+            open_language = language;
+        }
+
+        if block_kind == Some("code-block") {
+            rendered.push(line.segments.iter().map(|s| s.text.as_str()).collect());
+            continue;
+        }
+
+        let text = render_org_inline(line);
+        let list = attributes
+            .get("list")
+            .and_then(serde_json::Value::as_str)
+            .filter(|_| block_kind.is_none());
+        if list != Some("ordered") {
+            ordered_counters.clear();
+        }
+
+        if let Some(level) = attributes.get("header").and_then(serde_json::Value::as_u64) {
+            rendered.push(format!(
+                "{} {}",
+                "*".repeat(level.clamp(1, 6) as usize),
+                text
+            ));
+        } else if let Some(kind) = list {
+            let indent = attributes
+                .get("indent")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0) as usize;
+            let marker = match kind {
+                "ordered" => {
+                    ordered_counters.resize(indent + 1, 0);
+                    ordered_counters[indent] += 1;
+                    format!("{}.", ordered_counters[indent])
+                }
+                "checked" => "- [X]".to_string(),
+                "unchecked" => "- [ ]".to_string(),
+                _ => "-".to_string(),
+            };
+            rendered.push(format!("{}{marker} {text}", "  ".repeat(indent)));
+        } else {
+            rendered.push(text);
+        }
+    }
+
+    match open_block {
+        Some("code-block") => rendered.push("#+END_SRC".to_string()),
+        Some(_) => rendered.push("#+END_QUOTE".to_string()),
+        None => {}
+    }
+
+    rendered.join("\n")
+}
+
+fn render_org_inline(line: &DeltaLine) -> String {
+    line.segments.iter().map(render_org_segment).collect()
+}
+
+fn render_org_segment(segment: &InlineSegment) -> String {
+    if let Some(image) = segment.image_source() {
+        return format!("[[file:{image}]]");
+    }
+    if segment.is_divider() {
+        return "-----".to_string();
+    }
+    let is_set = |key: &str| {
+        segment
+            .attributes
+            .get(key)
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+    };
+
+    if segment.text.trim().is_empty() {
+        return segment.text.clone();
+    }
+    if is_set("code") {
+        return format!("~{}~", segment.text);
+    }
+
+    let mut rendered = match segment
+        .attributes
+        .get("link")
+        .and_then(serde_json::Value::as_str)
+    {
+        Some(link) => format!("[[{link}][{}]]", segment.text),
+        None => segment.text.clone(),
+    };
+    for (marker, attribute) in [('*', "bold"), ('/', "italic"), ('+', "strike")] {
+        if is_set(attribute) {
+            rendered = format!("{marker}{rendered}{marker}");
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use serde_json::json;
+
+    use super::{format_for_path, org_to_quill_delta, quill_delta_to_org};
+
+    fn round_trip(path: &str, body: &str) -> String {
+        let format = format_for_path(Path::new(path)).unwrap();
+        (format.serialize)(&(format.parse)(body))
+    }
+
+    #[test]
+    fn picks_formats_by_extension() {
+        assert!(format_for_path(Path::new("a.md")).unwrap().frontmatter);
+        assert!(
+            format_for_path(Path::new("a.MARKDOWN"))
+                .unwrap()
+                .frontmatter
+        );
+        assert!(!format_for_path(Path::new("a.txt")).unwrap().frontmatter);
+        assert_eq!(
+            format_for_path(Path::new("a.org")).unwrap().extension,
+            "org"
+        );
+        assert!(format_for_path(Path::new("theme.css")).is_none());
+    }
+
+    #[test]
+    fn plain_text_keeps_every_character() {
+        let text = "# not a header\n\n- *stars* stay\n  indented";
+        assert_eq!(round_trip("scratch.txt", text), text);
+    }
+
+    #[test]
+    fn reads_org_markup() {
+        let delta: serde_json::Value = serde_json::from_str(&org_to_quill_delta(
+            "* Plan\n- [X] *done* item\n  1. /nested/ step\n#+BEGIN_SRC rust\nfn main() {}\n#+END_SRC\nsee [[https://orgmode.org][Org]] and 2*3*4 [[file:attachments/a.png]]",
+        ))
+        .unwrap();
+        let ops = delta["ops"].as_array().unwrap();
+
+        for op in [
+            json!({ "insert": "\n", "attributes": { "header": 1 } }),
+            json!({ "insert": "done", "attributes": { "bold": true } }),
+            json!({ "insert": "\n", "attributes": { "list": "ordered", "indent": 1 } }),
+            json!({ "insert": "fn main() {}" }),
+            json!({ "insert": "\n", "attributes": { "code-block": "rust" } }),
+            json!({ "insert": "Org", "attributes": { "link": "https://orgmode.org" } }),
+            json!({ "insert": " and 2*3*4 " }),
+            json!({ "insert": { "image": "attachments/a.png" } }),
+        ] {
+            assert!(ops.contains(&op), "missing {op}");
+        }
+    }
+
+    #[test]
+    fn org_round_trips() {
+        let org = "* Plan\n** Details\n- [ ] open item\n- [X] +old+ item\n  1. first\n  2. ~code~ second\nplain /italic/ text\n-----\n#+BEGIN_QUOTE\nquoted\n#+END_QUOTE\n#+BEGIN_SRC\nlet x = 1;\n#+END_SRC\n#+BEGIN_SRC emacs-lisp\n(message \"hi\")\n#+END_SRC";
+
+        assert_eq!(round_trip("plan.org", org), org);
+        assert_eq!(quill_delta_to_org(&org_to_quill_delta(org)), org);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use tauri_plugin_log::log;
+
+use crate::atomic_file::write_atomically;
+use crate::save_load::{deserialize_record, ensure_unique_id, NoteRecord};
+
+/// Note records kept as one JSON file per note, with every record cached in
+/// memory. Reads never touch the disk and a write only rewrites the file of
+/// the note that changed.
+pub struct NoteStore {
+    dir: PathBuf,
+    records: Mutex<HashMap<String, NoteRecord>>,
+}
+
+impl NoteStore {
+    /// Loads every record file in `dir`, creating the folder if needed.
+    /// Unreadable files are skipped so one corrupt note cannot hide the others.
+    pub fn open(dir: PathBuf) -> anyhow::Result<Self> {
+        fs::create_dir_all(&dir).context("Failed to create note records directory")?;
+
+        let mut records = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+
+            match read_record(&path) {
+                Ok(record) => {
+                    records.insert(record.id.clone(), record);
+                }
+                Err(error) => log::warn!("Skipping note record {:?}: {:#}", path, error),
+            }
+        }
+
+        Ok(Self {
+            dir,
+            records: Mutex::new(records),
+        })
+    }
+
+    fn path_for(&self, note_id: &str) -> PathBuf {
+        self.dir.join(format!("{note_id}.json"))
+    }
+
+    fn write(&self, record: &NoteRecord) -> anyhow::Result<()> {
+        let json = serde_json::to_string_pretty(record)?;
+        write_atomically(&self.path_for(&record.id), json)
+            .with_context(|| format!("Failed to write note record {}", record.id))
+    }
+
+    pub fn get(&self, note_id: &str) -> Option<NoteRecord> {
+        self.records.lock().unwrap().get(note_id).cloned()
+    }
+
+    /// All records, most recently updated first.
+    pub fn all(&self) -> Vec<NoteRecord> {
+        let mut records: Vec<NoteRecord> = self.records.lock().unwrap().values().cloned().collect();
+        records.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.lock().unwrap().is_empty()
+    }
+
+    /// Inserts or replaces a record.
+    pub fn put(&self, record: NoteRecord) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        self.write(&record)?;
+        records.insert(record.id.clone(), record);
+        Ok(())
+    }
+
+    /// Applies `change` to the record with the given id and writes it back if
+    /// `change` reports that something changed. Returns the updated record, or
+    /// `None` if there is no such note.
+    pub fn update(
+        &self,
+        note_id: &str,
+        change: impl FnOnce(&mut NoteRecord) -> bool,
+    ) -> anyhow::Result<Option<NoteRecord>> {
+        let mut records = self.records.lock().unwrap();
+        let Some(record) = records.get(note_id) else {
+            return Ok(None);
+        };
+
+        let mut updated = record.clone();
+        if change(&mut updated) {
+            self.write(&updated)?;
+            records.insert(note_id.to_string(), updated.clone());
+        }
+
+        Ok(Some(updated))
+    }
+
+    pub fn remove(&self, note_id: &str) -> anyhow::Result<Option<NoteRecord>> {
+        let mut records = self.records.lock().unwrap();
+        let path = self.path_for(note_id);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove note record {}", note_id))?;
+        }
+        Ok(records.remove(note_id))
+    }
+
+    /// Imports the record map of the old single-file `save_data` store. Ids
+    /// that clash with existing records get a numeric suffix.
+    pub fn import_legacy_records(
+        &self,
+        data: &serde_json::Map<String, serde_json::Value>,
+    ) -> anyhow::Result<usize> {
+        let mut used_ids: HashSet<String> = self.records.lock().unwrap().keys().cloned().collect();
+        let mut imported = 0;
+
+        for (key, value) in data {
+            let mut record = deserialize_record(key, value.clone())
+                .with_context(|| format!("Failed to read legacy note {}", key))?;
+            record.id = ensure_unique_id(record.id, &mut used_ids);
+            self.put(record)?;
+            imported += 1;
+        }
+
+        Ok(imported)
+    }
+}
+
+fn read_record(path: &Path) -> anyhow::Result<NoteRecord> {
+    let raw = fs::read_to_string(path)?;
+    let value: serde_json::Value = serde_json::from_str(&raw)?;
+    let file_id = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    deserialize_record(file_id, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::NoteStore;
+    use crate::save_load::make_default_record;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("note_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn writes_each_note_to_its_own_file() {
+        let dir = temp_dir("files");
+        let store = NoteStore::open(dir.clone()).unwrap();
+
+        store.put(make_default_record("first".to_string())).unwrap();
+        store
+            .put(make_default_record("second".to_string()))
+            .unwrap();
+        let before = fs::read_to_string(dir.join("second.json")).unwrap();
+
+        store
+            .update("first", |record| {
+                record.note.color = "#bddcf6".to_string();
+                true
+            })
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(dir.join("second.json")).unwrap(), before);
+        let reopened = NoteStore::open(dir.clone()).unwrap();
+        assert_eq!(reopened.get("first").unwrap().note.color, "#bddcf6");
+        assert_eq!(reopened.all().len(), 2);
+
+        reopened.remove("first").unwrap();
+        assert!(!dir.join("first.json").exists());
+        assert!(reopened.get("first").is_none());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn imports_legacy_store_entries() {
+        let dir = temp_dir("legacy");
+        let store = NoteStore::open(dir.clone()).unwrap();
+        store.put(make_default_record("abc".to_string())).unwrap();
+
+        let legacy = serde_json::json!({
+            "sticky_abc": {
+                "status": "open",
+                "created_at": "2025-01-10T09:00:00+00:00",
+                "color": "#bfe6bf",
+                "contents": "{\"ops\":[{\"insert\":\"legacy\\n\"}]}",
+                "x": 10, "y": 20, "width": 300, "height": 250,
+                "always_on_top": false
+            },
+            "def": {
+                "id": "def",
+                "status": "archived",
+                "color": "#f9e7a7",
+                "contents": "",
+                "x": 0, "y": 0, "width": 300, "height": 250,
+                "always_on_top": true
+            }
+        });
+
+        let imported = store
+            .import_legacy_records(legacy.as_object().unwrap())
+            .unwrap();
+
+        assert_eq!(imported, 2);
+        assert_eq!(store.get("abc_1").unwrap().note.color, "#bfe6bf");
+        assert!(store.get("def").unwrap().note.always_on_top);
+        assert_eq!(NoteStore::open(dir.clone()).unwrap().all().len(), 3);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -10,7 +10,7 @@ use std::{
 };
 
 use anyhow::Context;
-use chrono::{DateTime, Duration, Local, Utc};
+use chrono::{Local, Utc};
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use tauri_plugin_log::log;
 use tauri_plugin_store::StoreExt;
@@ -18,16 +18,37 @@ use tauri_plugin_store::StoreExt;
 use tauri::{AppHandle, Emitter, Manager};
 
 use crate::{
+    anchor::AnchorInfo,
+    atomic_file::write_atomically,
+    attachments::{
+        self, embed_images, file_references, note_references, store_images, DroppedFile,
+        ATTACHMENTS_FOLDER,
+    },
+    backups::{self, BackedUpNote, BackupEntry, BackupPolicy, BackupState},
+    edit_journal::EditJournal,
+    filenames::{self, FilenameParts, FilenameStrategy},
+    frontmatter::{split_frontmatter, yaml_scalar, Frontmatter},
+    markdown::{markdown_to_quill_delta, quill_delta_to_markdown},
+    merge::{merge_lines, MergeOutcome},
+    migrations::{self, StoreLayout},
+    missing_notes::{confirm_mass_disappearance, needs_confirmation, MissingNotesState},
+    note_formats::{default_format, format_for_path, NoteFormat},
+    note_store::NoteStore,
     settings::MenuSettings,
     windows::{close_sticky_by_note_id, create_sticky},
 };
 
 const NOTES_DATA: &str = "save_data";
+const NOTE_RECORDS_FOLDER: &str = "note_records";
+const EDIT_JOURNAL_FOLDER: &str = "pending_edits";
 const BACKUP_FOLDER: &str = "backups";
 const MARKDOWN_NOTES_FOLDER: &str = "notes";
 const THEME_STYLESHEET_FILE: &str = "theme.css";
+const CONFLICT_COPY_MARKER: &str = ".conflict-";
 const SETTINGS: &str = "settings";
 const NOTES_DIRECTORY_SETTING_KEY: &str = "notes_directory";
+const FILENAME_STRATEGY_SETTING_KEY: &str = "filename_strategy";
+const BACKUP_POLICY_SETTING_KEY: &str = "backup_policy";
 const DEFAULT_THEME_STYLESHEET: &str = r#"/* macStickyNotes theme.css
    Edit values below, then restart the app.
 */
@@ -107,18 +128,16 @@ impl Default for Note {
     }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum NoteStatus {
+    #[default]
     Open,
     Closed,
     Archived,
-}
-
-impl Default for NoteStatus {
-    fn default() -> Self {
-        Self::Open
-    }
+    /// The note's markdown file vanished from the notes folder. The record is
+    /// kept so the note can be restored or deleted from the manager.
+    Missing,
 }
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
@@ -135,10 +154,44 @@ pub struct NoteRecord {
     pub closed_at: Option<String>,
     #[serde(default)]
     pub archived_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<AnchorInfo>,
+    /// Application the note is shown with; the note hides while other apps are frontmost.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_with_app: Option<String>,
+    /// When the note's markdown file was found missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub missing_since: Option<String>,
+    /// Markdown body as last written to or read from the note's file; the
+    /// common base when the file and the app both changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synced_markdown: Option<String>,
+    /// File name of the copy kept after an external edit conflicted with the
+    /// note, until the user dismisses the conflict.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict_copy: Option<String>,
+    /// Frontmatter keys of the note's file that the app does not use itself,
+    /// such as tags or aliases, as the YAML they were written in.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub extra_frontmatter: String,
+    /// Subfolder of the notes folder the note's file lives in, `/`-separated;
+    /// empty for notes at the top level.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notebook: String,
+    /// Name of the note's markdown file, as last written or found on disk.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
     #[serde(flatten)]
     pub note: Note,
 }
 
+impl NoteRecord {
+    /// Values of the frontmatter keys kept in `extra_frontmatter`.
+    pub fn extra_frontmatter_values(&self) -> serde_json::Map<String, serde_json::Value> {
+        Frontmatter::parse(&self.extra_frontmatter).to_json()
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct NoteListItem {
     pub id: String,
@@ -147,8 +200,12 @@ pub struct NoteListItem {
     pub updated_at: String,
     pub closed_at: Option<String>,
     pub archived_at: Option<String>,
+    pub missing_since: Option<String>,
     pub color: String,
     pub preview: String,
+    /// Frontmatter keys from the note's file the app does not use, e.g. tags.
+    pub extra_frontmatter: serde_json::Map<String, serde_json::Value>,
+    pub notebook: String,
 }
 
 fn default_zoom() -> f64 {
@@ -200,7 +257,7 @@ fn sanitize_note_id(raw: &str) -> String {
     }
 }
 
-fn ensure_unique_id(candidate: String, used: &mut HashSet<String>) -> String {
+pub fn ensure_unique_id(candidate: String, used: &mut HashSet<String>) -> String {
     if !used.contains(&candidate) {
         used.insert(candidate.clone());
         return candidate;
@@ -217,7 +274,7 @@ fn ensure_unique_id(candidate: String, used: &mut HashSet<String>) -> String {
     }
 }
 
-fn storage_key_to_note_id(key: &str) -> String {
+pub fn storage_key_to_note_id(key: &str) -> String {
     let raw = key.strip_prefix("sticky_").unwrap_or(key);
     sanitize_note_id(raw)
 }
@@ -265,69 +322,71 @@ fn normalize_record(record: &mut NoteRecord) {
                 record.archived_at = Some(record.updated_at.clone());
             }
         }
-    }
-}
-
-fn deserialize_record(storage_key: &str, value: serde_json::Value) -> anyhow::Result<NoteRecord> {
-    if let Ok(mut record) = serde_json::from_value::<NoteRecord>(value.clone()) {
-        if record.id.trim().is_empty() {
-            record.id = storage_key_to_note_id(storage_key);
+        NoteStatus::Missing => {
+            if record.missing_since.is_none() {
+                record.missing_since = Some(record.updated_at.clone());
+            }
         }
-        normalize_record(&mut record);
-        return Ok(record);
     }
+    if record.status != NoteStatus::Missing {
+        record.missing_since = None;
+    }
+}
 
-    let mut note = serde_json::from_value::<Note>(value)?;
-    if note.color.trim().is_empty() {
-        note.color = default_note_color();
+/// Reads a note record stored under `storage_key`. Older record shapes are
+/// upgraded by the store migrations before they get here.
+pub fn deserialize_record(
+    storage_key: &str,
+    value: serde_json::Value,
+) -> anyhow::Result<NoteRecord> {
+    let mut record = serde_json::from_value::<NoteRecord>(value)?;
+    if record.id.trim().is_empty() {
+        record.id = storage_key_to_note_id(storage_key);
     }
+    normalize_record(&mut record);
+    Ok(record)
+}
 
-    let now = now_iso();
-    Ok(NoteRecord {
-        id: storage_key_to_note_id(storage_key),
-        status: NoteStatus::Open,
-        created_at: now.clone(),
-        updated_at: now,
-        closed_at: None,
-        archived_at: None,
-        note,
-    })
+fn note_store(app: &AppHandle) -> tauri::State<'_, NoteStore> {
+    app.state::<NoteStore>()
 }
 
 fn load_note_records(app: &AppHandle) -> anyhow::Result<Vec<NoteRecord>> {
-    let store = app.store(NOTES_DATA)?;
-
-    let mut used_ids = HashSet::new();
-    let mut out = Vec::new();
-
-    if let Some(val) = store.get("data") {
-        let map = val
-            .as_object()
-            .context("json key 'data' contained a non-object")?;
+    Ok(note_store(app).all())
+}
 
-        for (key, value) in map {
-            let mut record = deserialize_record(key, value.clone())?;
-            record.id = ensure_unique_id(record.id, &mut used_ids);
-            out.push(record);
-        }
-    }
+/// Opens the per-note record store in the app data directory.
+fn open_note_store(app: &AppHandle) -> anyhow::Result<NoteStore> {
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .context("Failed to get app data directory")?;
 
-    out.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
-    Ok(out)
+    NoteStore::open(app_data_dir.join(NOTE_RECORDS_FOLDER))
 }
 
-fn save_note_records(app: &AppHandle, records: &[NoteRecord]) -> anyhow::Result<()> {
-    let store = app.store(NOTES_DATA)?;
+/// Opens the journal of pending note edits in the app data directory.
+pub fn open_edit_journal(app: &AppHandle) -> anyhow::Result<EditJournal> {
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .context("Failed to get app data directory")?;
 
-    let mut map = serde_json::Map::new();
-    for record in records {
-        map.insert(record.id.clone(), serde_json::to_value(record)?);
-    }
+    EditJournal::open(app_data_dir.join(EDIT_JOURNAL_FOLDER))
+}
 
-    store.set("data", map);
-    store.save()?;
+/// Where the note store and its backups live in the app data directory.
+fn store_layout(app: &AppHandle) -> anyhow::Result<StoreLayout> {
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .context("Failed to get app data directory")?;
 
-    Ok(())
+    Ok(StoreLayout {
+        legacy_store: app_data_dir.join(NOTES_DATA),
+        records_dir: app_data_dir.join(NOTE_RECORDS_FOLDER),
+        backup_dir: app_data_dir.join(BACKUP_FOLDER),
+    })
 }
 
 fn plain_text_from_quill_delta(contents: &str) -> String {
@@ -344,8 +403,6 @@ fn plain_text_from_quill_delta(contents: &str) -> String {
         if let Some(insert) = op.get("insert") {
             if let Some(text) = insert.as_str() {
                 out.push_str(text);
-            } else if insert.is_object() {
-                out.push_str("\n[embedded content]\n");
             }
         }
     }
@@ -353,80 +410,53 @@ fn plain_text_from_quill_delta(contents: &str) -> String {
     out
 }
 
-#[derive(Default)]
-struct InlineSegment {
-    text: String,
-    attributes: serde_json::Map<String, serde_json::Value>,
+#[derive(Default, Clone, PartialEq)]
+pub struct InlineSegment {
+    pub text: String,
+    pub attributes: serde_json::Map<String, serde_json::Value>,
+    /// Insert of an embed such as `{"image": url}`; its text is empty.
+    pub embed: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl InlineSegment {
+    /// Source of the segment's image, if it is one.
+    pub fn image_source(&self) -> Option<&str> {
+        self.embed.as_ref()?.get("image")?.as_str()
+    }
+
+    /// Whether the segment is a divider, a line of its own.
+    pub fn is_divider(&self) -> bool {
+        self.embed
+            .as_ref()
+            .is_some_and(|embed| embed.contains_key("divider"))
+    }
 }
 
 #[derive(Default)]
-struct DeltaLine {
-    segments: Vec<InlineSegment>,
-    block_attributes: serde_json::Map<String, serde_json::Value>,
+pub struct DeltaLine {
+    pub segments: Vec<InlineSegment>,
+    pub block_attributes: serde_json::Map<String, serde_json::Value>,
 }
 
-fn bool_attr(attributes: &serde_json::Map<String, serde_json::Value>, key: &str) -> bool {
+pub fn bool_attr(attributes: &serde_json::Map<String, serde_json::Value>, key: &str) -> bool {
     attributes
         .get(key)
         .and_then(serde_json::Value::as_bool)
         .unwrap_or(false)
 }
 
-fn u64_attr(attributes: &serde_json::Map<String, serde_json::Value>, key: &str) -> Option<u64> {
+pub fn u64_attr(attributes: &serde_json::Map<String, serde_json::Value>, key: &str) -> Option<u64> {
     attributes.get(key).and_then(serde_json::Value::as_u64)
 }
 
-fn str_attr<'a>(
+pub fn str_attr<'a>(
     attributes: &'a serde_json::Map<String, serde_json::Value>,
     key: &str,
 ) -> Option<&'a str> {
     attributes.get(key).and_then(serde_json::Value::as_str)
 }
 
-fn render_plain_segments(segments: &[InlineSegment]) -> String {
-    segments
-        .iter()
-        .map(|segment| segment.text.as_str())
-        .collect::<String>()
-}
-
-fn render_inline_segment(segment: &InlineSegment) -> String {
-    if segment.text.is_empty() {
-        return String::new();
-    }
-
-    if bool_attr(&segment.attributes, "code") {
-        return format!("`{}`", segment.text.replace('`', "\\`"));
-    }
-
-    let mut rendered = segment.text.clone();
-
-    if let Some(link) = str_attr(&segment.attributes, "link") {
-        rendered = format!("[{rendered}]({link})");
-    }
-
-    if bool_attr(&segment.attributes, "bold") {
-        rendered = format!("**{rendered}**");
-    }
-    if bool_attr(&segment.attributes, "italic") {
-        rendered = format!("*{rendered}*");
-    }
-    if bool_attr(&segment.attributes, "strike") {
-        rendered = format!("~~{rendered}~~");
-    }
-
-    rendered
-}
-
-fn render_inline_segments(segments: &[InlineSegment]) -> String {
-    segments
-        .iter()
-        .map(render_inline_segment)
-        .collect::<Vec<_>>()
-        .join("")
-}
-
-fn quill_delta_to_lines(contents: &str) -> Option<Vec<DeltaLine>> {
+pub fn quill_delta_to_lines(contents: &str) -> Option<Vec<DeltaLine>> {
     let delta_value = serde_json::from_str::<serde_json::Value>(contents).ok()?;
     let ops = delta_value
         .get("ops")
@@ -458,6 +488,7 @@ fn quill_delta_to_lines(contents: &str) -> Option<Vec<DeltaLine>> {
                     current_line.segments.push(InlineSegment {
                         text: text[chunk_start..index].to_string(),
                         attributes: attributes.clone(),
+                        embed: None,
                     });
                 }
 
@@ -473,13 +504,27 @@ fn quill_delta_to_lines(contents: &str) -> Option<Vec<DeltaLine>> {
                 current_line.segments.push(InlineSegment {
                     text: text[chunk_start..].to_string(),
                     attributes,
+                    embed: None,
+                });
+            }
+        } else if let Some(embed) = insert.as_object() {
+            let segment = InlineSegment {
+                text: String::new(),
+                attributes,
+                embed: Some(embed.clone()),
+            };
+            if segment.is_divider() {
+                // A divider has no newline of its own.
+                if !current_line.segments.is_empty() {
+                    lines.push(std::mem::take(&mut current_line));
+                }
+                lines.push(DeltaLine {
+                    segments: vec![segment],
+                    block_attributes: serde_json::Map::new(),
                 });
+            } else {
+                current_line.segments.push(segment);
             }
-        } else if insert.is_object() {
-            current_line.segments.push(InlineSegment {
-                text: "[embedded content]".to_string(),
-                attributes: serde_json::Map::new(),
-            });
         }
     }
 
@@ -493,111 +538,87 @@ fn quill_delta_to_lines(contents: &str) -> Option<Vec<DeltaLine>> {
     Some(lines)
 }
 
-fn quill_delta_to_markdown(contents: &str) -> String {
-    let Some(lines) = quill_delta_to_lines(contents) else {
-        return contents.to_string();
-    };
-
-    let mut rendered = Vec::new();
-    let mut ordered_counters: Vec<usize> = Vec::new();
-    let mut in_code_block = false;
-
-    for line in lines {
-        if bool_attr(&line.block_attributes, "code-block") {
-            if !in_code_block {
-                rendered.push("```".to_string());
-                in_code_block = true;
-            }
-            rendered.push(render_plain_segments(&line.segments));
-            continue;
-        }
-
-        if in_code_block {
-            rendered.push("```".to_string());
-            in_code_block = false;
-        }
+/// Contents of the note's file in its format: frontmatter, if the format
+/// has it, followed by the body.
+fn note_file_contents(record: &NoteRecord) -> String {
+    let format = record_format(record);
+    let body = (format.serialize)(&file_references(&record.note.contents, &record.notebook));
+    if !format.frontmatter {
+        return body.trim_end().to_string();
+    }
 
-        if let Some(level) = u64_attr(&line.block_attributes, "header") {
-            ordered_counters.clear();
-            let header_level = level.clamp(1, 6) as usize;
-            let header_text = render_inline_segments(&line.segments).trim().to_string();
-            if header_text.is_empty() {
-                rendered.push("#".repeat(header_level));
-            } else {
-                rendered.push(format!("{} {header_text}", "#".repeat(header_level)));
-            }
-            continue;
-        }
+    let note_fields: String = note_frontmatter_fields(&record.note)
+        .into_iter()
+        .map(|(key, value)| format!("{key}: {value}\n"))
+        .collect();
 
-        if let Some(list_kind) = str_attr(&line.block_attributes, "list") {
-            let indent = u64_attr(&line.block_attributes, "indent").unwrap_or(0) as usize;
-            let indent_prefix = "  ".repeat(indent);
-            let item_text = render_inline_segments(&line.segments).trim().to_string();
-
-            match list_kind {
-                "ordered" => {
-                    if ordered_counters.len() <= indent {
-                        ordered_counters.resize(indent + 1, 0);
-                    }
-                    ordered_counters.truncate(indent + 1);
-                    ordered_counters[indent] += 1;
-                    rendered.push(format!(
-                        "{indent_prefix}{}. {item_text}",
-                        ordered_counters[indent]
-                    ));
-                }
-                "checked" => {
-                    ordered_counters.clear();
-                    rendered.push(format!("{indent_prefix}- [x] {item_text}"));
-                }
-                "unchecked" => {
-                    ordered_counters.clear();
-                    rendered.push(format!("{indent_prefix}- [ ] {item_text}"));
-                }
-                _ => {
-                    ordered_counters.clear();
-                    rendered.push(format!("{indent_prefix}- {item_text}"));
-                }
-            }
+    format!(
+        "---\nid: {}\nstatus: {:?}\ncreated_at: {}\nupdated_at: {}\nclosed_at: {}\narchived_at: {}\n{}{}---\n\n{}",
+        yaml_scalar(&record.id),
+        record.status,
+        yaml_scalar(&record.created_at),
+        yaml_scalar(&record.updated_at),
+        yaml_scalar(record.closed_at.as_deref().unwrap_or_default()),
+        yaml_scalar(record.archived_at.as_deref().unwrap_or_default()),
+        note_fields,
+        record.extra_frontmatter,
+        body.trim_end()
+    )
+}
 
-            continue;
-        }
+/// Frontmatter keys written from the record itself; any other key is kept
+/// in `extra_frontmatter`.
+fn is_record_frontmatter_key(key: &str) -> bool {
+    const RECORD_KEYS: [&str; 6] = [
+        "id",
+        "status",
+        "created_at",
+        "updated_at",
+        "closed_at",
+        "archived_at",
+    ];
+    RECORD_KEYS.contains(&key) || note_frontmatter_fields(&Note::default()).contains_key(key)
+}
+
+/// The note's own fields as they appear in frontmatter: everything but the
+/// contents, which make up the body. Values are JSON scalars, which YAML
+/// reads the same way.
+fn note_frontmatter_fields(note: &Note) -> serde_json::Map<String, serde_json::Value> {
+    let Ok(serde_json::Value::Object(mut fields)) = serde_json::to_value(note) else {
+        return serde_json::Map::new();
+    };
+    fields.remove("contents");
+    fields
+}
 
-        ordered_counters.clear();
+/// Applies note fields read from frontmatter, skipping values of the wrong
+/// type. Returns whether the note changed.
+fn apply_note_fields(note: &mut Note, fields: &serde_json::Map<String, serde_json::Value>) -> bool {
+    let Ok(mut current) = serde_json::to_value(&*note) else {
+        return false;
+    };
+    let original = current.clone();
 
-        if bool_attr(&line.block_attributes, "blockquote") {
-            let quote_text = render_inline_segments(&line.segments).trim().to_string();
-            if quote_text.is_empty() {
-                rendered.push(">".to_string());
-            } else {
-                rendered.push(format!("> {quote_text}"));
-            }
-            continue;
+    for (key, value) in fields {
+        let mut candidate = current.clone();
+        candidate[key.as_str()] = value.clone();
+        if serde_json::from_value::<Note>(candidate.clone()).is_ok() {
+            current = candidate;
+        } else {
+            log::warn!("Ignoring invalid frontmatter value {}: {}", key, value);
         }
-
-        rendered.push(render_inline_segments(&line.segments));
     }
 
-    if in_code_block {
-        rendered.push("```".to_string());
+    if current == original {
+        return false;
+    }
+    match serde_json::from_value(current) {
+        Ok(updated) => {
+            *note = updated;
+            true
+        }
+        Err(_) => false,
     }
-
-    rendered.join("\n")
-}
-
-fn note_to_markdown(record: &NoteRecord) -> String {
-    let body = quill_delta_to_markdown(&record.note.contents);
-
-    format!(
-        "---\nid: {}\nstatus: {:?}\ncreated_at: {}\nupdated_at: {}\nclosed_at: {}\narchived_at: {}\n---\n\n{}",
-        record.id,
-        record.status,
-        record.created_at,
-        record.updated_at,
-        record.closed_at.clone().unwrap_or_default(),
-        record.archived_at.clone().unwrap_or_default(),
-        body.trim_end()
-    )
 }
 
 #[derive(Default)]
@@ -605,6 +626,14 @@ struct ImportedMarkdownNote {
     id: String,
     status: Option<NoteStatus>,
     contents: String,
+    markdown: String,
+    /// Frontmatter values of note fields such as color or position.
+    note_fields: serde_json::Map<String, serde_json::Value>,
+    extra_frontmatter: String,
+    notebook: String,
+    file_name: String,
+    /// The file has no id of its own; it was derived from the file name.
+    named_by_path: bool,
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -636,49 +665,22 @@ fn parse_note_status(value: &str) -> Option<NoteStatus> {
     }
 }
 
-fn parse_frontmatter_and_body(raw: &str) -> (HashMap<String, String>, String) {
-    let normalized = raw.replace("\r\n", "\n");
-    let Some(rest) = normalized.strip_prefix("---\n") else {
-        return (HashMap::new(), normalized);
-    };
-
-    let frontmatter_end = rest
-        .find("\n---\n")
-        .map(|index| (index, 5usize))
-        .or_else(|| rest.find("\n---").map(|index| (index, 4usize)));
-
-    let Some((index, marker_len)) = frontmatter_end else {
-        return (HashMap::new(), normalized);
-    };
-
-    let mut map = HashMap::new();
-    let frontmatter = &rest[..index];
-    for line in frontmatter.lines() {
-        let Some((key, value)) = line.split_once(':') else {
-            continue;
-        };
-
-        map.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+/// Id of a note file that does not state one. The app names markdown files
+/// after the note's id, so that is the last part of the name. Formats without
+/// frontmatter keep whatever name the user gave them, so the whole path
+/// inside the notes folder is used.
+fn note_id_from_markdown_path(path: &Path, format: &NoteFormat, notebook: &str) -> Option<String> {
+    if !format.frontmatter {
+        let file_name = path.file_name()?.to_str()?;
+        return normalize_id_for_lookup(&format!("{notebook}/{file_name}"));
     }
 
-    let body_start = index.saturating_add(marker_len);
-    let mut body = if body_start <= rest.len() {
-        rest[body_start..].to_string()
-    } else {
-        String::new()
-    };
-    body = body.trim_start_matches('\n').to_string();
-
-    (map, body)
-}
-
-fn note_id_from_markdown_path(path: &Path) -> Option<String> {
     let stem = path.file_stem()?.to_str()?;
     let fallback = stem.rsplit('_').next().unwrap_or(stem);
     normalize_id_for_lookup(fallback)
 }
 
-fn make_delta_text_op(
+pub fn make_delta_text_op(
     text: String,
     attributes: serde_json::Map<String, serde_json::Value>,
 ) -> serde_json::Value {
@@ -689,265 +691,209 @@ fn make_delta_text_op(
     }
 }
 
-fn parse_inline_markdown(text: &str) -> Vec<(String, serde_json::Map<String, serde_json::Value>)> {
-    let mut out = Vec::new();
-    let mut index = 0usize;
-
-    while index < text.len() {
-        let remaining = &text[index..];
-
-        if let Some(link_body) = remaining.strip_prefix('[') {
-            if let Some(label_end) = link_body.find("](") {
-                let after_label = &link_body[label_end + 2..];
-                if let Some(url_end) = after_label.find(')') {
-                    let label = &link_body[..label_end];
-                    let url = &after_label[..url_end];
-                    if !label.is_empty() && !url.is_empty() {
-                        let mut attrs = serde_json::Map::new();
-                        attrs.insert(
-                            "link".to_string(),
-                            serde_json::Value::String(url.to_string()),
-                        );
-                        out.push((label.to_string(), attrs));
-                        index += 1 + label_end + 2 + url_end + 1;
-                        continue;
-                    }
-                }
-            }
-        }
-
-        if let Some(italic_body) = remaining.strip_prefix('*') {
-            if let Some(end) = italic_body.find('*') {
-                let value = &italic_body[..end];
-                if !value.is_empty() {
-                    let mut attrs = serde_json::Map::new();
-                    attrs.insert("italic".to_string(), serde_json::Value::Bool(true));
-                    out.push((value.to_string(), attrs));
-                    index += end + 2;
-                    continue;
-                }
-            }
-        }
-
-        if let Some(bold_body) = remaining.strip_prefix("**") {
-            if let Some(end) = bold_body.find("**") {
-                let value = &bold_body[..end];
-                if !value.is_empty() {
-                    let mut attrs = serde_json::Map::new();
-                    attrs.insert("bold".to_string(), serde_json::Value::Bool(true));
-                    out.push((value.to_string(), attrs));
-                    index += end + 4;
-                    continue;
-                }
-            }
-        }
+/// Format of the note's file. Notes whose file name is not known yet were
+/// written as markdown.
+fn record_format(record: &NoteRecord) -> &'static NoteFormat {
+    record
+        .file_name
+        .as_deref()
+        .and_then(|file_name| format_for_path(Path::new(file_name)))
+        .unwrap_or_else(default_format)
+}
 
-        if let Some(strike_body) = remaining.strip_prefix("~~") {
-            if let Some(end) = strike_body.find("~~") {
-                let value = &strike_body[..end];
-                if !value.is_empty() {
-                    let mut attrs = serde_json::Map::new();
-                    attrs.insert("strike".to_string(), serde_json::Value::Bool(true));
-                    out.push((value.to_string(), attrs));
-                    index += end + 4;
-                    continue;
-                }
-            }
-        }
+/// Frontmatter and body of a note file in `format`.
+fn split_note_file(format: &NoteFormat, raw: &str) -> (Frontmatter, String) {
+    if format.frontmatter {
+        split_frontmatter(raw)
+    } else {
+        (Frontmatter::default(), raw.replace("\r\n", "\n"))
+    }
+}
 
-        if let Some(code_body) = remaining.strip_prefix('`') {
-            if let Some(end) = code_body.find('`') {
-                let value = &code_body[..end];
-                if !value.is_empty() {
-                    let mut attrs = serde_json::Map::new();
-                    attrs.insert("code".to_string(), serde_json::Value::Bool(true));
-                    out.push((value.to_string(), attrs));
-                    index += end + 2;
-                    continue;
-                }
-            }
-        }
+/// Reads a note file found in `notebook`. Its attachments stay paths; the
+/// images are embedded once the note is known to have changed.
+fn parse_markdown_note(path: &Path, raw: &str, notebook: &str) -> Option<ImportedMarkdownNote> {
+    let format = format_for_path(path).unwrap_or_else(default_format);
+    let (frontmatter, body) = split_note_file(format, raw);
+    let contents = note_references(&(format.parse)(&body), notebook);
 
-        let mut next_break = remaining.len();
-        for token in ['[', '*', '`', '~'] {
-            if let Some(next) = remaining.find(token) {
-                next_break = next_break.min(next);
-            }
-        }
+    let frontmatter_id = frontmatter
+        .get_string("id")
+        .and_then(|value| normalize_id_for_lookup(&value));
+    let named_by_path = frontmatter_id.is_none();
+    let note_id = frontmatter_id.or_else(|| note_id_from_markdown_path(path, format, notebook))?;
 
-        if next_break == 0 {
-            out.push((remaining[..1].to_string(), serde_json::Map::new()));
-            index += 1;
-            continue;
-        }
+    let status = frontmatter
+        .get_string("status")
+        .and_then(|value| parse_note_status(&value));
 
-        out.push((remaining[..next_break].to_string(), serde_json::Map::new()));
-        index += next_break;
-    }
+    let note_fields = note_frontmatter_fields(&Note::default())
+        .into_iter()
+        .filter_map(|(key, _)| {
+            let value = serde_json::to_value(frontmatter.get(&key)?).ok()?;
+            Some((key, value))
+        })
+        .collect();
 
-    out
+    Some(ImportedMarkdownNote {
+        id: note_id,
+        status,
+        markdown: record_markdown(&contents),
+        contents,
+        note_fields,
+        extra_frontmatter: frontmatter.unknown_entries(is_record_frontmatter_key),
+        notebook: notebook.to_string(),
+        file_name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        named_by_path,
+    })
 }
 
-fn parse_markdown_line(line: &str) -> (String, serde_json::Map<String, serde_json::Value>) {
-    let mut block_attributes = serde_json::Map::new();
-    let mut text = line.to_string();
-    let trimmed = line.trim_start();
-    let leading_spaces = line.len().saturating_sub(trimmed.len());
-    let indent = (leading_spaces / 2) as u64;
-
-    if let Some(rest) = trimmed.strip_prefix('>') {
-        block_attributes.insert("blockquote".to_string(), serde_json::Value::Bool(true));
-        text = rest.trim_start().to_string();
-        return (text, block_attributes);
-    }
-
-    if trimmed.starts_with('#') {
-        let level = trimmed.chars().take_while(|ch| *ch == '#').count();
-        let content = trimmed[level..].trim_start();
-        if level > 0 && level <= 6 && !content.is_empty() {
-            block_attributes.insert(
-                "header".to_string(),
-                serde_json::Value::Number(level.into()),
-            );
-            return (content.to_string(), block_attributes);
-        }
-    }
-
-    if let Some(rest) = trimmed.strip_prefix("- [ ] ") {
-        block_attributes.insert(
-            "list".to_string(),
-            serde_json::Value::String("unchecked".to_string()),
-        );
-        if indent > 0 {
-            block_attributes.insert(
-                "indent".to_string(),
-                serde_json::Value::Number(indent.into()),
-            );
-        }
-        return (rest.to_string(), block_attributes);
-    }
+/// Reads every note file in `notes_dir`, keyed by note id. A file still at
+/// the path of the record it belongs to keeps that record's id; any other
+/// file whose id is already taken, such as `todo.md` in a second notebook,
+/// gets a fresh one instead of replacing the first.
+fn import_note_files(
+    notes_dir: &Path,
+    records: &[NoteRecord],
+) -> anyhow::Result<HashMap<String, ImportedMarkdownNote>> {
+    let known_paths: HashMap<PathBuf, &str> = records
+        .iter()
+        .map(|record| (markdown_relative_path(record), record.id.as_str()))
+        .collect();
 
-    if let Some(rest) = trimmed
-        .strip_prefix("- [x] ")
-        .or_else(|| trimmed.strip_prefix("- [X] "))
-    {
-        block_attributes.insert(
-            "list".to_string(),
-            serde_json::Value::String("checked".to_string()),
-        );
-        if indent > 0 {
-            block_attributes.insert(
-                "indent".to_string(),
-                serde_json::Value::Number(indent.into()),
-            );
+    let mut paths = note_file_paths(notes_dir)?;
+    paths.sort();
+    let mut claimed = Vec::new();
+    let mut unclaimed = Vec::new();
+    for path in paths {
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(error) => {
+                log::warn!("Failed reading markdown note {:?}: {}", path, error);
+                continue;
+            }
+        };
+        let notebook = notebook_for_path(notes_dir, &path);
+        let Some(mut imported) = parse_markdown_note(&path, &raw, &notebook) else {
+            continue;
+        };
+        let known_id = path
+            .strip_prefix(notes_dir)
+            .ok()
+            .and_then(|relative| known_paths.get(relative));
+        match known_id {
+            Some(&id) if imported.named_by_path || imported.id == id => {
+                imported.id = id.to_string();
+                claimed.push(imported);
+            }
+            _ => unclaimed.push(imported),
         }
-        return (rest.to_string(), block_attributes);
     }
 
-    if let Some(rest) = trimmed
-        .strip_prefix("- ")
-        .or_else(|| trimmed.strip_prefix("* "))
-    {
-        block_attributes.insert(
-            "list".to_string(),
-            serde_json::Value::String("bullet".to_string()),
-        );
-        if indent > 0 {
-            block_attributes.insert(
-                "indent".to_string(),
-                serde_json::Value::Number(indent.into()),
+    let mut used = HashSet::new();
+    let mut imported_by_id = HashMap::new();
+    for mut imported in claimed.into_iter().chain(unclaimed) {
+        let id = ensure_unique_id(imported.id.clone(), &mut used);
+        if id != imported.id {
+            log::info!(
+                "Note id {} of {} is taken, importing it as {}",
+                imported.id,
+                imported.file_name,
+                id
             );
+            imported.id = id.clone();
         }
-        return (rest.to_string(), block_attributes);
+        imported_by_id.insert(id, imported);
     }
+    Ok(imported_by_id)
+}
 
-    if let Some(dot) = trimmed.find(". ") {
-        let prefix = &trimmed[..dot];
-        if !prefix.is_empty() && prefix.chars().all(|ch| ch.is_ascii_digit()) {
-            block_attributes.insert(
-                "list".to_string(),
-                serde_json::Value::String("ordered".to_string()),
-            );
-            if indent > 0 {
-                block_attributes.insert(
-                    "indent".to_string(),
-                    serde_json::Value::Number(indent.into()),
-                );
-            }
-            return (trimmed[dot + 2..].to_string(), block_attributes);
-        }
+fn is_note_file_path(path: &Path) -> bool {
+    if format_for_path(path).is_none() {
+        return false;
     }
 
-    (text, block_attributes)
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    file_name != Some(THEME_STYLESHEET_FILE) && !file_name.is_some_and(is_conflict_copy_name)
 }
 
-fn markdown_to_quill_delta(markdown: &str) -> String {
-    let normalized = markdown.replace("\r\n", "\n");
-    if normalized.trim().is_empty() {
-        return String::new();
-    }
+fn is_conflict_copy_name(file_name: &str) -> bool {
+    file_name.contains(CONFLICT_COPY_MARKER)
+}
 
-    let mut ops = Vec::new();
-    let mut in_code_block = false;
+/// Markdown put through the delta conversion, so text written by another
+/// editor compares equal to what the app would write for the same note.
+fn normalized_markdown(markdown: &str) -> String {
+    quill_delta_to_markdown(&markdown_to_quill_delta(markdown))
+        .trim()
+        .to_string()
+}
 
-    for line in normalized.split('\n') {
-        let trimmed = line.trim();
-        if trimmed.starts_with("```") {
-            in_code_block = !in_code_block;
-            continue;
-        }
+/// Markdown of a note's contents with pasted images as the attachment
+/// paths they are written with, so it compares equal to the note's file.
+fn record_markdown(contents: &str) -> String {
+    normalized_markdown(&quill_delta_to_markdown(&file_references(contents, "")))
+}
 
-        if in_code_block {
-            ops.push(make_delta_text_op(line.to_string(), serde_json::Map::new()));
-            let mut newline_attributes = serde_json::Map::new();
-            newline_attributes.insert("code-block".to_string(), serde_json::Value::Bool(true));
-            ops.push(make_delta_text_op("\n".to_string(), newline_attributes));
-            continue;
+/// Three-way merges two versions of a note's markdown edited from `base`,
+/// storing the result in `record`. On conflict the record keeps `ours` and
+/// `theirs` is saved as a conflict copy next to the note's file. Returns
+/// whether the record's contents changed.
+fn merge_note_markdown(
+    app: &AppHandle,
+    record: &mut NoteRecord,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+) -> anyhow::Result<bool> {
+    match merge_lines(base, ours, theirs) {
+        MergeOutcome::Merged(merged) => {
+            if merged == ours {
+                return Ok(false);
+            }
+            log::info!("Merged external edit into note {}", record.id);
+            record.note.contents =
+                embed_images(&notes_directory(app)?, &markdown_to_quill_delta(&merged));
+            Ok(true)
         }
-
-        let (text, block_attributes) = parse_markdown_line(line);
-        let inline_segments = parse_inline_markdown(&text);
-        for (segment, attrs) in inline_segments {
-            if !segment.is_empty() {
-                ops.push(make_delta_text_op(segment, attrs));
+        MergeOutcome::Conflict => {
+            let copy_name = write_conflict_copy(app, record, theirs)?;
+            log::warn!(
+                "External edit of note {} conflicts with the app's version, kept it as {}",
+                record.id,
+                copy_name
+            );
+            if let Some(window) = app.get_webview_window(&format!("sticky_{}", record.id)) {
+                let _ = window.emit("note_conflict", copy_name.clone());
             }
+            record.conflict_copy = Some(copy_name);
+            Ok(false)
         }
-
-        ops.push(make_delta_text_op("\n".to_string(), block_attributes));
-    }
-
-    if ops.is_empty() {
-        return String::new();
     }
-
-    serde_json::to_string(&serde_json::json!({ "ops": ops })).unwrap_or_default()
-}
-
-fn parse_markdown_note(path: &Path, raw: &str) -> Option<ImportedMarkdownNote> {
-    let (frontmatter, body) = parse_frontmatter_and_body(raw);
-
-    let note_id = frontmatter
-        .get("id")
-        .and_then(|value| normalize_id_for_lookup(value))
-        .or_else(|| note_id_from_markdown_path(path))?;
-
-    let status = frontmatter
-        .get("status")
-        .and_then(|value| parse_note_status(value));
-
-    Some(ImportedMarkdownNote {
-        id: note_id,
-        status,
-        contents: markdown_to_quill_delta(&body),
-    })
 }
 
-fn is_markdown_note_path(path: &Path) -> bool {
-    if path.extension().and_then(|extension| extension.to_str()) != Some("md") {
-        return false;
-    }
+fn write_conflict_copy(
+    app: &AppHandle,
+    record: &NoteRecord,
+    markdown: &str,
+) -> anyhow::Result<String> {
+    let path = note_markdown_path(app, record)?;
+    let file_name = markdown_filename(record);
+    let stem = Path::new(&file_name)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
+    let extension = record_format(record).extension;
+    let copy_name = format!("{stem}{CONFLICT_COPY_MARKER}{timestamp}.{extension}");
 
-    path.file_name().and_then(|name| name.to_str()) != Some(THEME_STYLESHEET_FILE)
+    suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
+    write_atomically(&path.with_file_name(&copy_name), markdown)
+        .context("Failed to write conflict copy")?;
+    Ok(copy_name)
 }
 
 fn emit_external_note_update(app: &AppHandle, record: &NoteRecord) {
@@ -968,78 +914,107 @@ fn sync_from_markdown_directory_internal(
     update_windows: bool,
 ) -> anyhow::Result<bool> {
     let notes_dir = notes_directory(app)?;
-    let mut records = load_note_records(app)?;
-
-    let mut imported_by_id = HashMap::<String, ImportedMarkdownNote>::new();
-    for entry in fs::read_dir(notes_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if !path.is_file() || !is_markdown_note_path(&path) {
-            continue;
-        }
+    let records = load_note_records(app)?;
 
-        match fs::read_to_string(&path) {
-            Ok(raw) => {
-                if let Some(imported) = parse_markdown_note(&path, &raw) {
-                    imported_by_id.insert(imported.id.clone(), imported);
-                }
-            }
-            Err(error) => {
-                log::warn!("Failed reading markdown note {:?}: {}", path, error);
-            }
-        }
-    }
+    let mut imported_by_id = import_note_files(&notes_dir, &records)?;
 
-    let imported_ids = imported_by_id.keys().cloned().collect::<HashSet<String>>();
+    let store = note_store(app);
+    let missing_notes = app.state::<MissingNotesState>();
     let mut changed = false;
     let mut open_changed_ids = Vec::<String>::new();
     let mut open_created_ids = Vec::<String>::new();
     let mut close_ids = Vec::<String>::new();
+    let mut vanished_ids = Vec::<String>::new();
 
-    for record in &mut records {
-        if let Some(imported) = imported_by_id.get(&record.id) {
-            let desired_status = imported.status.unwrap_or(record.status);
-
-            if record.note.contents != imported.contents || record.status != desired_status {
-                record.note.contents = imported.contents.clone();
-                record.status = desired_status;
-                record.updated_at = now_iso();
-                normalize_record(record);
-                changed = true;
+    for record in &records {
+        let Some(imported) = imported_by_id.remove(&record.id) else {
+            if record.status != NoteStatus::Missing
+                && !missing_notes.is_awaiting_confirmation(&record.id)
+            {
+                vanished_ids.push(record.id.clone());
+            }
+            continue;
+        };
+        missing_notes.release(&record.id);
 
-                if record.status == NoteStatus::Open {
-                    open_changed_ids.push(record.id.clone());
-                } else {
-                    close_ids.push(record.id.clone());
-                }
+        // A note whose file comes back returns to the status its file states.
+        let previous_status = match record.status {
+            NoteStatus::Missing => NoteStatus::Open,
+            status => status,
+        };
+        let desired_status = imported.status.unwrap_or(previous_status);
+        let ours = record_markdown(&record.note.contents);
+        let mut note = record.note.clone();
+        let fields_changed = apply_note_fields(&mut note, &imported.note_fields)
+            || record.extra_frontmatter != imported.extra_frontmatter
+            || record.notebook != imported.notebook
+            || record.file_name.as_ref() != Some(&imported.file_name);
+        if ours == imported.markdown && record.status == desired_status && !fields_changed {
+            continue;
+        }
+
+        let mut record = record.clone();
+        record.note = note;
+        record.extra_frontmatter = imported.extra_frontmatter;
+        // The file was moved or renamed; keep it where it is now.
+        record.notebook = imported.notebook;
+        record.file_name = Some(imported.file_name);
+        match record.synced_markdown.clone() {
+            // Only the file changed, or there is no base to merge with yet.
+            None => record.note.contents = embed_images(&notes_dir, &imported.contents),
+            Some(base) if base == ours => {
+                record.note.contents = embed_images(&notes_dir, &imported.contents)
+            }
+            // The file still has the last synced version; our pending write wins.
+            Some(base) if base == imported.markdown => {}
+            Some(base) => {
+                merge_note_markdown(app, &mut record, &base, &ours, &imported.markdown)?;
             }
         }
-    }
+        record.status = desired_status;
+        // The file now holds their version, which becomes the new base.
+        record.synced_markdown = Some(imported.markdown.clone());
+        if record_markdown(&record.note.contents) != imported.markdown {
+            sync_markdown_file(app, &mut record)?;
+        }
+        record.updated_at = now_iso();
+        normalize_record(&mut record);
+        changed = true;
 
-    let mut retained = Vec::with_capacity(records.len());
-    for record in records {
-        if imported_ids.contains(&record.id) {
-            retained.push(record);
+        if record.status == NoteStatus::Open {
+            open_changed_ids.push(record.id.clone());
         } else {
-            changed = true;
             close_ids.push(record.id.clone());
         }
+        store.put(record)?;
     }
-    let mut records = retained;
 
-    for imported in imported_by_id.into_values() {
-        if records.iter().any(|record| record.id == imported.id) {
-            continue;
-        }
+    let tracked = records
+        .iter()
+        .filter(|record| record.status != NoteStatus::Missing)
+        .count();
+    if needs_confirmation(vanished_ids.len(), tracked) {
+        confirm_mass_disappearance(app, vanished_ids);
+    } else if !vanished_ids.is_empty() {
+        set_notes_missing(&store, &vanished_ids)?;
+        changed = true;
+        close_ids.extend(vanished_ids);
+    }
 
+    for imported in imported_by_id.into_values() {
         let mut record = make_default_record(imported.id);
-        record.note.contents = imported.contents;
+        apply_note_fields(&mut record.note, &imported.note_fields);
+        record.extra_frontmatter = imported.extra_frontmatter;
+        record.notebook = imported.notebook;
+        record.file_name = Some(imported.file_name);
+        record.note.contents = embed_images(&notes_dir, &imported.contents);
+        record.synced_markdown = Some(imported.markdown);
         record.status = imported.status.unwrap_or(NoteStatus::Open);
         normalize_record(&mut record);
         if record.status == NoteStatus::Open {
             open_created_ids.push(record.id.clone());
         }
-        records.push(record);
+        store.put(record)?;
         changed = true;
     }
 
@@ -1047,26 +1022,24 @@ fn sync_from_markdown_directory_internal(
         return Ok(false);
     }
 
-    save_note_records(app, &records)?;
-
     if update_windows {
         for note_id in close_ids {
             let _ = close_sticky_by_note_id(app, &note_id);
         }
 
         for note_id in open_created_ids {
-            if let Some(record) = records.iter().find(|record| record.id == note_id) {
-                let _ = create_sticky(app, Some(record));
+            if let Some(record) = store.get(&note_id) {
+                let _ = create_sticky(app, Some(&record));
             }
         }
 
         for note_id in open_changed_ids {
-            if let Some(record) = records.iter().find(|record| record.id == note_id) {
+            if let Some(record) = store.get(&note_id) {
                 let window_label = format!("sticky_{}", record.id);
                 if app.get_webview_window(&window_label).is_some() {
-                    emit_external_note_update(app, record);
+                    emit_external_note_update(app, &record);
                 } else {
-                    let _ = create_sticky(app, Some(record));
+                    let _ = create_sticky(app, Some(&record));
                 }
             }
         }
@@ -1076,6 +1049,41 @@ fn sync_from_markdown_directory_internal(
     Ok(true)
 }
 
+fn set_notes_missing(store: &NoteStore, note_ids: &[String]) -> anyhow::Result<()> {
+    let now = now_iso();
+    for note_id in note_ids {
+        log::warn!("Markdown file of note {} is missing", note_id);
+        store.update(note_id, |record| {
+            if record.status == NoteStatus::Missing {
+                return false;
+            }
+            record.status = NoteStatus::Missing;
+            record.missing_since = Some(now.clone());
+            true
+        })?;
+    }
+    Ok(())
+}
+
+/// Marks notes missing after the user confirmed a mass disappearance.
+pub fn mark_notes_missing(app: &AppHandle, note_ids: &[String]) -> anyhow::Result<()> {
+    set_notes_missing(&note_store(app), note_ids)?;
+    for note_id in note_ids {
+        let _ = close_sticky_by_note_id(app, note_id);
+    }
+    Ok(())
+}
+
+/// Writes the markdown files of the given notes back from the store.
+pub fn restore_markdown_files(app: &AppHandle, note_ids: &[String]) -> anyhow::Result<()> {
+    let store = note_store(app);
+    for mut record in note_ids.iter().filter_map(|note_id| store.get(note_id)) {
+        sync_markdown_file(app, &mut record)?;
+        store.put(record)?;
+    }
+    Ok(())
+}
+
 pub fn sync_from_markdown_directory(app: &AppHandle) -> anyhow::Result<bool> {
     sync_from_markdown_directory_internal(app, true)
 }
@@ -1101,7 +1109,7 @@ pub fn restart_notes_directory_watcher(app: &AppHandle) -> anyhow::Result<()> {
         }
 
         let relevant = event.paths.iter().any(|path| {
-            is_markdown_note_path(path.as_path())
+            is_note_file_path(path.as_path())
                 || path == &watched_dir
                 || path.starts_with(&watched_dir)
         });
@@ -1129,7 +1137,7 @@ pub fn restart_notes_directory_watcher(app: &AppHandle) -> anyhow::Result<()> {
     })?;
 
     watcher.configure(Config::default())?;
-    watcher.watch(&notes_dir, RecursiveMode::NonRecursive)?;
+    watcher.watch(&notes_dir, RecursiveMode::Recursive)?;
 
     let mut guard = state
         .watcher
@@ -1163,30 +1171,6 @@ pub fn notes_directory(app: &AppHandle) -> anyhow::Result<PathBuf> {
     Ok(notes_dir)
 }
 
-fn file_date_prefix(created_at: &str) -> String {
-    DateTime::parse_from_rfc3339(created_at)
-        .map(|date| date.with_timezone(&Local).format("%Y-%m-%d").to_string())
-        .unwrap_or_else(|_| Local::now().format("%Y-%m-%d").to_string())
-}
-
-fn slugify_filename_part(value: &str) -> String {
-    let mut slug = String::new();
-    let mut previous_was_separator = false;
-
-    for ch in value.chars() {
-        if ch.is_ascii_alphanumeric() {
-            slug.push(ch.to_ascii_lowercase());
-            previous_was_separator = false;
-        } else if !previous_was_separator {
-            slug.push('_');
-            previous_was_separator = true;
-        }
-    }
-
-    slug = slug.trim_matches('_').to_string();
-    slug.chars().take(48).collect()
-}
-
 fn title_from_record(record: &NoteRecord) -> String {
     plain_text_from_quill_delta(&record.note.contents)
         .lines()
@@ -1196,21 +1180,133 @@ fn title_from_record(record: &NoteRecord) -> String {
         .to_string()
 }
 
+/// Name of the note's markdown file as it is now. Records from before file
+/// names were kept were named after their first line.
 fn markdown_filename(record: &NoteRecord) -> String {
-    let date_prefix = file_date_prefix(&record.created_at);
-    let title_part = slugify_filename_part(&title_from_record(record));
-    let id_part = sanitize_note_id(&record.id).to_ascii_lowercase();
+    record
+        .file_name
+        .clone()
+        .unwrap_or_else(|| filename_for_strategy(record, FilenameStrategy::FollowTitle))
+}
+
+/// Name the note's markdown file should have under `strategy`.
+///
+/// Files without frontmatter keep their name, which is what identifies them.
+fn filename_for_strategy(record: &NoteRecord, strategy: FilenameStrategy) -> String {
+    let format = record_format(record);
+    if let Some(file_name) = record.file_name.clone().filter(|_| !format.frontmatter) {
+        return file_name;
+    }
+
+    let id = sanitize_note_id(&record.id).to_ascii_lowercase();
+    let first_line = title_from_record(record);
+    let frontmatter_title = Frontmatter::parse(&record.extra_frontmatter).get_string("title");
+
+    filenames::markdown_filename(
+        strategy,
+        &FilenameParts {
+            created_at: &record.created_at,
+            id: &id,
+            first_line: &first_line,
+            frontmatter_title: frontmatter_title.as_deref(),
+            current: record.file_name.as_deref(),
+            extension: format.extension,
+        },
+    )
+}
 
-    if title_part.is_empty() {
-        format!("{date_prefix}_{id_part}.md")
-    } else {
-        format!("{date_prefix}_{title_part}_{id_part}.md")
+pub fn filename_strategy(app: &AppHandle) -> anyhow::Result<FilenameStrategy> {
+    let store = app.store(SETTINGS)?;
+    Ok(store
+        .get(FILENAME_STRATEGY_SETTING_KEY)
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default())
+}
+
+/// Stores the filename strategy and renames every note file to match it.
+pub fn set_filename_strategy(app: &AppHandle, strategy: FilenameStrategy) -> anyhow::Result<()> {
+    let store = app.store(SETTINGS)?;
+    store.set(
+        FILENAME_STRATEGY_SETTING_KEY,
+        serde_json::to_value(strategy)?,
+    );
+    store.save()?;
+
+    let records = load_note_records(app)?;
+    sync_all_markdown_files(app, &records)
+}
+
+pub fn backup_policy(app: &AppHandle) -> anyhow::Result<BackupPolicy> {
+    let store = app.store(SETTINGS)?;
+    Ok(store
+        .get(BACKUP_POLICY_SETTING_KEY)
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default())
+}
+
+/// Stores the backup policy and prunes the backups it no longer keeps.
+pub fn set_backup_policy(app: &AppHandle, policy: BackupPolicy) -> anyhow::Result<()> {
+    let store = app.store(SETTINGS)?;
+    store.set(BACKUP_POLICY_SETTING_KEY, serde_json::to_value(&policy)?);
+    store.save()?;
+
+    let backup_dir = store_layout(app)?.backup_dir;
+    if backup_dir.exists() {
+        backups::prune_snapshots(&backup_dir, &policy)?;
     }
+    Ok(())
+}
+
+/// Path of the note's markdown file relative to the notes folder: its file
+/// name inside its notebook's folder.
+fn markdown_relative_path(record: &NoteRecord) -> PathBuf {
+    let mut path: PathBuf = record
+        .notebook
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+        .collect();
+    path.push(markdown_filename(record));
+    path
 }
 
 fn note_markdown_path(app: &AppHandle, record: &NoteRecord) -> anyhow::Result<PathBuf> {
     let notes_dir = notes_directory(app)?;
-    Ok(notes_dir.join(markdown_filename(record)))
+    Ok(notes_dir.join(markdown_relative_path(record)))
+}
+
+/// Notebook of a note file found at `path`: its folder below `notes_dir`.
+fn notebook_for_path(notes_dir: &Path, path: &Path) -> String {
+    path.parent()
+        .and_then(|folder| folder.strip_prefix(notes_dir).ok())
+        .map(|folder| {
+            folder
+                .components()
+                .filter_map(|component| component.as_os_str().to_str())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default()
+}
+
+/// Note files in `dir` and all of its subfolders. Hidden folders,
+/// such as those of sync tools or `.obsidian`, and attachment folders are
+/// skipped.
+fn note_file_paths(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with('.') && name != ATTACHMENTS_FOLDER {
+                paths.extend(note_file_paths(&path)?);
+            }
+        } else if file_type.is_file() && is_note_file_path(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
 }
 
 fn theme_stylesheet_path(app: &AppHandle) -> anyhow::Result<PathBuf> {
@@ -1221,51 +1317,128 @@ fn theme_stylesheet_path(app: &AppHandle) -> anyhow::Result<PathBuf> {
 pub fn load_theme_stylesheet(app: &AppHandle) -> anyhow::Result<String> {
     let path = theme_stylesheet_path(app)?;
     if !path.exists() {
-        fs::write(&path, DEFAULT_THEME_STYLESHEET).context("Failed to create theme stylesheet")?;
+        write_atomically(&path, DEFAULT_THEME_STYLESHEET)
+            .context("Failed to create theme stylesheet")?;
     }
 
     fs::read_to_string(path).context("Failed to read theme stylesheet")
 }
 
-fn sync_markdown_file(app: &AppHandle, record: &NoteRecord) -> anyhow::Result<()> {
-    let path = note_markdown_path(app, record)?;
+/// Writes the note's markdown file. If the file was edited externally since
+/// it was last synced, that edit is merged in first. A file whose name no
+/// longer fits the filename strategy is renamed, not rewritten under the new
+/// name. Returns whether the merge changed the record's contents.
+fn sync_markdown_file(app: &AppHandle, record: &mut NoteRecord) -> anyhow::Result<bool> {
+    let mut path = note_markdown_path(app, record)?;
+
+    let mut merged = false;
+    if let (Some(base), Ok(raw)) = (record.synced_markdown.clone(), fs::read_to_string(&path)) {
+        let (_, body) = split_note_file(record_format(record), &raw);
+        let contents = (record_format(record).parse)(&body);
+        let theirs = record_markdown(&note_references(&contents, &record.notebook));
+        if theirs != base {
+            let ours = record_markdown(&record.note.contents);
+            merged = merge_note_markdown(app, record, &base, &ours, &theirs)?;
+        }
+    }
+
+    let file_name = filename_for_strategy(record, filename_strategy(app)?);
+    if record.file_name.as_deref() != Some(file_name.as_str()) {
+        let renamed = path.with_file_name(&file_name);
+        if path.exists() && !renamed.exists() {
+            suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
+            fs::rename(&path, &renamed).context("Failed to rename markdown note")?;
+            log::info!("Renamed markdown note {:?} to {}", path, file_name);
+        }
+        record.file_name = Some(file_name);
+        path = renamed;
+    }
+
+    if let Some(folder) = path.parent() {
+        fs::create_dir_all(folder).context("Failed to create notebook folder")?;
+    }
     suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
-    fs::write(path, note_to_markdown(record)).context("Failed to write markdown note")?;
-    Ok(())
+    store_images(&notes_directory(app)?, &record.note.contents)?;
+    write_atomically(&path, note_file_contents(record)).context("Failed to write markdown note")?;
+    record.synced_markdown = Some(record_markdown(&record.note.contents));
+    Ok(merged)
 }
 
 fn sync_all_markdown_files(app: &AppHandle, records: &[NoteRecord]) -> anyhow::Result<()> {
     let notes_dir = notes_directory(app)?;
 
     let mut expected_files = HashSet::new();
-    for record in records {
-        expected_files.insert(markdown_filename(record));
-        sync_markdown_file(app, record)?;
+    let mut previous_files = Vec::new();
+    let store = note_store(app);
+    let missing_notes = app.state::<MissingNotesState>();
+    for record in records_to_write(records, |note_id| {
+        missing_notes.is_awaiting_confirmation(note_id)
+    }) {
+        let mut synced = record.clone();
+        sync_markdown_file(app, &mut synced)?;
+        previous_files.push(notes_dir.join(markdown_relative_path(record)));
+        expected_files.insert(notes_dir.join(markdown_relative_path(&synced)));
+        if synced.synced_markdown != record.synced_markdown
+            || synced.note.contents != record.note.contents
+            || synced.file_name != record.file_name
+        {
+            store.put(synced)?;
+        }
     }
 
-    for entry in fs::read_dir(notes_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if !path.is_file() {
-            continue;
+    for path in stale_note_files(&previous_files, &expected_files) {
+        suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
+        match fs::remove_file(&path) {
+            Ok(()) => log::info!("Removed stale markdown note {:?}", path),
+            Err(error) => log::warn!("Failed removing stale markdown note {:?}: {}", path, error),
         }
+    }
 
-        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
-            continue;
-        };
+    remove_unused_attachments(app)
+}
 
-        if path.extension().and_then(|extension| extension.to_str()) != Some("md") {
-            continue;
-        }
+/// Notes whose files are written back. Missing notes have none, and notes
+/// whose files vanished wait for the user to decide, so neither their files
+/// nor their old ones are touched.
+fn records_to_write<'a>(
+    records: &'a [NoteRecord],
+    awaiting_confirmation: impl Fn(&str) -> bool + 'a,
+) -> impl Iterator<Item = &'a NoteRecord> {
+    records.iter().filter(move |record| {
+        record.status != NoteStatus::Missing && !awaiting_confirmation(&record.id)
+    })
+}
 
-        if !expected_files.contains(file_name) {
-            suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
-            let _ = fs::remove_file(path);
-        }
-    }
+/// Files the app wrote for its notes before that no note is written to
+/// anymore. Files it never wrote, or failed to import, are left alone, and
+/// so are files without frontmatter, whose name is all that identifies them.
+fn stale_note_files(previous: &[PathBuf], expected: &HashSet<PathBuf>) -> Vec<PathBuf> {
+    previous
+        .iter()
+        .filter(|path| !expected.contains(*path) && path.is_file())
+        .filter(|path| format_for_path(path).is_some_and(|format| format.frontmatter))
+        .cloned()
+        .collect()
+}
 
-    Ok(())
+/// Deletes attachment files that no note refers to anymore.
+fn remove_unused_attachments(app: &AppHandle) -> anyhow::Result<()> {
+    let records = load_note_records(app)?;
+    suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
+    attachments::remove_unused_attachments(
+        &notes_directory(app)?,
+        records.iter().map(|record| record.note.contents.as_str()),
+    )
+}
+
+/// Copies files dropped on a note into the attachments folder.
+pub fn attach_files(app: &AppHandle, paths: &[PathBuf]) -> anyhow::Result<Vec<DroppedFile>> {
+    let notes_dir = notes_directory(app)?;
+    suppress_markdown_watch_events_for(StdDuration::from_millis(1200));
+    paths
+        .iter()
+        .map(|path| attachments::attach_file(&notes_dir, path))
+        .collect()
 }
 
 pub fn get_notes_directory_path(app: &AppHandle) -> anyhow::Result<PathBuf> {
@@ -1308,71 +1481,90 @@ pub fn make_default_record(note_id: String) -> NoteRecord {
         updated_at: now,
         closed_at: None,
         archived_at: None,
+        anchor: None,
+        show_with_app: None,
+        missing_since: None,
+        synced_markdown: None,
+        conflict_copy: None,
+        extra_frontmatter: String::new(),
+        notebook: String::new(),
+        file_name: None,
         note: Note::default(),
     }
 }
 
+/// Archives the note store, the notes folder's note files and attachments
+/// and the theme into the backups folder, unless nothing changed since the
+/// last backup, and deletes the backups the backup policy no longer keeps.
 pub fn create_backup(app: &AppHandle) -> anyhow::Result<()> {
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .context("Failed to get app data directory")?;
+    let state = app.state::<BackupState>();
+    let _running = state.begin();
 
-    let store_path = app_data_dir.join(NOTES_DATA);
-
-    if !store_path.exists() {
+    let layout = store_layout(app)?;
+    let store = note_store(app);
+    if !layout.legacy_store.exists() && store.is_empty() {
         return Ok(());
     }
 
-    let backup_dir = app_data_dir.join(BACKUP_FOLDER);
-    fs::create_dir_all(&backup_dir).context("Failed to create backup directory")?;
-
-    let timestamp = Local::now().format("%Y-%m-%d_%H-%M-%S");
-    let backup_filename = format!("{}_{}", timestamp, NOTES_DATA);
-    let backup_path = backup_dir.join(backup_filename);
-
-    fs::copy(&store_path, &backup_path).context("Failed to create backup")?;
-
-    log::info!("Created backup: {:?}", backup_path);
-
-    cleanup_old_backups(&backup_dir)?;
-
-    Ok(())
-}
-
-// Remove backups older than 30 days
-fn cleanup_old_backups(backup_dir: &PathBuf) -> anyhow::Result<()> {
-    let cutoff_date = Local::now() - Duration::days(30);
-
-    let entries = fs::read_dir(backup_dir).context("Failed to read backup directory")?;
-
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
+    let notes_dir = notes_directory(app)?;
+    let in_notes_folder = |path: &Path| {
+        let relative = path.strip_prefix(&notes_dir).unwrap_or(path);
+        format!(
+            "{MARKDOWN_NOTES_FOLDER}/{}",
+            relative.to_string_lossy().replace('\\', "/")
+        )
+    };
 
-        if !path.is_file() {
-            continue;
-        }
+    let mut entries = backups::folder_entries(&layout.records_dir, NOTE_RECORDS_FOLDER)?;
+    entries.extend(backups::folder_entries(
+        &notes_dir.join(ATTACHMENTS_FOLDER),
+        &format!("{MARKDOWN_NOTES_FOLDER}/{ATTACHMENTS_FOLDER}"),
+    )?);
+    if layout.legacy_store.is_file() {
+        entries.push(BackupEntry {
+            name: NOTES_DATA.to_string(),
+            path: layout.legacy_store,
+        });
+    }
+    let theme = notes_dir.join(THEME_STYLESHEET_FILE);
+    for path in note_file_paths(&notes_dir)?
+        .into_iter()
+        .chain(theme.is_file().then_some(theme))
+    {
+        entries.push(BackupEntry {
+            name: in_notes_folder(&path),
+            path,
+        });
+    }
 
-        if let Ok(metadata) = fs::metadata(&path) {
-            if let Ok(modified) = metadata.modified() {
-                let modified_date = chrono::DateTime::<Local>::from(modified);
+    let notes: Vec<BackedUpNote> = store
+        .all()
+        .iter()
+        .map(|record| BackedUpNote {
+            id: record.id.clone(),
+            record: format!("{NOTE_RECORDS_FOLDER}/{}.json", record.id),
+            file: in_notes_folder(&markdown_relative_path(record)),
+        })
+        .collect();
 
-                if modified_date < cutoff_date {
-                    match fs::remove_file(&path) {
-                        Ok(_) => log::info!("Deleted old backup: {:?}", path),
-                        Err(e) => log::warn!("Failed to delete backup {:?}: {}", path, e),
-                    }
-                }
-            }
-        }
+    match backups::create_snapshot(
+        &layout.backup_dir,
+        &entries,
+        &notes,
+        Local::now().naive_local(),
+    )? {
+        Some(snapshot) => log::info!("Created backup: {:?}", snapshot),
+        None => log::info!("Skipped backup: nothing changed since the last one"),
     }
 
-    Ok(())
+    backups::prune_snapshots(&layout.backup_dir, &backup_policy(app)?)
 }
 
 pub fn load_stickies(app: &AppHandle) -> Result<(), anyhow::Error> {
+    migrations::migrate(&store_layout(app)?)?;
+    app.manage(open_note_store(app)?);
     create_backup(app)?;
+    replay_edit_journal(app)?;
     let _ = sync_from_markdown_directory_internal(app, false)?;
 
     let records = load_note_records(app)?;
@@ -1386,124 +1578,195 @@ pub fn load_stickies(app: &AppHandle) -> Result<(), anyhow::Error> {
         }
     }
 
-    save_note_records(app, &records)?;
     sync_all_markdown_files(app, &records)?;
 
     Ok(())
 }
 
-pub fn save_sticky(app: &AppHandle, note_id: &str, note: Note) -> Result<(), anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    let now = now_iso();
+/// Saves an edit from a sticky window. The edit is journaled first so that a
+/// crash between updating the record and the markdown file loses nothing.
+///
+/// `base_contents` are the contents the window last saved or received. If the
+/// stored note changed since, e.g. through an external edit, both changes are
+/// merged.
+pub fn save_sticky(
+    app: &AppHandle,
+    note_id: &str,
+    note: Note,
+    base_contents: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let journal = app.state::<EditJournal>();
     let normalized_id = sanitize_note_id(note_id);
-    let mut stale_markdown_file: Option<PathBuf> = None;
 
-    if let Some(record) = records.iter_mut().find(|record| record.id == normalized_id) {
-        let previous_filename = markdown_filename(record);
-        record.note = note;
-        record.status = NoteStatus::Open;
-        record.updated_at = now;
-        record.closed_at = None;
-        record.archived_at = None;
-        sync_markdown_file(app, record)?;
+    journal.record(&normalized_id, &note)?;
+    apply_note_edit(app, &normalized_id, note, base_contents)?;
+    journal.clear(&normalized_id)?;
+    backups::note_changed(app);
+    Ok(())
+}
 
-        let next_filename = markdown_filename(record);
-        if previous_filename != next_filename {
-            stale_markdown_file = Some(notes_directory(app)?.join(previous_filename));
+/// Re-applies edits that were journaled but not confirmed applied, e.g.
+/// because the app crashed or was killed mid-save.
+fn replay_edit_journal(app: &AppHandle) -> anyhow::Result<()> {
+    let journal = app.state::<EditJournal>();
+
+    for (note_id, note) in journal.pending()? {
+        log::info!("Replaying unsaved edit of note {}", note_id);
+        match apply_note_edit(app, &note_id, note, None) {
+            Ok(()) => journal.clear(&note_id)?,
+            Err(e) => log::error!("Failed to replay edit of note {}: {:#}", note_id, e),
         }
-    } else {
+    }
+
+    Ok(())
+}
+
+fn apply_note_edit(
+    app: &AppHandle,
+    note_id: &str,
+    note: Note,
+    base_contents: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let store = note_store(app);
+    let now = now_iso();
+    let normalized_id = sanitize_note_id(note_id);
+
+    let Some(previous) = store.get(&normalized_id) else {
         let mut record = make_default_record(normalized_id);
         record.note = note;
-        sync_markdown_file(app, &record)?;
-        records.push(record);
+        sync_markdown_file(app, &mut record)?;
+        return store.put(record);
+    };
+
+    // The window of a note that just went missing may still flush a last
+    // save; keep its contents without bringing the file back.
+    if previous.status == NoteStatus::Missing {
+        let mut record = previous;
+        record.note = note;
+        return store.put(record);
     }
 
-    save_note_records(app, &records)?;
-    if let Some(path) = stale_markdown_file {
-        let _ = fs::remove_file(path);
+    let stored_contents = previous.note.contents.clone();
+    let mut record = previous;
+    record.note = note;
+    record.status = NoteStatus::Open;
+    record.updated_at = now;
+    record.closed_at = None;
+    record.archived_at = None;
+
+    let mut contents_merged = false;
+    if let Some(base) = base_contents.filter(|base| *base != stored_contents) {
+        let ours = record_markdown(&record.note.contents);
+        let theirs = record_markdown(&stored_contents);
+        contents_merged =
+            merge_note_markdown(app, &mut record, &record_markdown(&base), &ours, &theirs)?;
     }
+    contents_merged |= sync_markdown_file(app, &mut record)?;
 
-    Ok(())
+    if contents_merged {
+        emit_external_note_update(app, &record);
+    }
+    store.put(record)
 }
 
-pub fn mark_note_closed(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    let now = now_iso();
+fn update_note_status(
+    app: &AppHandle,
+    note_id: &str,
+    change: impl FnOnce(&mut NoteRecord, String),
+) -> Result<Option<NoteRecord>, anyhow::Error> {
+    let mut sync_result = Ok(());
+    let record = note_store(app).update(note_id, |record| {
+        change(record, now_iso());
+        if record.status != NoteStatus::Missing {
+            record.missing_since = None;
+        }
+        sync_result = sync_markdown_file(app, record).map(|_| ());
+        sync_result.is_ok()
+    })?;
+    sync_result?;
+
+    Ok(record)
+}
 
-    if let Some(record) = records.iter_mut().find(|record| record.id == note_id) {
+pub fn mark_note_closed(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
+    update_note_status(app, note_id, |record, now| {
         record.status = NoteStatus::Closed;
         record.closed_at = Some(now.clone());
         record.updated_at = now;
-        sync_markdown_file(app, record)?;
-        save_note_records(app, &records)?;
-    }
+    })?;
 
     Ok(())
 }
 
 pub fn mark_note_archived(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    let now = now_iso();
-
-    if let Some(record) = records.iter_mut().find(|record| record.id == note_id) {
+    update_note_status(app, note_id, |record, now| {
         record.status = NoteStatus::Archived;
         record.archived_at = Some(now.clone());
         record.closed_at = Some(now.clone());
         record.updated_at = now;
-        sync_markdown_file(app, record)?;
-        save_note_records(app, &records)?;
-    }
+    })?;
 
     Ok(())
 }
 
 pub fn mark_note_open(app: &AppHandle, note_id: &str) -> Result<Option<NoteRecord>, anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    let now = now_iso();
-
-    let mut out = None;
-
-    if let Some(record) = records.iter_mut().find(|record| record.id == note_id) {
+    update_note_status(app, note_id, |record, now| {
         record.status = NoteStatus::Open;
         record.updated_at = now;
         record.closed_at = None;
         record.archived_at = None;
-        sync_markdown_file(app, record)?;
-        out = Some(record.clone());
-        save_note_records(app, &records)?;
-    }
-
-    Ok(out)
+    })
 }
 
-pub fn delete_note(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    let mut removed_record = None;
+pub fn set_note_anchor(
+    app: &AppHandle,
+    note_id: &str,
+    anchor: Option<AnchorInfo>,
+) -> Result<(), anyhow::Error> {
+    note_store(app).update(note_id, |record| {
+        let changed = record.anchor != anchor;
+        record.anchor = anchor;
+        changed
+    })?;
 
-    for record in &records {
-        if record.id == note_id {
-            removed_record = Some(record.clone());
-            break;
-        }
-    }
+    Ok(())
+}
 
-    records.retain(|record| record.id != note_id);
+pub fn set_note_app_binding(
+    app: &AppHandle,
+    note_id: &str,
+    show_with_app: Option<String>,
+) -> Result<(), anyhow::Error> {
+    note_store(app).update(note_id, |record| {
+        let changed = record.show_with_app != show_with_app;
+        record.show_with_app = show_with_app;
+        changed
+    })?;
 
-    save_note_records(app, &records)?;
+    Ok(())
+}
 
-    if let Some(record) = removed_record {
+/// Clears the conflict indicator of a note. The conflict copy stays in the
+/// notes folder.
+pub fn dismiss_note_conflict(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
+    note_store(app).update(note_id, |record| record.conflict_copy.take().is_some())?;
+    Ok(())
+}
+
+pub fn delete_note(app: &AppHandle, note_id: &str) -> Result<(), anyhow::Error> {
+    if let Some(record) = note_store(app).remove(note_id)? {
         let path = note_markdown_path(app, &record)?;
         if path.exists() {
             let _ = fs::remove_file(path);
         }
+        remove_unused_attachments(app)?;
     }
 
     Ok(())
 }
 
 pub fn list_notes(app: &AppHandle) -> Result<Vec<NoteListItem>, anyhow::Error> {
-    let mut records = load_note_records(app)?;
-    records.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    let records = load_note_records(app)?;
 
     Ok(records
         .into_iter()
@@ -1515,13 +1778,17 @@ pub fn list_notes(app: &AppHandle) -> Result<Vec<NoteListItem>, anyhow::Error> {
                 .take(160)
                 .collect::<String>();
 
+            let extra_frontmatter = record.extra_frontmatter_values();
             NoteListItem {
+                extra_frontmatter,
+                notebook: record.notebook,
                 id: record.id,
                 status: record.status,
                 created_at: record.created_at,
                 updated_at: record.updated_at,
                 closed_at: record.closed_at,
                 archived_at: record.archived_at,
+                missing_since: record.missing_since,
                 color: record.note.color,
                 preview,
             }
@@ -1559,32 +1826,249 @@ pub fn save_settings(app: &AppHandle) -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::quill_delta_to_markdown;
+    use std::{collections::HashSet, path::Path};
+
+    use super::{
+        apply_note_fields, import_note_files, is_note_file_path, make_default_record,
+        markdown_relative_path, normalized_markdown, note_file_contents, note_file_paths,
+        notebook_for_path, parse_markdown_note, record_markdown, records_to_write,
+        stale_note_files, Note, NoteRecord, NoteStatus,
+    };
+
+    #[test]
+    fn external_markdown_compares_equal_to_the_app_rendering() {
+        let delta = r#"{"ops":[{"insert":"Title"},{"insert":"\n","attributes":{"header":1}},{"insert":"item"},{"insert":"\n","attributes":{"list":"bullet"}}]}"#;
+
+        assert_eq!(
+            normalized_markdown("\n# Title\n* item\n\n"),
+            record_markdown(delta)
+        );
+    }
 
     #[test]
-    fn converts_rich_text_blocks_to_markdown() {
-        let delta = r#"{"ops":[{"insert":"Title"},{"insert":"\n","attributes":{"header":1}},{"insert":"Bold "},{"insert":"text","attributes":{"bold":true}},{"insert":" and "},{"insert":"italic","attributes":{"italic":true}},{"insert":"\n"},{"insert":"Done item"},{"insert":"\n","attributes":{"list":"checked"}},{"insert":"Todo item"},{"insert":"\n","attributes":{"list":"unchecked"}}]}"#;
+    fn conflict_copies_are_not_imported_as_notes() {
+        assert!(is_note_file_path(Path::new("2025-01-10_todo_abc.md")));
+        assert!(!is_note_file_path(Path::new(
+            "2025-01-10_todo_abc.conflict-2025-01-11_09-00-00.md"
+        )));
+    }
+
+    #[test]
+    fn note_fields_round_trip_through_frontmatter() {
+        let mut record = make_default_record("abc".to_string());
+        record.note.color = "#bddcf6".to_string();
+        record.note.x = -40;
+        record.note.y = 120;
+        record.note.width = 420;
+        record.note.height = 180;
+        record.note.always_on_top = true;
+        record.note.zoom = 1.2;
+
+        let markdown = note_file_contents(&record);
+        assert!(markdown.contains("color: \"#bddcf6\"\n"));
+
+        let imported = parse_markdown_note(Path::new("note_abc.md"), &markdown, "").unwrap();
+        let mut note = Note::default();
+        assert!(apply_note_fields(&mut note, &imported.note_fields));
+
+        assert_eq!(note.color, "#bddcf6");
+        assert_eq!(
+            (note.x, note.y, note.width, note.height),
+            (-40, 120, 420, 180)
+        );
+        assert!(note.always_on_top);
+        assert_eq!(note.zoom, 1.2);
+    }
+
+    #[test]
+    fn hand_written_frontmatter_skips_invalid_values() {
+        let raw = "---\ncolor: '#c8e6c9'\nx: left\ny: 300\n---\n\nDropped in";
+
+        let imported = parse_markdown_note(Path::new("dropped.md"), raw, "").unwrap();
+        let mut note = Note::default();
+        apply_note_fields(&mut note, &imported.note_fields);
+
+        assert_eq!(note.color, "#c8e6c9");
+        assert_eq!((note.x, note.y), (0, 300));
+    }
+
+    #[test]
+    fn unknown_frontmatter_keys_survive_rewrites() {
+        let raw = "---\nid: abc\ntags:\n  - work   # from Obsidian\n  - todo\nstatus: open\naliases: [plan]\ncolor: '#c8e6c9'\n---\n\nBody";
+
+        let imported = parse_markdown_note(Path::new("note_abc.md"), raw, "").unwrap();
+        assert_eq!(
+            imported.extra_frontmatter,
+            "tags:\n  - work   # from Obsidian\n  - todo\naliases: [plan]\n"
+        );
 
-        let markdown = quill_delta_to_markdown(delta);
+        let mut record = make_default_record(imported.id);
+        record.extra_frontmatter = imported.extra_frontmatter;
+        let markdown = note_file_contents(&record);
+        assert!(markdown
+            .contains("tags:\n  - work   # from Obsidian\n  - todo\naliases: [plan]\n---\n"));
 
+        let reparsed = parse_markdown_note(Path::new("note_abc.md"), &markdown, "").unwrap();
+        assert_eq!(reparsed.extra_frontmatter, record.extra_frontmatter);
         assert_eq!(
-            markdown,
-            "# Title\nBold **text** and *italic*\n- [x] Done item\n- [ ] Todo item"
+            record.extra_frontmatter_values()["tags"],
+            serde_json::json!(["work", "todo"])
         );
     }
 
     #[test]
-    fn converts_code_blocks_to_fenced_markdown() {
-        let delta = r#"{"ops":[{"insert":"let x = 1;"},{"insert":"\n","attributes":{"code-block":true}},{"insert":"let y = 2;"},{"insert":"\n","attributes":{"code-block":true}}]}"#;
+    fn notes_in_subfolders_belong_to_notebooks() {
+        let notes_dir = std::env::temp_dir().join(format!("notebooks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&notes_dir);
+        std::fs::create_dir_all(notes_dir.join("work/client a")).unwrap();
+        std::fs::create_dir_all(notes_dir.join(".obsidian")).unwrap();
+        std::fs::create_dir_all(notes_dir.join("attachments")).unwrap();
+        std::fs::write(notes_dir.join("top_abc.md"), "top").unwrap();
+        std::fs::write(notes_dir.join("attachments/readme.txt"), "").unwrap();
+        std::fs::write(notes_dir.join("work/client a/plan_def.md"), "plan").unwrap();
+        std::fs::write(notes_dir.join(".obsidian/workspace.md"), "").unwrap();
+
+        let mut notebooks: Vec<String> = note_file_paths(&notes_dir)
+            .unwrap()
+            .iter()
+            .map(|path| notebook_for_path(&notes_dir, path))
+            .collect();
+        notebooks.sort();
+        assert_eq!(notebooks, vec!["".to_string(), "work/client a".to_string()]);
+
+        let mut record = make_default_record("def".to_string());
+        record.notebook = "work/client a".to_string();
+        let path = markdown_relative_path(&record);
+        assert_eq!(path.parent(), Some(Path::new("work/client a")));
+        assert_eq!(
+            notebook_for_path(&notes_dir, &notes_dir.join(path)),
+            record.notebook
+        );
+
+        record.notebook = "../outside".to_string();
+        assert_eq!(
+            markdown_relative_path(&record).parent(),
+            Some(Path::new("outside"))
+        );
+
+        let _ = std::fs::remove_dir_all(notes_dir);
+    }
+
+    #[test]
+    fn plain_text_notes_are_written_back_without_frontmatter() {
+        let raw = "Scratch\r\n---\r\n*not bold*";
+        let imported = parse_markdown_note(Path::new("ideas_scratch.txt"), raw, "").unwrap();
+        assert_eq!(imported.id, "ideas_scratch_txt");
+
+        let mut record = make_default_record(imported.id);
+        record.file_name = Some(imported.file_name);
+        record.note.contents = imported.contents;
+
+        assert_eq!(note_file_contents(&record), "Scratch\n---\n*not bold*");
+    }
+
+    #[test]
+    fn notes_with_the_same_file_name_in_two_notebooks_are_both_kept() {
+        let notes_dir = std::env::temp_dir().join(format!("same_names_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&notes_dir);
+        std::fs::create_dir_all(notes_dir.join("projA")).unwrap();
+        std::fs::create_dir_all(notes_dir.join("projB")).unwrap();
+        std::fs::write(notes_dir.join("projA/todo.md"), "ship A").unwrap();
+        std::fs::write(notes_dir.join("projB/todo.md"), "ship B").unwrap();
+
+        let imported = import_note_files(&notes_dir, &[]).unwrap();
+        let mut ids: Vec<&str> = imported.keys().map(String::as_str).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["todo", "todo_1"]);
+
+        let records: Vec<NoteRecord> = imported
+            .values()
+            .map(|note| {
+                let mut record = make_default_record(note.id.clone());
+                record.notebook = note.notebook.clone();
+                record.file_name = Some(note.file_name.clone());
+                record
+            })
+            .collect();
+        let reimported = import_note_files(&notes_dir, &records).unwrap();
+        for record in &records {
+            assert_eq!(reimported[&record.id].notebook, record.notebook);
+        }
+
+        let previous: Vec<_> = records
+            .iter()
+            .map(|record| notes_dir.join(markdown_relative_path(record)))
+            .collect();
+        let expected: HashSet<_> = previous.iter().cloned().collect();
+        assert!(stale_note_files(&previous, &expected).is_empty());
+
+        let _ = std::fs::remove_dir_all(notes_dir);
+    }
+
+    #[test]
+    fn only_files_the_app_wrote_are_swept() {
+        let notes_dir = std::env::temp_dir().join(format!("sweep_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&notes_dir);
+        std::fs::create_dir_all(&notes_dir).unwrap();
+        let renamed_from = notes_dir.join("old_abc.md");
+        let foreign = notes_dir.join("readme.md");
+        std::fs::write(&renamed_from, "old").unwrap();
+        std::fs::write(&foreign, "not a note of ours").unwrap();
+
+        let previous = vec![renamed_from];
+        let expected = HashSet::from([notes_dir.join("new_abc.md")]);
+        assert_eq!(stale_note_files(&previous, &expected), previous);
+        assert!(foreign.exists());
+
+        let _ = std::fs::remove_dir_all(notes_dir);
+    }
+
+    #[test]
+    fn plain_text_notes_are_named_after_their_whole_path() {
+        let notes_dir = std::env::temp_dir().join(format!("plain_ids_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&notes_dir);
+        std::fs::create_dir_all(&notes_dir).unwrap();
+        for name in [
+            "meeting_notes.txt",
+            "grocery_notes.txt",
+            "todo.txt",
+            "todo.md",
+        ] {
+            std::fs::write(notes_dir.join(name), name).unwrap();
+        }
+
+        let imported = import_note_files(&notes_dir, &[]).unwrap();
+        let mut ids: Vec<&str> = imported.keys().map(String::as_str).collect();
+        ids.sort();
+        assert_eq!(
+            ids,
+            vec!["grocery_notes_txt", "meeting_notes_txt", "todo", "todo_txt"]
+        );
 
-        let markdown = quill_delta_to_markdown(delta);
+        let previous: Vec<_> = imported
+            .values()
+            .map(|note| notes_dir.join(&note.file_name))
+            .collect();
+        assert!(stale_note_files(&previous, &HashSet::new())
+            .iter()
+            .all(|path| path.ends_with("todo.md")));
 
-        assert_eq!(markdown, "```\nlet x = 1;\nlet y = 2;\n```");
+        let _ = std::fs::remove_dir_all(notes_dir);
     }
 
     #[test]
-    fn returns_original_contents_when_not_a_delta() {
-        let raw = "# already markdown";
-        assert_eq!(quill_delta_to_markdown(raw), raw);
+    fn startup_rewrite_skips_notes_awaiting_confirmation() {
+        let mut records: Vec<NoteRecord> = ["a", "b", "c", "d", "e"]
+            .into_iter()
+            .map(|id| make_default_record(id.to_string()))
+            .collect();
+        records[4].status = NoteStatus::Missing;
+        let awaiting = HashSet::from(["a", "b", "c"]);
+
+        let written: Vec<&str> = records_to_write(&records, |id| awaiting.contains(id))
+            .map(|record| record.id.as_str())
+            .collect();
+
+        assert_eq!(written, vec!["d"]);
     }
 }
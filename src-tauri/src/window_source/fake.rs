@@ -0,0 +1,97 @@
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use super::{ExternalWindow, WindowSource};
+
+/// In-memory window source for tests. Windows are reported in the order they
+/// were given, so the first entry plays the frontmost window. Hidden windows
+/// still exist but are left out of `external_windows`, like minimized ones.
+#[derive(Default)]
+pub struct FakeWindowSource {
+    windows: Mutex<Vec<ExternalWindow>>,
+    hidden: Mutex<Vec<u32>>,
+    watchers: Mutex<Vec<Sender<()>>>,
+}
+
+impl FakeWindowSource {
+    pub fn new(windows: Vec<ExternalWindow>) -> Self {
+        Self {
+            windows: Mutex::new(windows),
+            hidden: Mutex::default(),
+            watchers: Mutex::default(),
+        }
+    }
+
+    pub fn set_windows(&self, windows: Vec<ExternalWindow>) {
+        *self.windows.lock().unwrap() = windows;
+        self.notify();
+    }
+
+    pub fn set_hidden(&self, ids: Vec<u32>) {
+        *self.hidden.lock().unwrap() = ids;
+        self.notify();
+    }
+
+    /// Drops every watcher, as a source whose connection failed does.
+    pub fn stop_watching(&self) {
+        self.watchers.lock().unwrap().clear();
+    }
+
+    fn notify(&self) {
+        self.watchers
+            .lock()
+            .unwrap()
+            .retain(|watcher| watcher.send(()).is_ok());
+    }
+}
+
+impl WindowSource for FakeWindowSource {
+    fn external_windows(&self, own_pid: u32) -> Vec<ExternalWindow> {
+        let hidden = self.hidden.lock().unwrap();
+        self.windows
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|window| window.owner_pid != own_pid && window.is_anchorable())
+            .filter(|window| !hidden.contains(&window.id))
+            .cloned()
+            .collect()
+    }
+
+    fn window_exists(&self, id: u32) -> bool {
+        self.windows
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|window| window.id == id)
+    }
+
+    fn watch_changes(&self, changes: Sender<()>) -> bool {
+        self.watchers.lock().unwrap().push(changes);
+        true
+    }
+
+    fn is_watching_changes(&self) -> bool {
+        !self.watchers.lock().unwrap().is_empty()
+    }
+}
+
+pub fn window(
+    id: u32,
+    owner_name: &str,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+) -> ExternalWindow {
+    ExternalWindow {
+        id,
+        x,
+        y,
+        width,
+        height,
+        owner_name: owner_name.to_string(),
+        owner_pid: 1000 + id,
+        title: format!("{owner_name} window {id}"),
+    }
+}
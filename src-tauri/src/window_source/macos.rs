@@ -0,0 +1,163 @@
+use std::sync::mpsc::Sender;
+
+use core_foundation::base::{CFType, TCFType};
+use core_foundation::dictionary::CFDictionaryRef;
+use core_foundation::number::CFNumber;
+use core_foundation::string::CFString;
+use core_graphics::display::{
+    kCGNullWindowID, kCGWindowListExcludeDesktopElements, kCGWindowListOptionIncludingWindow,
+    kCGWindowListOptionOnScreenOnly, CGWindowListCopyWindowInfo,
+};
+
+use super::{ExternalWindow, WindowSource};
+
+pub struct CoreGraphicsWindowSource;
+
+impl WindowSource for CoreGraphicsWindowSource {
+    fn external_windows(&self, own_pid: u32) -> Vec<ExternalWindow> {
+        let mut windows = Vec::new();
+
+        unsafe {
+            let window_list = CGWindowListCopyWindowInfo(
+                kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+                kCGNullWindowID,
+            );
+
+            if window_list.is_null() {
+                return windows;
+            }
+
+            let cf_array = core_foundation::array::CFArray::<CFType>::wrap_under_get_rule(
+                window_list as core_foundation::array::CFArrayRef,
+            );
+            let count = cf_array.len();
+
+            for i in 0..count {
+                let item = match cf_array.get(i) {
+                    Some(item) => item,
+                    None => continue,
+                };
+                let dict_ref = item.as_CFTypeRef() as CFDictionaryRef;
+
+                let layer = get_dict_number(dict_ref, "kCGWindowLayer");
+                if layer != Some(0) {
+                    continue;
+                }
+
+                let pid = match get_dict_number(dict_ref, "kCGWindowOwnerPID") {
+                    Some(pid) if pid != own_pid as i64 => pid as u32,
+                    _ => continue,
+                };
+
+                let window_id = match get_dict_number(dict_ref, "kCGWindowNumber") {
+                    Some(id) => id as u32,
+                    None => continue,
+                };
+
+                let owner_name =
+                    get_dict_string(dict_ref, "kCGWindowOwnerName").unwrap_or_default();
+                // Only populated when the app has been granted screen recording access.
+                let title = get_dict_string(dict_ref, "kCGWindowName").unwrap_or_default();
+
+                let bounds = match get_dict_bounds(dict_ref) {
+                    Some(b) => b,
+                    None => continue,
+                };
+
+                let window = ExternalWindow {
+                    id: window_id,
+                    x: bounds.0,
+                    y: bounds.1,
+                    width: bounds.2,
+                    height: bounds.3,
+                    owner_name,
+                    owner_pid: pid,
+                    title,
+                };
+
+                if window.is_anchorable() {
+                    windows.push(window);
+                }
+            }
+        }
+
+        windows
+    }
+
+    fn window_exists(&self, id: u32) -> bool {
+        // Unlike the on-screen list, this also finds minimized windows and
+        // windows on other Spaces.
+        unsafe {
+            let window_list = CGWindowListCopyWindowInfo(kCGWindowListOptionIncludingWindow, id);
+
+            if window_list.is_null() {
+                return false;
+            }
+
+            let cf_array = core_foundation::array::CFArray::<CFType>::wrap_under_get_rule(
+                window_list as core_foundation::array::CFArrayRef,
+            );
+            !cf_array.is_empty()
+        }
+    }
+
+    fn watch_changes(&self, _changes: Sender<()>) -> bool {
+        // CoreGraphics has no notifications for windows moving or resizing.
+        // The accessibility API does, but it needs a permission this app does
+        // not ask for, and app activation notifications alone miss windows
+        // being dragged. The window list is polled instead.
+        false
+    }
+}
+
+unsafe fn get_dict_number(dict: CFDictionaryRef, key: &str) -> Option<i64> {
+    let cf_key = CFString::new(key);
+    let mut value: *const core::ffi::c_void = std::ptr::null();
+    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
+        dict,
+        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
+        &mut value,
+    ) != 0
+    {
+        let cf_num = CFNumber::wrap_under_get_rule(value as core_foundation::number::CFNumberRef);
+        cf_num.to_i64()
+    } else {
+        None
+    }
+}
+
+unsafe fn get_dict_string(dict: CFDictionaryRef, key: &str) -> Option<String> {
+    let cf_key = CFString::new(key);
+    let mut value: *const core::ffi::c_void = std::ptr::null();
+    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
+        dict,
+        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
+        &mut value,
+    ) != 0
+    {
+        let cf_str = CFString::wrap_under_get_rule(value as core_foundation::string::CFStringRef);
+        Some(cf_str.to_string())
+    } else {
+        None
+    }
+}
+
+unsafe fn get_dict_bounds(dict: CFDictionaryRef) -> Option<(f64, f64, f64, f64)> {
+    let cf_key = CFString::new("kCGWindowBounds");
+    let mut value: *const core::ffi::c_void = std::ptr::null();
+    if core_foundation::dictionary::CFDictionaryGetValueIfPresent(
+        dict,
+        cf_key.as_concrete_TypeRef() as *const core::ffi::c_void,
+        &mut value,
+    ) != 0
+    {
+        let bounds_dict = value as CFDictionaryRef;
+        let x = get_dict_number(bounds_dict, "X").unwrap_or(0) as f64;
+        let y = get_dict_number(bounds_dict, "Y").unwrap_or(0) as f64;
+        let w = get_dict_number(bounds_dict, "Width").unwrap_or(0) as f64;
+        let h = get_dict_number(bounds_dict, "Height").unwrap_or(0) as f64;
+        Some((x, y, w, h))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::mpsc::Sender;
+
+#[cfg(test)]
+pub mod fake;
+#[cfg(target_os = "macos")]
+mod macos;
+#[cfg(target_os = "linux")]
+mod x11;
+
+// Windows smaller than this (tooltips, status items, helper panels) are never anchor targets.
+const MIN_WINDOW_SIZE: f64 = 50.0;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ExternalWindow {
+    pub id: u32,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub owner_name: String,
+    pub owner_pid: u32,
+    pub title: String,
+}
+
+impl ExternalWindow {
+    fn is_anchorable(&self) -> bool {
+        self.width >= MIN_WINDOW_SIZE && self.height >= MIN_WINDOW_SIZE
+    }
+}
+
+/// Lists the normal, on-screen windows of other applications.
+///
+/// Implementations return windows front-to-back (frontmost first), in logical
+/// screen coordinates, excluding every window owned by `own_pid`.
+pub trait WindowSource: Send + Sync {
+    fn external_windows(&self, own_pid: u32) -> Vec<ExternalWindow>;
+
+    /// Whether the window still exists, even while minimized, hidden or on
+    /// another workspace. Tells a temporarily hidden anchor target from a
+    /// closed one.
+    fn window_exists(&self, id: u32) -> bool;
+
+    /// Sends on `changes` whenever windows may have been created, destroyed or
+    /// moved, alongside any receiver watching already. Returns `false` if the
+    /// source has no change notifications and has to be polled instead.
+    fn watch_changes(&self, _changes: Sender<()>) -> bool {
+        false
+    }
+
+    /// Whether notifications started by `watch_changes` still arrive. A
+    /// watch can stop, e.g. when the connection to the window server drops.
+    fn is_watching_changes(&self) -> bool {
+        false
+    }
+}
+
+/// Used on platforms without a window enumeration backend; anchoring always
+/// reports that no windows were found.
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+struct UnsupportedWindowSource;
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+impl WindowSource for UnsupportedWindowSource {
+    fn external_windows(&self, _own_pid: u32) -> Vec<ExternalWindow> {
+        Vec::new()
+    }
+
+    fn window_exists(&self, _id: u32) -> bool {
+        false
+    }
+}
+
+pub fn platform_window_source() -> Box<dyn WindowSource> {
+    #[cfg(target_os = "macos")]
+    {
+        Box::new(macos::CoreGraphicsWindowSource)
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Box::new(x11::X11WindowSource::default())
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        Box::new(UnsupportedWindowSource)
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use tauri_plugin_log::log;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum, ChangeWindowAttributesAux, ConnectionExt, EventMask, MapState, Window,
+};
+use x11rb::rust_connection::RustConnection;
+
+use super::{ExternalWindow, WindowSource};
+
+type X11Result<T> = Result<T, Box<dyn std::error::Error>>;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_CLIENT_LIST,
+        _NET_CLIENT_LIST_STACKING,
+        _NET_WM_PID,
+        _NET_WM_NAME,
+        UTF8_STRING,
+    }
+}
+
+struct X11Connection {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl X11Connection {
+    fn open() -> X11Result<Self> {
+        let (conn, screen_num) = RustConnection::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        Ok(Self { conn, root, atoms })
+    }
+
+    fn cardinal_property(&self, window: Window, property: u32) -> X11Result<Option<u32>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, AtomEnum::CARDINAL, 0, 1)?
+            .reply()?;
+        Ok(reply.value32().and_then(|mut values| values.next()))
+    }
+
+    fn string_property(&self, window: Window, property: u32, kind: u32) -> X11Result<Vec<u8>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, kind, 0, u32::MAX)?
+            .reply()?;
+        Ok(reply.value)
+    }
+
+    /// Client windows managed by the window manager, frontmost first.
+    fn client_windows(&self) -> X11Result<Vec<Window>> {
+        for (property, bottom_to_top) in [
+            (self.atoms._NET_CLIENT_LIST_STACKING, true),
+            (self.atoms._NET_CLIENT_LIST, false),
+        ] {
+            let reply = self
+                .conn
+                .get_property(false, self.root, property, AtomEnum::WINDOW, 0, u32::MAX)?
+                .reply()?;
+            let Some(values) = reply.value32() else {
+                continue;
+            };
+
+            let mut windows: Vec<Window> = values.collect();
+            if bottom_to_top {
+                windows.reverse();
+            }
+            return Ok(windows);
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn title(&self, window: Window) -> X11Result<String> {
+        let utf8 = self.string_property(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)?;
+        if !utf8.is_empty() {
+            return Ok(String::from_utf8_lossy(&utf8).into_owned());
+        }
+
+        let legacy =
+            self.string_property(window, AtomEnum::WM_NAME.into(), AtomEnum::STRING.into())?;
+        Ok(String::from_utf8_lossy(&legacy).into_owned())
+    }
+
+    fn owner_name(&self, window: Window, pid: Option<u32>) -> X11Result<String> {
+        // WM_CLASS holds "instance\0class\0"; the class is the human-facing application name.
+        let class =
+            self.string_property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?;
+        let name = class
+            .split(|byte| *byte == 0)
+            .rfind(|part| !part.is_empty())
+            .map(|part| String::from_utf8_lossy(part).into_owned());
+
+        if let Some(name) = name {
+            return Ok(name);
+        }
+
+        Ok(pid
+            .and_then(|pid| std::fs::read_to_string(format!("/proc/{pid}/comm")).ok())
+            .map(|comm| comm.trim().to_string())
+            .unwrap_or_default())
+    }
+
+    fn describe(&self, window: Window, own_pid: u32) -> X11Result<Option<ExternalWindow>> {
+        let attributes = self.conn.get_window_attributes(window)?.reply()?;
+        if attributes.map_state != MapState::VIEWABLE {
+            return Ok(None);
+        }
+
+        let pid = self.cardinal_property(window, self.atoms._NET_WM_PID)?;
+        if pid == Some(own_pid) {
+            return Ok(None);
+        }
+
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let origin = self
+            .conn
+            .translate_coordinates(window, self.root, 0, 0)?
+            .reply()?;
+
+        Ok(Some(ExternalWindow {
+            id: window,
+            x: origin.dst_x as f64,
+            y: origin.dst_y as f64,
+            width: geometry.width as f64,
+            height: geometry.height as f64,
+            owner_name: self.owner_name(window, pid)?,
+            owner_pid: pid.unwrap_or_default(),
+            title: self.title(window)?,
+        }))
+    }
+
+    fn external_windows(&self, own_pid: u32) -> X11Result<Vec<ExternalWindow>> {
+        let mut windows = Vec::new();
+        for window in self.client_windows()? {
+            // Windows can be destroyed between listing and querying them; skip those.
+            match self.describe(window, own_pid) {
+                Ok(Some(external)) if external.is_anchorable() => windows.push(external),
+                Ok(_) => {}
+                Err(error) => log::debug!("Skipping X11 window {}: {}", window, error),
+            }
+        }
+        Ok(windows)
+    }
+}
+
+/// Receivers of window change notifications, fed by one watcher thread.
+#[derive(Default)]
+struct ChangeWatcher {
+    senders: Vec<Sender<()>>,
+    running: bool,
+}
+
+/// Reads the EWMH client list from the X server named by `$DISPLAY`.
+///
+/// The connection is opened lazily and re-opened after a failed query, so the
+/// source keeps working across X server restarts.
+#[derive(Default)]
+pub struct X11WindowSource {
+    connection: Mutex<Option<X11Connection>>,
+    watcher: Arc<Mutex<ChangeWatcher>>,
+}
+
+impl X11WindowSource {
+    fn with_connection<T>(&self, query: impl FnOnce(&X11Connection) -> X11Result<T>) -> Option<T> {
+        let Ok(mut guard) = self.connection.lock() else {
+            return None;
+        };
+
+        if guard.is_none() {
+            match X11Connection::open() {
+                Ok(connection) => *guard = Some(connection),
+                Err(error) => {
+                    log::warn!("Could not connect to X server: {}", error);
+                    return None;
+                }
+            }
+        }
+
+        let connection = guard.as_ref()?;
+        match query(connection) {
+            Ok(result) => Some(result),
+            Err(error) => {
+                log::warn!("X11 query failed: {}", error);
+                *guard = None;
+                None
+            }
+        }
+    }
+}
+
+impl WindowSource for X11WindowSource {
+    fn external_windows(&self, own_pid: u32) -> Vec<ExternalWindow> {
+        self.with_connection(|connection| connection.external_windows(own_pid))
+            .unwrap_or_default()
+    }
+
+    fn window_exists(&self, id: u32) -> bool {
+        // Minimized windows and windows on other desktops stay in the client
+        // list; only destroyed ones leave it. If the server cannot be reached,
+        // assume the window is still there rather than dropping anchors.
+        self.with_connection(|connection| Ok(connection.client_windows()?.contains(&id)))
+            .unwrap_or(true)
+    }
+
+    fn watch_changes(&self, changes: Sender<()>) -> bool {
+        let Ok(mut watcher) = self.watcher.lock() else {
+            return false;
+        };
+        if watcher.running {
+            watcher.senders.push(changes);
+            return true;
+        }
+
+        // Events are read on a dedicated connection so waiting for them never
+        // blocks the queries above.
+        let connection = match X11Connection::open().and_then(|connection| {
+            let events = ChangeWindowAttributesAux::new()
+                .event_mask(EventMask::SUBSTRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE);
+            connection
+                .conn
+                .change_window_attributes(connection.root, &events)?
+                .check()?;
+            Ok(connection)
+        }) {
+            Ok(connection) => connection,
+            Err(error) => {
+                log::warn!("Could not watch X11 window changes: {}", error);
+                return false;
+            }
+        };
+        watcher.senders.push(changes);
+        watcher.running = true;
+
+        // One thread serves every receiver and stops once none is left, so
+        // watching again later starts a new one.
+        let shared = Arc::clone(&self.watcher);
+        std::thread::spawn(move || loop {
+            let event = connection.conn.wait_for_event();
+            let Ok(mut watcher) = shared.lock() else {
+                break;
+            };
+            if let Err(error) = event {
+                log::warn!("Stopped watching X11 window changes: {}", error);
+                *watcher = ChangeWatcher::default();
+                break;
+            }
+            watcher.senders.retain(|sender| sender.send(()).is_ok());
+            if watcher.senders.is_empty() {
+                watcher.running = false;
+                break;
+            }
+        });
+
+        true
+    }
+
+    fn is_watching_changes(&self) -> bool {
+        self.watcher.lock().is_ok_and(|watcher| watcher.running)
+    }
+}
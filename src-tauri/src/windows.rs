@@ -5,11 +5,15 @@ use tauri::{
 };
 use tauri_plugin_log::log;
 
+use crate::anchor::restore_anchor;
+use crate::app_binding::restore_app_binding;
 use crate::save_load::{
     generate_note_id, make_default_record, mark_note_closed, save_sticky, Note, NoteRecord,
 };
 
 const GAP: i32 = 20;
+// How far two notes may be from exactly GAP apart and still count as snapped.
+const SNAP_TOLERANCE: i32 = 2;
 const VISIBLE_PADDING: f64 = 32.0;
 const STICKY_WINDOW_PREFIX: &str = "sticky_";
 pub const MANAGER_WINDOW_LABEL: &str = "manager";
@@ -25,7 +29,7 @@ pub fn note_id_from_label(label: &str) -> Option<String> {
         .filter(|id| !id.is_empty())
 }
 
-fn sticky_label(note_id: &str) -> String {
+pub fn sticky_label(note_id: &str) -> String {
     format!("{STICKY_WINDOW_PREFIX}{note_id}")
 }
 
@@ -59,6 +63,62 @@ fn window_overlap(start_1: i32, len_1: i32, start_2: i32, len_2: i32) -> bool {
     overlap_end - overlap_start > GAP
 }
 
+/// Whether two notes sit GAP apart along one axis while overlapping on the
+/// other, the way `snap_window` leaves them.
+fn snapped_together(a: (i32, i32, i32, i32), b: (i32, i32, i32, i32)) -> bool {
+    let gap_between = |start_1: i32, len_1: i32, start_2: i32, len_2: i32| {
+        ((start_1 + len_1 + GAP) - start_2).abs() <= SNAP_TOLERANCE
+            || ((start_2 + len_2 + GAP) - start_1).abs() <= SNAP_TOLERANCE
+    };
+
+    (gap_between(a.0, a.2, b.0, b.2) && window_overlap(a.1, a.3, b.1, b.3))
+        || (gap_between(a.1, a.3, b.1, b.3) && window_overlap(a.0, a.2, b.0, b.2))
+}
+
+/// Indices of the notes snapped to `rects[start]`, directly or through other
+/// snapped notes, excluding `start` itself.
+fn snapped_cluster_indices(rects: &[(i32, i32, i32, i32)], start: usize) -> Vec<usize> {
+    let mut cluster = vec![start];
+    let mut next = 0;
+
+    while next < cluster.len() {
+        let current = rects[cluster[next]];
+        for (index, rect) in rects.iter().enumerate() {
+            if !cluster.contains(&index) && snapped_together(current, *rect) {
+                cluster.push(index);
+            }
+        }
+        next += 1;
+    }
+
+    cluster.remove(0);
+    cluster
+}
+
+/// Visible notes snapped together with `window`, not including `window`.
+pub fn snapped_notes(app: &AppHandle, window: &WebviewWindow) -> Vec<WebviewWindow> {
+    let mut windows = vec![window.clone()];
+    windows.extend(
+        sorted_windows(app)
+            .into_iter()
+            .filter(|other| other.label() != window.label()),
+    );
+
+    let rects: Vec<(i32, i32, i32, i32)> = windows
+        .iter()
+        .map(|w| {
+            get_position_and_size(w)
+                .map(|(p, s)| (p.x, p.y, s.width as i32, s.height as i32))
+                .unwrap_or((i32::MIN / 2, i32::MIN / 2, 0, 0))
+        })
+        .collect();
+
+    snapped_cluster_indices(&rects, 0)
+        .into_iter()
+        .map(|index| windows[index].clone())
+        .collect()
+}
+
 pub fn snap_window(
     app: &AppHandle,
     direction: Direction,
@@ -307,11 +367,7 @@ fn ensure_note_visible(app: &AppHandle, note: &Note) -> (f64, f64) {
         .ok()
         .flatten()
         .map(|monitor| logical_monitor_bounds(&monitor))
-        .or_else(|| {
-            monitors
-                .first()
-                .map(|monitor| logical_monitor_bounds(monitor))
-        });
+        .or_else(|| monitors.first().map(logical_monitor_bounds));
 
     if let Some(bounds) = target_bounds {
         return clamp_note_within_bounds(note_x, note_y, note_width, note_height, bounds);
@@ -331,7 +387,7 @@ pub fn create_sticky(
     } else {
         let note_id = generate_note_id();
         let record = make_default_record(note_id);
-        save_sticky(app, &record.id, record.note.clone())?;
+        save_sticky(app, &record.id, record.note.clone(), None)?;
         record
     };
 
@@ -342,7 +398,7 @@ pub fn create_sticky(
     if record.note.x != corrected_x || record.note.y != corrected_y {
         record.note.x = corrected_x;
         record.note.y = corrected_y;
-        save_sticky(app, &record.id, record.note.clone())?;
+        save_sticky(app, &record.id, record.note.clone(), None)?;
     }
 
     let label = sticky_label(&record.id);
@@ -376,6 +432,12 @@ pub fn create_sticky(
     builder = builder.position(initial_x, initial_y);
 
     let window = builder.build().context("Could not create sticky window")?;
+    if let Some(anchor_info) = record.anchor.clone() {
+        restore_anchor(app, window.label(), anchor_info);
+    }
+    if let Some(app_name) = record.show_with_app.clone() {
+        restore_app_binding(app, window.label(), app_name);
+    }
     let _ = window.unminimize();
     let _ = window.show();
     let _ = window.set_focus();
@@ -456,6 +518,8 @@ pub fn sorted_windows(app: &AppHandle) -> Vec<WebviewWindow> {
         .webview_windows()
         .into_iter()
         .filter(|(label, _)| is_sticky_window_label(label))
+        // Notes hidden along with their anchor target stay out of focus cycling.
+        .filter(|(_, w)| w.is_visible().unwrap_or(true))
         .filter_map(|(_label, w)| get_position_and_size(&w).ok().map(|(p, _)| (p, w)))
         .collect();
 
@@ -488,7 +552,7 @@ pub fn fit_text(app: &AppHandle) -> Result<(), anyhow::Error> {
         .for_each(|(label, window)| {
             if is_sticky_window_label(&label) && window.is_focused().unwrap_or(false) {
                 log::info!("emitting fit_text to window {}", label);
-                let _ = window.emit_to(EventTarget::webview_window(label), "fit_text", {});
+                let _ = window.emit_to(EventTarget::webview_window(label), "fit_text", ());
             }
         });
 
@@ -512,12 +576,11 @@ pub fn reset_note_positions(app: &AppHandle) -> anyhow::Result<()> {
     app.webview_windows()
         .into_iter()
         .filter(|(label, _)| is_sticky_window_label(label))
-        .map(|(_, window)| {
+        .try_for_each(|(_, window)| {
             window
                 .set_position(PhysicalPosition { x: 0, y: 0 })
                 .context("could not set note position")
         })
-        .collect::<Result<(), anyhow::Error>>()
 }
 
 pub fn emit_to_focused(app: &AppHandle, event: &str, payload: &str) -> anyhow::Result<()> {
@@ -547,3 +610,24 @@ pub enum Direction {
     Left,
     Right,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{snapped_cluster_indices, GAP};
+
+    #[test]
+    fn finds_notes_snapped_together_transitively() {
+        let rects = [
+            (0, 0, 300, 250),
+            (300 + GAP, 0, 300, 250),
+            (300 + GAP, 250 + GAP + 1, 300, 200),
+            (2000, 0, 300, 250),
+            (0, 700, 300, 250),
+        ];
+
+        let mut cluster = snapped_cluster_indices(&rects, 0);
+        cluster.sort();
+
+        assert_eq!(cluster, vec![1, 2]);
+    }
+}